@@ -0,0 +1,1088 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::Result;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use serde::Deserialize;
+
+use crate::scheduler::{capacity, wasm};
+
+// Scheduler configuration file, loosely modelled after the upstream
+// KubeSchedulerConfiguration: each profile configures the scheduler instance serving the
+// pods with the matching schedulerName
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SchedulerConfig {
+    #[serde(default)]
+    pub(crate) profiles: Vec<Profile>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Profile {
+    pub(crate) scheduler_name: String,
+    // Filter plugins run after the built-in filters, a node must pass all of them
+    #[serde(default)]
+    pub(crate) filter_plugins: Vec<FilterPlugin>,
+    // Score plugins whose weighted results are added to the score of the algorithm
+    #[serde(default)]
+    pub(crate) score_plugins: Vec<ScorePluginConfig>,
+    // Settings of the BinPacking algorithm
+    #[serde(default)]
+    pub(crate) bin_packing: BinPackingArgs,
+    // Settings of the Optimal algorithm
+    #[serde(default)]
+    pub(crate) optimal: OptimalArgs,
+    // Settings of the Annealing algorithm
+    #[serde(default)]
+    pub(crate) annealing: AnnealingArgs,
+    // Settings of gang scheduling of pod groups
+    #[serde(default)]
+    pub(crate) pod_group: PodGroupArgs,
+    // Settings of the scheduling queue
+    #[serde(default)]
+    pub(crate) queue: QueueArgs,
+    // Settings of the cache of assumed pods
+    #[serde(default)]
+    pub(crate) cache: CacheArgs,
+    // Settings of binding the placed pods to their nodes
+    #[serde(default)]
+    pub(crate) binding: BindingArgs,
+    // HTTP services implementing the kube-scheduler extender protocol, called by the
+    // BinPacking and LeastAllocated algorithms with the nodes left by the built-in filters
+    #[serde(default)]
+    pub(crate) extenders: Vec<ExtenderConfig>,
+    // Settings of collecting the actual usage of nodes for the LoadAware plugins
+    #[serde(default)]
+    pub(crate) load: LoadArgs,
+    // Overcommit ratios of node pools, the first entry selecting a node applies to it
+    #[serde(default)]
+    pub(crate) overcommit: Vec<OvercommitConfig>,
+    // Capacity kept free on node pools, the first entry selecting a node applies to it
+    #[serde(default)]
+    pub(crate) headroom: Vec<HeadroomConfig>,
+    // Watch the Reservation objects holding capacity on nodes for workloads, requires the
+    // Reservation CRD of deployment/kube-scheduler-rs.yaml
+    #[serde(default)]
+    pub(crate) reservations: bool,
+}
+
+impl Profile {
+    // Whether any filter or score plugin relies on the actual usage of nodes
+    pub(crate) fn uses_load(&self) -> bool {
+        self.filter_plugins
+            .iter()
+            .any(|plugin| matches!(plugin, FilterPlugin::LoadAware { .. }))
+            || self
+                .score_plugins
+                .iter()
+                .any(|plugin| matches!(plugin.plugin, ScorePlugin::LoadAware { .. }))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LoadArgs {
+    // URL serving a NodeMetricsList, e.g. a stand-in for the metrics server, instead of the
+    // metrics.k8s.io API of the cluster
+    #[serde(default)]
+    pub(crate) metrics_url: Option<String>,
+    // Interval in which the usage of nodes is polled
+    #[serde(default = "default_load_interval_seconds")]
+    pub(crate) interval_seconds: u64,
+    // Time span the usage samples of a node are averaged over
+    #[serde(default = "default_load_window_seconds")]
+    pub(crate) window_seconds: u64,
+}
+
+impl Default for LoadArgs {
+    fn default() -> Self {
+        Self {
+            metrics_url: None,
+            interval_seconds: default_load_interval_seconds(),
+            window_seconds: default_load_window_seconds(),
+        }
+    }
+}
+
+fn default_load_interval_seconds() -> u64 {
+    15
+}
+
+fn default_load_window_seconds() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OvercommitConfig {
+    // Nodes the ratios apply to, an empty selector selects all nodes
+    #[serde(default)]
+    pub(crate) node_selector: LabelSelector,
+    // Factor the allocatable quantity of each resource is scaled by, resources not listed
+    // keep a ratio of 1.0. Memory cannot be overcommitted.
+    pub(crate) ratios: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HeadroomConfig {
+    // Nodes the headroom applies to, an empty selector selects all nodes
+    #[serde(default)]
+    pub(crate) node_selector: LabelSelector,
+    // Amount of each resource kept free, either a quantity like 500m or 1Gi or a percentage
+    // of allocatable like 10%
+    pub(crate) resources: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BinPackingArgs {
+    #[serde(default)]
+    pub(crate) ordering: BinPackingOrdering,
+}
+
+// Order in which a batch of pods is placed and how their node is chosen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub(crate) enum BinPackingOrdering {
+    // Place pods by QoS class onto the highest scoring node
+    #[default]
+    Qos,
+    // Place pods by dominant resource share, largest first, onto the first node they fit on
+    FirstFitDecreasing,
+    // Place pods by dominant resource share, largest first, onto the tightest fitting node
+    BestFitDecreasing,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OptimalArgs {
+    // Wall-clock time the solver may spend on a batch before settling for the best
+    // placement found so far
+    #[serde(default = "default_time_budget_ms")]
+    pub(crate) time_budget_ms: u64,
+}
+
+impl Default for OptimalArgs {
+    fn default() -> Self {
+        Self {
+            time_budget_ms: default_time_budget_ms(),
+        }
+    }
+}
+
+fn default_time_budget_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BindingArgs {
+    // Maximum number of bindings in flight at once
+    #[serde(default = "default_binding_concurrency")]
+    pub(crate) concurrency: usize,
+    // Number of retries of a binding failing with a transient error
+    #[serde(default = "default_binding_max_retries")]
+    pub(crate) max_retries: u32,
+    // Backoff before the first retry, doubled with every further retry
+    #[serde(default = "default_binding_initial_backoff_ms")]
+    pub(crate) initial_backoff_ms: u64,
+}
+
+impl Default for BindingArgs {
+    fn default() -> Self {
+        Self {
+            concurrency: default_binding_concurrency(),
+            max_retries: default_binding_max_retries(),
+            initial_backoff_ms: default_binding_initial_backoff_ms(),
+        }
+    }
+}
+
+fn default_binding_concurrency() -> usize {
+    16
+}
+
+fn default_binding_max_retries() -> u32 {
+    3
+}
+
+fn default_binding_initial_backoff_ms() -> u64 {
+    100
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ExtenderConfig {
+    // Base URL of the extender, the verbs are appended as path segments
+    pub(crate) url_prefix: String,
+    // Verbs of the extender; an unset verb is not called
+    #[serde(default)]
+    pub(crate) filter_verb: Option<String>,
+    #[serde(default)]
+    pub(crate) prioritize_verb: Option<String>,
+    // Binding is delegated to the first extender with a bind verb
+    #[serde(default)]
+    pub(crate) bind_verb: Option<String>,
+    // Multiplier of the priorities, which the extender returns between 0 and 10
+    #[serde(default = "default_weight")]
+    pub(crate) weight: i64,
+    #[serde(default = "default_extender_timeout_ms")]
+    pub(crate) timeout_ms: u64,
+    // Failures of an ignorable extender are logged and skipped rather than failing the
+    // scheduling attempt of the pod
+    #[serde(default)]
+    pub(crate) ignorable: bool,
+}
+
+fn default_extender_timeout_ms() -> u64 {
+    5000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CacheArgs {
+    // Time after which a placement whose binding was not observed is dropped
+    #[serde(default = "default_assumed_pod_ttl_seconds")]
+    pub(crate) assumed_pod_ttl_seconds: u64,
+}
+
+impl Default for CacheArgs {
+    fn default() -> Self {
+        Self {
+            assumed_pod_ttl_seconds: default_assumed_pod_ttl_seconds(),
+        }
+    }
+}
+
+fn default_assumed_pod_ttl_seconds() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct QueueArgs {
+    // Backoff after the first failed scheduling attempt of a pod, doubled with every
+    // further failed attempt
+    #[serde(default = "default_pod_initial_backoff_seconds")]
+    pub(crate) pod_initial_backoff_seconds: u64,
+    #[serde(default = "default_pod_max_backoff_seconds")]
+    pub(crate) pod_max_backoff_seconds: u64,
+    // Time after which an unschedulable pod is retried even without a cluster event
+    #[serde(default = "default_pod_max_in_unschedulable_pods_seconds")]
+    pub(crate) pod_max_in_unschedulable_pods_seconds: u64,
+}
+
+impl Default for QueueArgs {
+    fn default() -> Self {
+        Self {
+            pod_initial_backoff_seconds: default_pod_initial_backoff_seconds(),
+            pod_max_backoff_seconds: default_pod_max_backoff_seconds(),
+            pod_max_in_unschedulable_pods_seconds: default_pod_max_in_unschedulable_pods_seconds(),
+        }
+    }
+}
+
+fn default_pod_initial_backoff_seconds() -> u64 {
+    1
+}
+
+fn default_pod_max_backoff_seconds() -> u64 {
+    10
+}
+
+fn default_pod_max_in_unschedulable_pods_seconds() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PodGroupArgs {
+    // Time a pod group may wait for enough of its members to be placed before it is
+    // rejected, during which its placed members hold their nodes
+    #[serde(default = "default_permit_waiting_time_seconds")]
+    pub(crate) permit_waiting_time_seconds: u64,
+}
+
+impl Default for PodGroupArgs {
+    fn default() -> Self {
+        Self {
+            permit_waiting_time_seconds: default_permit_waiting_time_seconds(),
+        }
+    }
+}
+
+fn default_permit_waiting_time_seconds() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AnnealingArgs {
+    #[serde(default = "default_time_budget_ms")]
+    pub(crate) time_budget_ms: u64,
+    #[serde(default = "default_max_iterations")]
+    pub(crate) max_iterations: u64,
+    // Seed of the random number generator, a random seed is used when unset
+    #[serde(default)]
+    pub(crate) seed: Option<u64>,
+    // Temperature at the first iteration, relative to the energy of the objective
+    #[serde(default = "default_initial_temperature")]
+    pub(crate) initial_temperature: f64,
+    // Factor the temperature is multiplied with after each iteration
+    #[serde(default = "default_cooling_rate")]
+    pub(crate) cooling_rate: f64,
+    #[serde(default)]
+    pub(crate) objective: AnnealingObjective,
+    // Node label whose values form the domains replicas of a workload are spread across
+    #[serde(default = "default_topology_key")]
+    pub(crate) topology_key: String,
+}
+
+impl Default for AnnealingArgs {
+    fn default() -> Self {
+        Self {
+            time_budget_ms: default_time_budget_ms(),
+            max_iterations: default_max_iterations(),
+            seed: None,
+            initial_temperature: default_initial_temperature(),
+            cooling_rate: default_cooling_rate(),
+            objective: AnnealingObjective::default(),
+            topology_key: default_topology_key(),
+        }
+    }
+}
+
+// Weights of the terms the annealing objective is made of, each term lies between 0 and 1
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AnnealingObjective {
+    // Fraction of nodes in use
+    #[serde(default = "default_active_nodes_weight")]
+    pub(crate) active_nodes: f64,
+    // Average spread between the utilization of the resources of the nodes in use
+    #[serde(default)]
+    pub(crate) balanced_utilization: f64,
+    // Average skew of the replicas of each workload across topology domains
+    #[serde(default)]
+    pub(crate) topology_spread: f64,
+}
+
+impl Default for AnnealingObjective {
+    fn default() -> Self {
+        Self {
+            active_nodes: default_active_nodes_weight(),
+            balanced_utilization: 0.0,
+            topology_spread: 0.0,
+        }
+    }
+}
+
+fn default_max_iterations() -> u64 {
+    10000
+}
+
+fn default_initial_temperature() -> f64 {
+    0.1
+}
+
+fn default_cooling_rate() -> f64 {
+    0.999
+}
+
+fn default_active_nodes_weight() -> f64 {
+    1.0
+}
+
+fn default_topology_key() -> String {
+    "topology.kubernetes.io/zone".to_owned()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ScorePluginConfig {
+    #[serde(flatten)]
+    pub(crate) plugin: ScorePlugin,
+    #[serde(default = "default_weight")]
+    pub(crate) weight: i64,
+}
+
+fn default_weight() -> i64 {
+    1
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "name")]
+pub(crate) enum ScorePlugin {
+    RequestedToCapacityRatio {
+        args: RequestedToCapacityRatioArgs,
+    },
+    BalancedAllocation {
+        #[serde(default)]
+        args: BalancedAllocationArgs,
+    },
+    // Sum of the weights of the preferred node affinity terms of the pod a node matches
+    NodeAffinity,
+    // Weights of the preferred pod affinity and anti-affinity terms satisfied or violated in
+    // the topology domain of a node
+    InterPodAffinity {
+        #[serde(default)]
+        args: InterPodAffinityArgs,
+    },
+    // Number of PreferNoSchedule taints of a node the pod does not tolerate, fewer is better
+    TaintToleration,
+    // Sizes of the container images of the pod a node already holds, scaled by how widely
+    // the images are spread across nodes
+    ImageLocality,
+    // Network bandwidth a node advertises that is left once the pod is placed
+    NetworkBandwidth,
+    // Share of the resources of a node left by its actual usage, averaged over the window
+    LoadAware {
+        #[serde(default)]
+        args: LoadAwareArgs,
+    },
+    Wasm {
+        args: WasmPluginArgs,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct InterPodAffinityArgs {
+    // Weight of the required affinity terms of placed pods that the incoming pod matches
+    #[serde(default = "default_hard_pod_affinity_weight")]
+    pub(crate) hard_pod_affinity_weight: i64,
+}
+
+impl Default for InterPodAffinityArgs {
+    fn default() -> Self {
+        Self {
+            hard_pod_affinity_weight: default_hard_pod_affinity_weight(),
+        }
+    }
+}
+
+fn default_hard_pod_affinity_weight() -> i64 {
+    1
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "name")]
+pub(crate) enum FilterPlugin {
+    // Reject nodes whose actual usage of a resource exceeds its threshold
+    LoadAware { args: LoadThresholdArgs },
+    Wasm { args: WasmPluginArgs },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LoadAwareArgs {
+    // Resources whose usage is taken into account, the most used one decides the score
+    #[serde(default = "default_load_resources")]
+    pub(crate) resources: Vec<String>,
+}
+
+impl Default for LoadAwareArgs {
+    fn default() -> Self {
+        Self {
+            resources: default_load_resources(),
+        }
+    }
+}
+
+fn default_load_resources() -> Vec<String> {
+    vec!["cpu".to_owned(), "memory".to_owned()]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LoadThresholdArgs {
+    // Highest usage of each resource, as a fraction of allocatable, a node may run at
+    pub(crate) thresholds: BTreeMap<String, f64>,
+}
+
+// WebAssembly module implementing a filter or score plugin, see scheduler/wasm.rs for the
+// interface the module has to export
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct WasmPluginArgs {
+    // Path to the module, in binary or text format
+    pub(crate) path: PathBuf,
+    // Fuel a single call may consume, roughly the number of instructions it may execute
+    #[serde(default = "default_wasm_fuel")]
+    pub(crate) fuel: u64,
+    // Wall-clock time a single call may take
+    #[serde(default = "default_wasm_timeout_ms")]
+    pub(crate) timeout_ms: u64,
+    // Whether pods may be scheduled without the plugin if its module fails to load, otherwise
+    // a filter rejects every node until it loads
+    #[serde(default)]
+    pub(crate) ignorable: bool,
+}
+
+fn default_wasm_fuel() -> u64 {
+    10_000_000
+}
+
+fn default_wasm_timeout_ms() -> u64 {
+    100
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RequestedToCapacityRatioArgs {
+    // Piecewise linear function mapping the utilization of a resource to a score
+    pub(crate) shape: Vec<UtilizationShapePoint>,
+    #[serde(default = "default_resources")]
+    pub(crate) resources: Vec<ResourceSpec>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BalancedAllocationArgs {
+    // Resources whose utilization should be kept balanced, weights are ignored
+    #[serde(default = "default_resources")]
+    pub(crate) resources: Vec<ResourceSpec>,
+}
+
+impl Default for BalancedAllocationArgs {
+    fn default() -> Self {
+        Self {
+            resources: default_resources(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UtilizationShapePoint {
+    // Utilization in percent, between 0 and 100
+    pub(crate) utilization: i64,
+    // Score between 0 and 10
+    pub(crate) score: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ResourceSpec {
+    pub(crate) name: String,
+    #[serde(default = "default_weight")]
+    pub(crate) weight: i64,
+}
+
+fn default_resources() -> Vec<ResourceSpec> {
+    vec![
+        ResourceSpec {
+            name: "cpu".to_owned(),
+            weight: 1,
+        },
+        ResourceSpec {
+            name: "memory".to_owned(),
+            weight: 1,
+        },
+    ]
+}
+
+impl SchedulerConfig {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let config: SchedulerConfig = serde_yaml::from_reader(std::fs::File::open(path)?)?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    // Profile for the given scheduler name, falling back to an empty profile
+    pub(crate) fn profile(&self, scheduler_name: &str) -> Profile {
+        self.profiles
+            .iter()
+            .find(|profile| profile.scheduler_name == scheduler_name)
+            .cloned()
+            .unwrap_or_else(|| Profile {
+                scheduler_name: scheduler_name.to_owned(),
+                ..Default::default()
+            })
+    }
+
+    fn validate(&self) -> Result<()> {
+        for profile in &self.profiles {
+            for plugin in &profile.score_plugins {
+                if plugin.weight < 0 {
+                    color_eyre::eyre::bail!(
+                        "Score plugin weights must not be negative in profile {}",
+                        profile.scheduler_name
+                    );
+                }
+
+                match &plugin.plugin {
+                    ScorePlugin::RequestedToCapacityRatio { args } => args.validate()?,
+                    ScorePlugin::BalancedAllocation { args } => {
+                        if args.resources.is_empty() {
+                            color_eyre::eyre::bail!(
+                                "BalancedAllocation requires at least one resource"
+                            );
+                        }
+                    }
+                    ScorePlugin::NodeAffinity => {}
+                    ScorePlugin::InterPodAffinity { args } => {
+                        if !(0..=100).contains(&args.hard_pod_affinity_weight) {
+                            color_eyre::eyre::bail!(
+                                "InterPodAffinity hard pod affinity weight must be between 0 and 100, got {}",
+                                args.hard_pod_affinity_weight
+                            );
+                        }
+                    }
+                    ScorePlugin::TaintToleration
+                    | ScorePlugin::ImageLocality
+                    | ScorePlugin::NetworkBandwidth
+                    | ScorePlugin::LoadAware { .. } => {}
+                    ScorePlugin::Wasm { args } => args.validate(wasm::SCORE_EXPORT)?,
+                }
+            }
+
+            for plugin in &profile.filter_plugins {
+                match plugin {
+                    FilterPlugin::LoadAware { args } => {
+                        if args
+                            .thresholds
+                            .values()
+                            .any(|threshold| !(threshold.is_finite() && *threshold > 0.0))
+                        {
+                            color_eyre::eyre::bail!(
+                                "LoadAware thresholds must be finite and positive in profile {}",
+                                profile.scheduler_name
+                            );
+                        }
+                    }
+                    FilterPlugin::Wasm { args } => args.validate(wasm::FILTER_EXPORT)?,
+                }
+            }
+
+            profile.annealing.validate()?;
+
+            for headroom in &profile.headroom {
+                for (resource, amount) in &headroom.resources {
+                    if let Err(err) = capacity::Amount::parse(amount) {
+                        color_eyre::eyre::bail!(
+                            "Invalid headroom of {resource} in profile {}: {err}",
+                            profile.scheduler_name
+                        );
+                    }
+                }
+            }
+
+            for overcommit in &profile.overcommit {
+                for (resource, ratio) in &overcommit.ratios {
+                    if !(ratio.is_finite() && *ratio > 0.0) {
+                        color_eyre::eyre::bail!(
+                            "Overcommit ratio of {resource} must be finite and positive in profile {}",
+                            profile.scheduler_name
+                        );
+                    }
+                    if resource == "memory" && *ratio != 1.0 {
+                        color_eyre::eyre::bail!(
+                            "Memory cannot be overcommitted in profile {}",
+                            profile.scheduler_name
+                        );
+                    }
+                }
+            }
+
+            if profile.uses_load()
+                && (profile.load.interval_seconds == 0 || profile.load.window_seconds == 0)
+            {
+                color_eyre::eyre::bail!(
+                    "Load interval and window must be positive in profile {}",
+                    profile.scheduler_name
+                );
+            }
+
+            for extender in &profile.extenders {
+                if extender.weight <= 0 {
+                    color_eyre::eyre::bail!(
+                        "Extender weights must be positive in profile {}",
+                        profile.scheduler_name
+                    );
+                }
+                if extender.timeout_ms == 0 {
+                    color_eyre::eyre::bail!(
+                        "Extender timeouts must be positive in profile {}",
+                        profile.scheduler_name
+                    );
+                }
+            }
+
+            if profile.queue.pod_initial_backoff_seconds > profile.queue.pod_max_backoff_seconds {
+                color_eyre::eyre::bail!(
+                    "The initial pod backoff must not exceed the maximum pod backoff in profile {}",
+                    profile.scheduler_name
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl AnnealingArgs {
+    fn validate(&self) -> Result<()> {
+        if !(self.initial_temperature.is_finite() && self.initial_temperature > 0.0) {
+            color_eyre::eyre::bail!("Annealing initial temperature must be finite and positive");
+        }
+        if !(self.cooling_rate > 0.0 && self.cooling_rate <= 1.0) {
+            color_eyre::eyre::bail!(
+                "Annealing cooling rate must be between 0 and 1, got {}",
+                self.cooling_rate
+            );
+        }
+
+        let objective = &self.objective;
+        if [
+            objective.active_nodes,
+            objective.balanced_utilization,
+            objective.topology_spread,
+        ]
+        .iter()
+        .any(|weight| !(weight.is_finite() && *weight >= 0.0))
+        {
+            color_eyre::eyre::bail!("Annealing objective weights must be finite and not negative");
+        }
+
+        Ok(())
+    }
+}
+
+impl WasmPluginArgs {
+    // Compile the module up front, so a broken plugin fails the start of the scheduler
+    // rather than its scheduling runs
+    fn validate(&self, export: &str) -> Result<()> {
+        if self.fuel == 0 || self.timeout_ms == 0 {
+            color_eyre::eyre::bail!(
+                "Fuel and timeout of WebAssembly plugin {} must be positive",
+                self.path.display()
+            );
+        }
+
+        wasm::check_module(&self.path, export)
+    }
+}
+
+impl RequestedToCapacityRatioArgs {
+    fn validate(&self) -> Result<()> {
+        if self.shape.is_empty() {
+            color_eyre::eyre::bail!("RequestedToCapacityRatio shape must not be empty");
+        }
+
+        for point in &self.shape {
+            if !(0..=100).contains(&point.utilization) {
+                color_eyre::eyre::bail!(
+                    "RequestedToCapacityRatio utilization must be between 0 and 100, got {}",
+                    point.utilization
+                );
+            }
+            if !(0..=10).contains(&point.score) {
+                color_eyre::eyre::bail!(
+                    "RequestedToCapacityRatio score must be between 0 and 10, got {}",
+                    point.score
+                );
+            }
+        }
+
+        if self
+            .shape
+            .windows(2)
+            .any(|points| points[0].utilization >= points[1].utilization)
+        {
+            color_eyre::eyre::bail!(
+                "RequestedToCapacityRatio shape utilization values must be strictly increasing"
+            );
+        }
+
+        if self.resources.iter().any(|resource| resource.weight <= 0) {
+            color_eyre::eyre::bail!("RequestedToCapacityRatio resource weights must be positive");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_requested_to_capacity_ratio() {
+        let config: SchedulerConfig = serde_yaml::from_str(
+            r#"
+profiles:
+  - schedulerName: kube-scheduler-rs
+    scorePlugins:
+      - name: RequestedToCapacityRatio
+        weight: 2
+        args:
+          shape:
+            - utilization: 0
+              score: 0
+            - utilization: 100
+              score: 10
+          resources:
+            - name: cpu
+              weight: 3
+"#,
+        )
+        .unwrap();
+
+        config.validate().unwrap();
+
+        let profile = config.profile("kube-scheduler-rs");
+        assert_eq!(profile.score_plugins.len(), 1);
+        assert_eq!(profile.score_plugins[0].weight, 2);
+
+        let ScorePlugin::RequestedToCapacityRatio { args } = &profile.score_plugins[0].plugin
+        else {
+            panic!("expected RequestedToCapacityRatio");
+        };
+        assert_eq!(args.shape.len(), 2);
+        assert_eq!(args.resources[0].name, "cpu");
+        assert_eq!(args.resources[0].weight, 3);
+    }
+
+    #[test]
+    fn test_parse_balanced_allocation_without_args() {
+        let config: SchedulerConfig = serde_yaml::from_str(
+            r#"
+profiles:
+  - schedulerName: kube-scheduler-rs
+    scorePlugins:
+      - name: BalancedAllocation
+"#,
+        )
+        .unwrap();
+
+        let profile = config.profile("kube-scheduler-rs");
+        assert_eq!(profile.score_plugins[0].weight, 1);
+
+        let ScorePlugin::BalancedAllocation { args } = &profile.score_plugins[0].plugin else {
+            panic!("expected BalancedAllocation");
+        };
+        assert_eq!(args.resources.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_bin_packing_ordering() {
+        let config: SchedulerConfig = serde_yaml::from_str(
+            r#"
+profiles:
+  - schedulerName: ffd
+    binPacking:
+      ordering: FirstFitDecreasing
+  - schedulerName: bfd
+    binPacking:
+      ordering: BestFitDecreasing
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.profile("ffd").bin_packing.ordering,
+            BinPackingOrdering::FirstFitDecreasing
+        );
+        assert_eq!(
+            config.profile("bfd").bin_packing.ordering,
+            BinPackingOrdering::BestFitDecreasing
+        );
+        assert_eq!(
+            config.profile("other").bin_packing.ordering,
+            BinPackingOrdering::Qos
+        );
+    }
+
+    #[test]
+    fn test_parse_annealing() {
+        let config: SchedulerConfig = serde_yaml::from_str(
+            r#"
+profiles:
+  - schedulerName: kube-scheduler-rs
+    annealing:
+      seed: 42
+      objective:
+        balancedUtilization: 0.5
+"#,
+        )
+        .unwrap();
+
+        config.validate().unwrap();
+
+        let annealing = config.profile("kube-scheduler-rs").annealing;
+        assert_eq!(annealing.seed, Some(42));
+        assert_eq!(annealing.max_iterations, 10000);
+        assert_eq!(annealing.objective.active_nodes, 1.0);
+        assert_eq!(annealing.objective.balanced_utilization, 0.5);
+        assert_eq!(annealing.topology_key, "topology.kubernetes.io/zone");
+
+        let config = |annealing: &str| -> SchedulerConfig {
+            serde_yaml::from_str(&format!(
+                r#"
+profiles:
+  - schedulerName: kube-scheduler-rs
+    annealing: {annealing}
+"#
+            ))
+            .unwrap()
+        };
+        assert!(config("{initialTemperature: .nan}").validate().is_err());
+        assert!(config("{initialTemperature: .inf}").validate().is_err());
+        assert!(config("{objective: {activeNodes: .nan}}")
+            .validate()
+            .is_err());
+        assert!(config("{objective: {activeNodes: .inf}}")
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_reject_invalid_extenders() {
+        let config = |weight: i64, timeout_ms: u64| -> SchedulerConfig {
+            serde_yaml::from_str(&format!(
+                r#"
+profiles:
+  - schedulerName: kube-scheduler-rs
+    extenders:
+      - urlPrefix: http://extender
+        filterVerb: filter
+        weight: {weight}
+        timeoutMs: {timeout_ms}
+"#
+            ))
+            .unwrap()
+        };
+
+        config(1, 100).validate().unwrap();
+        assert!(config(0, 100).validate().is_err());
+        assert!(config(1, 0).validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_load_aware() {
+        let config: SchedulerConfig = serde_yaml::from_str(
+            r#"
+profiles:
+  - schedulerName: kube-scheduler-rs
+    load:
+      windowSeconds: 60
+    filterPlugins:
+      - name: LoadAware
+        args:
+          thresholds:
+            cpu: 0.8
+    scorePlugins:
+      - name: LoadAware
+"#,
+        )
+        .unwrap();
+
+        config.validate().unwrap();
+
+        let profile = config.profile("kube-scheduler-rs");
+        assert!(profile.uses_load());
+        assert_eq!(profile.load.interval_seconds, 15);
+        assert_eq!(profile.load.window_seconds, 60);
+
+        let ScorePlugin::LoadAware { args } = &profile.score_plugins[0].plugin else {
+            panic!("expected LoadAware");
+        };
+        assert_eq!(args.resources, ["cpu", "memory"]);
+        assert!(!SchedulerConfig::default().profile("other").uses_load());
+
+        let config = |threshold: &str| -> SchedulerConfig {
+            serde_yaml::from_str(&format!(
+                r#"
+profiles:
+  - schedulerName: kube-scheduler-rs
+    filterPlugins:
+      - name: LoadAware
+        args:
+          thresholds:
+            cpu: {threshold}
+"#
+            ))
+            .unwrap()
+        };
+        assert!(config("0.0").validate().is_err());
+        assert!(config(".nan").validate().is_err());
+        assert!(config(".inf").validate().is_err());
+    }
+
+    #[test]
+    fn test_reject_memory_overcommit() {
+        let config = |ratios: &str| -> SchedulerConfig {
+            serde_yaml::from_str(&format!(
+                r#"
+profiles:
+  - schedulerName: kube-scheduler-rs
+    overcommit:
+      - nodeSelector:
+          matchLabels:
+            pool: batch
+        ratios: {ratios}
+"#
+            ))
+            .unwrap()
+        };
+
+        config("{cpu: 2.0, memory: 1.0}").validate().unwrap();
+        assert!(config("{cpu: 2.0, memory: 1.5}").validate().is_err());
+        assert!(config("{cpu: 0.0}").validate().is_err());
+        assert!(config("{cpu: .nan}").validate().is_err());
+        assert!(config("{cpu: .inf}").validate().is_err());
+    }
+
+    #[test]
+    fn test_reject_invalid_headroom() {
+        let config = |amount: &str| -> SchedulerConfig {
+            serde_yaml::from_str(&format!(
+                r#"
+profiles:
+  - schedulerName: kube-scheduler-rs
+    headroom:
+      - resources:
+          cpu: {amount}
+"#
+            ))
+            .unwrap()
+        };
+
+        config("10%").validate().unwrap();
+        config("500m").validate().unwrap();
+        assert!(config("110%").validate().is_err());
+        assert!(config("plenty").validate().is_err());
+    }
+
+    #[test]
+    fn test_unknown_profile_falls_back_to_default() {
+        let profile = SchedulerConfig::default().profile("other");
+
+        assert_eq!(profile.scheduler_name, "other");
+        assert!(profile.score_plugins.is_empty());
+    }
+
+    #[test]
+    fn test_reject_decreasing_shape() {
+        let args = RequestedToCapacityRatioArgs {
+            shape: vec![
+                UtilizationShapePoint {
+                    utilization: 50,
+                    score: 5,
+                },
+                UtilizationShapePoint {
+                    utilization: 10,
+                    score: 0,
+                },
+            ],
+            resources: default_resources(),
+        };
+
+        assert!(args.validate().is_err());
+    }
+}
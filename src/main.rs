@@ -1,9 +1,13 @@
 #![forbid(unsafe_code)]
 
+mod config;
+mod metrics;
 mod reconciler;
 mod scheduler;
 mod utils;
 
+use std::{net::SocketAddr, path::PathBuf};
+
 use clap::{Parser, ValueEnum};
 use clap_verbosity_flag::InfoLevel;
 use color_eyre::Result;
@@ -23,6 +27,32 @@ struct Cli {
     #[arg(long, env, default_value = "kube-scheduler-rs")]
     scheduler_name: String,
 
+    /// Path to a scheduler configuration file defining the profile of this scheduler
+    #[arg(long, env)]
+    config: Option<PathBuf>,
+
+    /// Compute placements without binding pods: `client` only reports them, `server`
+    /// additionally validates the bindings with a server-side dry run
+    #[arg(value_enum, long, env)]
+    dry_run: Option<DryRun>,
+
+    /// File the would-be bindings of a dry run are appended to as JSON lines
+    #[arg(long, env)]
+    dry_run_output: Option<PathBuf>,
+
+    /// Name of another scheduler to shadow: its bindings are compared with the nodes the
+    /// algorithm would have chosen at the moment they were made
+    #[arg(long, env)]
+    shadow_scheduler_name: Option<String>,
+
+    /// File the decisions of the shadow mode are appended to as JSON lines
+    #[arg(long, env)]
+    shadow_report: Option<PathBuf>,
+
+    /// Address to serve Prometheus metrics on, e.g. 0.0.0.0:9090
+    #[arg(long, env)]
+    metrics_address: Option<SocketAddr>,
+
     /// Debounce duration in seconds
     #[arg(long, env, default_value_t = 3)]
     debounce_duration: u64,
@@ -35,6 +65,20 @@ struct Cli {
 #[derive(ValueEnum, Clone, Debug)]
 enum Algorithm {
     BinPacking,
+    /// Spread pods across nodes by preferring the node with the most remaining capacity
+    LeastAllocated,
+    /// Place each batch of pods at once, minimizing the number of nodes in use within a time
+    /// budget
+    Optimal,
+    /// Improve the greedy placement of each batch of pods by moving and swapping pods
+    /// between nodes within a time budget
+    Annealing,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DryRun {
+    Client,
+    Server,
 }
 
 #[tokio::main]
@@ -0,0 +1,41 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use color_eyre::Result;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Response, Server,
+};
+use prometheus::{Encoder, Registry, TextEncoder};
+
+// Serve the metrics of the registry in the Prometheus text format on every path
+pub(crate) async fn serve(address: SocketAddr, registry: Registry) -> Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let registry = registry.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |_| {
+                let registry = registry.clone();
+
+                async move {
+                    let encoder = TextEncoder::new();
+                    let mut buffer = vec![];
+                    if let Err(err) = encoder.encode(&registry.gather(), &mut buffer) {
+                        log::error!("Failed to encode metrics: {err}");
+                    }
+
+                    Ok::<_, Infallible>(
+                        Response::builder()
+                            .header(hyper::header::CONTENT_TYPE, encoder.format_type())
+                            .body(Body::from(buffer))
+                            .unwrap_or_default(),
+                    )
+                }
+            }))
+        }
+    });
+
+    log::info!("Serving metrics on {address}");
+    Server::try_bind(&address)?.serve(make_service).await?;
+
+    Ok(())
+}
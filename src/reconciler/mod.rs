@@ -1,9 +1,29 @@
-use color_eyre::Result;
+use std::{future::Future, time::Duration};
+
+use futures::StreamExt;
 use k8s_openapi::{
-    api::core::v1::{Binding, Pod},
+    api::core::v1::{Binding, ObjectReference, PersistentVolume, PersistentVolumeClaim, Pod},
     apimachinery::pkg::apis::meta::v1::Status,
 };
-use kube::{api::PostParams, Api, Client};
+use kube::{
+    api::{Patch, PatchParams, PostParams},
+    Api, Client,
+};
+use serde_json::json;
+
+use crate::{
+    config::BindingArgs,
+    scheduler::{
+        extenders::{Extender, ExtenderError},
+        volumes::ClaimBinding,
+    },
+};
+
+// Annotation telling the provisioner of a claim waiting for its first consumer which node
+// the consumer was placed on
+const SELECTED_NODE_ANNOTATION: &str = "volume.kubernetes.io/selected-node";
+// Annotation of volumes the scheduler or the volume controller reserved for a claim
+const BOUND_BY_CONTROLLER_ANNOTATION: &str = "pv.kubernetes.io/bound-by-controller";
 
 pub(crate) struct PodBindParameters {
     pub(crate) client: Client,
@@ -11,51 +31,440 @@ pub(crate) struct PodBindParameters {
     pub(crate) pod_namespace: String,
     pub(crate) node_name: String,
     pub(crate) scheduler_name: String,
+    // Only validate the binding with a server-side dry run
+    pub(crate) dry_run: bool,
 }
 
-pub(crate) async fn bind_pod_to_node(params: PodBindParameters) -> Result<()> {
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum BindError {
+    // The pod is bound to a node already, possibly by another scheduler
+    #[error("pod {pod} is already bound")]
+    Conflict { pod: String },
+    // The pod was deleted in the meantime
+    #[error("pod {pod} no longer exists")]
+    NotFound { pod: String },
+    // Server errors, throttling and connection failures, worth retrying
+    #[error("transient error while binding pod {pod}: {source}")]
+    Transient {
+        pod: String,
+        #[source]
+        source: kube::Error,
+    },
+    #[error("failed to bind pod {pod}: {source}")]
+    Permanent {
+        pod: String,
+        #[source]
+        source: kube::Error,
+    },
+    #[error("unexpected status while binding pod {pod}: {status:?}")]
+    UnexpectedStatus { pod: String, status: Box<Status> },
+    #[error("extender failed to bind pod {pod}: {source}")]
+    Extender {
+        pod: String,
+        #[source]
+        source: ExtenderError,
+    },
+    // Preparing a volume or claim of the pod failed, the pod is scheduled again
+    #[error("failed to bind volume {volume} of pod {pod}: {source}")]
+    Volume {
+        pod: String,
+        volume: String,
+        #[source]
+        source: VolumeError,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum VolumeError {
+    // Another claim took the volume since the scheduler run chose it
+    #[error("volume is bound to another claim")]
+    Claimed,
+    #[error(transparent)]
+    Api(#[from] kube::Error),
+}
+
+impl BindError {
+    pub(crate) fn from_kube(pod: String, source: kube::Error) -> Self {
+        match &source {
+            kube::Error::Api(response) if response.code == 409 => Self::Conflict { pod },
+            kube::Error::Api(response) if response.code == 404 => Self::NotFound { pod },
+            _ if is_transient_kube(&source) => Self::Transient { pod, source },
+            _ => Self::Permanent { pod, source },
+        }
+    }
+
+    pub(crate) fn is_transient(&self) -> bool {
+        match self {
+            Self::Transient { .. } => true,
+            Self::Extender { source, .. } => source.is_transient(),
+            Self::Volume {
+                source: VolumeError::Api(source),
+                ..
+            } => is_transient_kube(source),
+            _ => false,
+        }
+    }
+}
+
+// Server errors, throttling and connection failures
+fn is_transient_kube(err: &kube::Error) -> bool {
+    match err {
+        kube::Error::Api(response) => response.code == 429 || response.code >= 500,
+        kube::Error::HyperError(_) | kube::Error::Service(_) => true,
+        _ => false,
+    }
+}
+
+pub(crate) async fn bind_pod_to_node(params: PodBindParameters) -> Result<(), BindError> {
     let PodBindParameters {
         client,
         pod_name,
         pod_namespace,
         node_name,
         scheduler_name,
+        dry_run,
     } = params;
 
+    let pod = format!("{pod_namespace}/{pod_name}");
     let pods: Api<Pod> = Api::namespaced(client.clone(), &pod_namespace);
 
+    let binding = serde_json::to_vec(&Binding {
+        metadata: kube::core::ObjectMeta {
+            name: Some(pod_name.clone()),
+            ..Default::default()
+        },
+        target: ObjectReference {
+            api_version: Some("v1".to_owned()),
+            kind: Some("Node".to_owned()),
+            name: Some(node_name.clone()),
+            ..Default::default()
+        },
+    })
+    .map_err(|err| BindError::Permanent {
+        pod: pod.clone(),
+        source: kube::Error::SerdeError(err),
+    })?;
+
     let res: Result<Status, kube::Error> = pods
         .create_subresource(
             "binding",
             &pod_name.clone(),
             &PostParams {
+                dry_run,
                 field_manager: Some(scheduler_name.clone()),
-                ..Default::default()
             },
-            serde_json::to_vec(&Binding {
-                metadata: kube::core::ObjectMeta {
-                    name: Some(pod_name.clone()),
-                    ..Default::default()
-                },
-                target: k8s_openapi::api::core::v1::ObjectReference {
-                    api_version: Some("v1".to_owned()),
-                    kind: Some("Node".to_owned()),
-                    name: Some(node_name.clone()),
-                    ..Default::default()
-                },
-            })?,
+            binding,
         )
         .await;
     log::debug!("res: {res:#?}");
 
-    let status = res?;
-    let Some(code) =  status.code else { color_eyre::eyre::bail!("Could not obtain status code from kubernetes response") };
+    let status = res.map_err(|err| BindError::from_kube(pod.clone(), err))?;
+
+    match status.code {
+        Some(code) if (200..=202).contains(&code) => Ok(()),
+        _ => Err(BindError::UnexpectedStatus {
+            pod,
+            status: Box::new(status),
+        }),
+    }
+}
+
+// Prepare the unbound claims of a pod for its binding: reserve the chosen volumes for their
+// claims and select the node for the claims to be provisioned. The volume controller and
+// the provisioners finish the binding of the claims once they see these changes.
+pub(crate) async fn bind_pod_volumes(
+    client: &Client,
+    pod: &str,
+    node_name: &str,
+    bindings: &[ClaimBinding],
+    scheduler_name: &str,
+    dry_run: bool,
+) -> Result<(), BindError> {
+    for binding in bindings {
+        let (volume, result) = match binding {
+            ClaimBinding::Bind {
+                namespace,
+                claim,
+                uid,
+                volume,
+            } => (
+                volume.clone(),
+                reserve_volume(
+                    client,
+                    volume,
+                    namespace,
+                    claim,
+                    uid.as_deref(),
+                    scheduler_name,
+                    dry_run,
+                )
+                .await,
+            ),
+            ClaimBinding::Provision { namespace, claim } => {
+                let params = PatchParams {
+                    dry_run,
+                    field_manager: Some(scheduler_name.to_owned()),
+                    ..Default::default()
+                };
+                let patch = json!({
+                    "metadata": { "annotations": { SELECTED_NODE_ANNOTATION: node_name } }
+                });
+                let result = Api::<PersistentVolumeClaim>::namespaced(client.clone(), namespace)
+                    .patch(claim, &params, &Patch::Merge(patch))
+                    .await
+                    .map(|_| ())
+                    .map_err(VolumeError::from);
+
+                (format!("{namespace}/{claim}"), result)
+            }
+        };
+
+        result.map_err(|source| BindError::Volume {
+            pod: pod.to_owned(),
+            volume,
+            source,
+        })?;
+    }
+
+    Ok(())
+}
+
+// Reserve the volume for the claim with an update carrying the resourceVersion the volume
+// was read at, so a claim taking the volume in the meantime makes the update fail rather
+// than being overwritten. A volume reserved for the claim already is left as it is.
+async fn reserve_volume(
+    client: &Client,
+    volume: &str,
+    namespace: &str,
+    claim: &str,
+    uid: Option<&str>,
+    scheduler_name: &str,
+    dry_run: bool,
+) -> Result<(), VolumeError> {
+    let volumes = Api::<PersistentVolume>::all(client.clone());
+    let mut pv = volumes.get(volume).await?;
+
+    let spec = pv.spec.get_or_insert_with(Default::default);
+    if let Some(claim_ref) = &spec.claim_ref {
+        let reserved_for_claim = claim_ref.namespace.as_deref() == Some(namespace)
+            && claim_ref.name.as_deref() == Some(claim)
+            && (uid.is_none() || claim_ref.uid.as_deref() == uid);
+        if reserved_for_claim {
+            return Ok(());
+        }
+        return Err(VolumeError::Claimed);
+    }
+    spec.claim_ref = Some(ObjectReference {
+        api_version: Some("v1".to_owned()),
+        kind: Some("PersistentVolumeClaim".to_owned()),
+        namespace: Some(namespace.to_owned()),
+        name: Some(claim.to_owned()),
+        uid: uid.map(str::to_owned),
+        ..Default::default()
+    });
+    pv.metadata
+        .annotations
+        .get_or_insert_with(Default::default)
+        .insert(BOUND_BY_CONTROLLER_ANNOTATION.to_owned(), "yes".to_owned());
+
+    let params = PostParams {
+        dry_run,
+        field_manager: Some(scheduler_name.to_owned()),
+    };
+    volumes.replace(volume, &params, &pv).await?;
+
+    Ok(())
+}
+
+// Bind the pods to their nodes with at most the configured number of bindings in flight,
+// retrying transient failures, and return the outcome of each binding. The volumes chosen
+// for the unbound claims of a pod are bound before the pod itself. Bindings are delegated
+// to the binder extender if there is one, except for server-side dry runs as extenders
+// cannot validate a binding without making it.
+pub(crate) async fn bind_pods(
+    client: Client,
+    scheduler_name: String,
+    placements: Vec<(Pod, String, Vec<ClaimBinding>)>,
+    args: BindingArgs,
+    binder: Option<&Extender>,
+    dry_run: bool,
+) -> Vec<(Pod, Result<(), BindError>)> {
+    let binder = binder.filter(|_| !dry_run);
+    let concurrency = args.concurrency.max(1);
+
+    futures::stream::iter(placements)
+        .map(|(pod, node_name, volume_bindings)| {
+            let client = client.clone();
+            let scheduler_name = scheduler_name.clone();
+            let args = &args;
+
+            async move {
+                let result = with_retry(args, || async {
+                    let key = format!(
+                        "{}/{}",
+                        pod.metadata.namespace.as_deref().unwrap_or_default(),
+                        pod.metadata.name.as_deref().unwrap_or_default()
+                    );
+                    bind_pod_volumes(
+                        &client,
+                        &key,
+                        &node_name,
+                        &volume_bindings,
+                        &scheduler_name,
+                        dry_run,
+                    )
+                    .await?;
+
+                    if let Some(binder) = binder {
+                        return binder
+                            .bind(&pod, &node_name)
+                            .await
+                            .map_err(|source| BindError::Extender { pod: key, source });
+                    }
+
+                    bind_pod_to_node(PodBindParameters {
+                        client: client.clone(),
+                        pod_name: pod.metadata.name.clone().unwrap_or_default(),
+                        pod_namespace: pod.metadata.namespace.clone().unwrap_or_default(),
+                        node_name: node_name.clone(),
+                        scheduler_name: scheduler_name.clone(),
+                        dry_run,
+                    })
+                    .await
+                })
+                .await;
+
+                (pod, result)
+            }
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await
+}
+
+// Retry transient failures with a backoff doubling after every attempt
+async fn with_retry<F, Fut>(args: &BindingArgs, mut bind: F) -> Result<(), BindError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), BindError>>,
+{
+    let mut backoff = Duration::from_millis(args.initial_backoff_ms);
+    let mut retries = 0;
+
+    loop {
+        match bind().await {
+            Err(err) if err.is_transient() && retries < args.max_retries => {
+                log::debug!("Retrying in {backoff:?}: {err}");
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                retries += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use kube::error::ErrorResponse;
+
+    use super::*;
+
+    fn api_error(code: u16) -> kube::Error {
+        kube::Error::Api(ErrorResponse {
+            status: "Failure".to_string(),
+            message: String::new(),
+            reason: String::new(),
+            code,
+        })
+    }
+
+    #[test]
+    fn test_classify_api_errors() {
+        let classify = |code| BindError::from_kube("default/p".to_string(), api_error(code));
+
+        assert!(matches!(classify(409), BindError::Conflict { .. }));
+        assert!(matches!(classify(404), BindError::NotFound { .. }));
+        assert!(classify(500).is_transient());
+        assert!(classify(503).is_transient());
+        assert!(classify(429).is_transient());
+        assert!(matches!(classify(403), BindError::Permanent { .. }));
+    }
+
+    #[test]
+    fn test_classify_volume_errors() {
+        let volume = |source| BindError::Volume {
+            pod: "default/p".to_string(),
+            volume: "pv".to_string(),
+            source,
+        };
+
+        // Lost races on the volume are not retried in place but scheduled again
+        assert!(!volume(VolumeError::Claimed).is_transient());
+        assert!(!volume(VolumeError::Api(api_error(409))).is_transient());
+        assert!(volume(VolumeError::Api(api_error(503))).is_transient());
+    }
+
+    fn args() -> BindingArgs {
+        BindingArgs {
+            initial_backoff_ms: 1,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient_failures() {
+        let calls = AtomicU32::new(0);
+
+        let result = with_retry(&args(), || async {
+            if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                Err(BindError::from_kube(
+                    "default/p".to_string(),
+                    api_error(503),
+                ))
+            } else {
+                Ok(())
+            }
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_of_permanent_failures() {
+        let calls = AtomicU32::new(0);
+
+        let result = with_retry(&args(), || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(BindError::from_kube(
+                "default/p".to_string(),
+                api_error(409),
+            ))
+        })
+        .await;
+
+        assert!(matches!(result, Err(BindError::Conflict { .. })));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retries_are_bounded() {
+        let calls = AtomicU32::new(0);
+
+        let result = with_retry(&args(), || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(BindError::from_kube(
+                "default/p".to_string(),
+                api_error(500),
+            ))
+        })
+        .await;
 
-    if (200..=202).contains(&code) {
-        Ok(())
-    } else {
-        Err(color_eyre::eyre::eyre!(
-            "An error occurred while trying to bind pod to node: {status:?}"
-        ))
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), args().max_retries + 1);
     }
 }
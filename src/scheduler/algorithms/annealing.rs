@@ -0,0 +1,608 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, Instant},
+};
+
+use color_eyre::Result;
+use k8s_openapi::api::core::v1::Pod;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    config::{AnnealingArgs, Profile},
+    scheduler::{
+        algorithms::{
+            bin_packing,
+            model::{clone_world, Assignment, Problem, Usage, EPSILON},
+            sort_unscheduled_pods,
+        },
+        extenders, filters, TargetState, WorldState,
+    },
+};
+
+// Number of iterations between two checks of the time budget
+const BUDGET_CHECK_INTERVAL: u64 = 256;
+
+// Energy added per unit of value of a pod left unscheduled. It outweighs every term of the
+// objective, so placing a pod is always worth more than any rearrangement.
+const UNPLACED_PENALTY: f64 = 1000.0;
+
+// Improve the greedy bin packing placement of the batch of unscheduled pods with simulated
+// annealing. Each iteration moves a pod of the batch onto another feasible node or swaps the
+// nodes of two pods, and keeps the change when it lowers the energy of the objective, or
+// with a probability shrinking with the temperature otherwise. Pods already bound to nodes
+// are never moved.
+pub(crate) async fn schedule(params: WorldState, profile: &Profile) -> Result<TargetState> {
+    let greedy = bin_packing::schedule(clone_world(&params), profile).await?;
+
+    let WorldState {
+        nodes,
+        unscheduled_pods,
+        state,
+        volumes,
+        load,
+        capacity,
+    } = params;
+
+    let unscheduled_pods = sort_unscheduled_pods(unscheduled_pods);
+    let state: BTreeMap<String, Vec<Pod>> = state.into_iter().map(|(k, v)| (k, v.items)).collect();
+
+    let mut problem = Problem::new(
+        &nodes.items,
+        &unscheduled_pods,
+        &state,
+        &volumes,
+        &capacity,
+        &filters::plugins(profile, &load),
+    );
+    problem
+        .apply_extenders(
+            &extenders::extenders(profile),
+            &nodes.items,
+            &unscheduled_pods,
+        )
+        .await;
+    let initial = problem.assignment_from(&greedy, &unscheduled_pods);
+
+    let args = &profile.annealing;
+    let mut rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let solution = anneal(&problem, initial, args, &mut rng);
+    if solution.timed_out {
+        log::info!(
+            "Annealing exceeded its time budget of {}ms, using the best placement found",
+            args.time_budget_ms
+        );
+    }
+
+    Ok(problem.into_target_state(unscheduled_pods, state, solution.assignment))
+}
+
+struct Solution {
+    assignment: Assignment,
+    timed_out: bool,
+}
+
+fn anneal(
+    problem: &Problem,
+    initial: Assignment,
+    args: &AnnealingArgs,
+    rng: &mut StdRng,
+) -> Solution {
+    let energy = Energy::new(problem, args);
+
+    let mut usage = Usage::of(problem, &initial);
+    let mut current = initial;
+    let mut current_energy = energy.of(&current, &usage.remaining);
+    let mut best = current.clone();
+    let mut best_energy = current_energy;
+
+    // Only pods with at least one feasible node can be moved anywhere
+    let movable: Vec<usize> = (0..problem.items.len())
+        .filter(|&item| !problem.items[item].feasible_bins.is_empty())
+        .collect();
+    if movable.is_empty() {
+        return Solution {
+            assignment: best,
+            timed_out: false,
+        };
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(args.time_budget_ms);
+    let mut temperature = args.initial_temperature;
+    let mut timed_out = false;
+
+    for iteration in 0..args.max_iterations {
+        if iteration % BUDGET_CHECK_INTERVAL == 0 && Instant::now() >= deadline {
+            timed_out = true;
+            break;
+        }
+
+        let item = movable[rng.gen_range(0..movable.len())];
+        let moves = if rng.gen_bool(0.5) {
+            swap(problem, &current, item, rng)
+        } else {
+            relocate(problem, &current, item, rng)
+        };
+
+        if !moves.is_empty() && apply(problem, &mut current, &mut usage, &moves) {
+            let candidate_energy = energy.of(&current, &usage.remaining);
+            let delta = candidate_energy - current_energy;
+            if delta <= 0.0 || rng.gen::<f64>() < (-delta / temperature).exp() {
+                current_energy = candidate_energy;
+                if current_energy < best_energy - EPSILON {
+                    best = current.clone();
+                    best_energy = current_energy;
+                }
+            } else {
+                for &(item, from, _) in &moves {
+                    assign(problem, &mut current, &mut usage, item, from);
+                }
+            }
+        }
+
+        temperature *= args.cooling_rate;
+    }
+
+    Solution {
+        assignment: best,
+        timed_out,
+    }
+}
+
+// Change of the node of a pod of the batch: the pod, its previous node and its new node
+type Move = (usize, Option<usize>, usize);
+
+// Move the pod onto a random other feasible node
+fn relocate(
+    problem: &Problem,
+    assignment: &Assignment,
+    item: usize,
+    rng: &mut StdRng,
+) -> Vec<Move> {
+    let feasible_bins = &problem.items[item].feasible_bins;
+    let bin = feasible_bins[rng.gen_range(0..feasible_bins.len())];
+
+    if assignment[item] == Some(bin) {
+        return vec![];
+    }
+
+    vec![(item, assignment[item], bin)]
+}
+
+// Exchange the nodes of the pod and another random pod of the batch, falling back to
+// moving the pod when it is not placed yet
+fn swap(problem: &Problem, assignment: &Assignment, item: usize, rng: &mut StdRng) -> Vec<Move> {
+    let Some(bin) = assignment[item] else {
+        return relocate(problem, assignment, item, rng);
+    };
+
+    let other = rng.gen_range(0..problem.items.len());
+    let Some(other_bin) = assignment[other] else { return vec![] };
+    if other_bin == bin
+        || !problem.items[item].feasible_bins.contains(&other_bin)
+        || !problem.items[other].feasible_bins.contains(&bin)
+    {
+        return vec![];
+    }
+
+    vec![(item, Some(bin), other_bin), (other, Some(other_bin), bin)]
+}
+
+// Carry out the moves if each pod fits onto its new node once all moved pods left their
+// previous nodes, and leave the assignment unchanged otherwise
+fn apply(
+    problem: &Problem,
+    assignment: &mut Assignment,
+    usage: &mut Usage,
+    moves: &[Move],
+) -> bool {
+    for &(item, _, _) in moves {
+        assign(problem, assignment, usage, item, None);
+    }
+
+    for &(item, _, to) in moves {
+        if !usage.fits(problem, item, to) {
+            for &(item, from, _) in moves {
+                assign(problem, assignment, usage, item, from);
+            }
+            return false;
+        }
+        assign(problem, assignment, usage, item, Some(to));
+    }
+
+    true
+}
+
+fn assign(
+    problem: &Problem,
+    assignment: &mut Assignment,
+    usage: &mut Usage,
+    item: usize,
+    bin: Option<usize>,
+) {
+    if let Some(previous) = assignment[item] {
+        usage.unassign(problem, item, previous);
+    }
+    if let Some(bin) = bin {
+        usage.assign(problem, item, bin);
+    }
+
+    assignment[item] = bin;
+}
+
+// Objective minimized by the annealing, a weighted sum of terms between 0 and 1 plus a
+// penalty for every pod left unscheduled
+struct Energy<'a> {
+    problem: &'a Problem,
+    args: &'a AnnealingArgs,
+    // Topology domain of each bin, if the node carries the topology label
+    domains: Vec<Option<&'a String>>,
+    // Workloads with pods in the batch
+    groups: BTreeSet<&'a String>,
+}
+
+impl<'a> Energy<'a> {
+    fn new(problem: &'a Problem, args: &'a AnnealingArgs) -> Self {
+        Self {
+            problem,
+            args,
+            domains: problem
+                .bins
+                .iter()
+                .map(|bin| bin.labels.get(&args.topology_key))
+                .collect(),
+            groups: problem
+                .items
+                .iter()
+                .filter_map(|item| item.group.as_ref())
+                .collect(),
+        }
+    }
+
+    fn of(&self, assignment: &Assignment, remaining: &[Vec<f64>]) -> f64 {
+        let objective = &self.args.objective;
+
+        let unplaced: i64 = self
+            .problem
+            .items
+            .iter()
+            .zip(assignment)
+            .filter(|(_, bin)| bin.is_none())
+            .map(|(item, _)| item.value)
+            .sum();
+
+        let mut energy = unplaced as f64 * UNPLACED_PENALTY;
+        if objective.active_nodes > 0.0 {
+            energy += objective.active_nodes * self.active_nodes(assignment);
+        }
+        if objective.balanced_utilization > 0.0 {
+            energy += objective.balanced_utilization * self.imbalance(assignment, remaining);
+        }
+        if objective.topology_spread > 0.0 {
+            energy += objective.topology_spread * self.topology_skew(assignment);
+        }
+
+        energy
+    }
+
+    // Fraction of nodes in use
+    fn active_nodes(&self, assignment: &Assignment) -> f64 {
+        if self.problem.bins.is_empty() {
+            return 0.0;
+        }
+
+        let (_, active) = self.problem.objective(assignment);
+
+        active as f64 / self.problem.bins.len() as f64
+    }
+
+    // Standard deviation of the utilization of the resources of a node, averaged over the
+    // nodes in use
+    fn imbalance(&self, assignment: &Assignment, remaining: &[Vec<f64>]) -> f64 {
+        let deviations: Vec<f64> = self
+            .problem
+            .bins
+            .iter()
+            .enumerate()
+            .filter(|(index, bin)| bin.active || assignment.contains(&Some(*index)))
+            .filter_map(|(index, bin)| {
+                let utilizations: Vec<f64> = bin
+                    .capacity
+                    .iter()
+                    .zip(&remaining[index])
+                    .filter(|(capacity, _)| **capacity > 0.0)
+                    .map(|(capacity, remaining)| (capacity - remaining) / capacity)
+                    .collect();
+                if utilizations.is_empty() {
+                    return None;
+                }
+
+                let mean = utilizations.iter().sum::<f64>() / utilizations.len() as f64;
+                let variance = utilizations
+                    .iter()
+                    .map(|utilization| (utilization - mean).powi(2))
+                    .sum::<f64>()
+                    / utilizations.len() as f64;
+
+                Some(variance.sqrt())
+            })
+            .collect();
+
+        if deviations.is_empty() {
+            return 0.0;
+        }
+
+        deviations.iter().sum::<f64>() / deviations.len() as f64
+    }
+
+    // Difference between the most and the least pods of a workload in a topology domain,
+    // relative to the pods of the workload and averaged over the workloads of the batch
+    fn topology_skew(&self, assignment: &Assignment) -> f64 {
+        let all_domains: BTreeSet<&String> = self.domains.iter().flatten().copied().collect();
+        if all_domains.is_empty() || self.groups.is_empty() {
+            return 0.0;
+        }
+
+        let skews: f64 = self
+            .groups
+            .iter()
+            .map(|&group| {
+                let mut counts: BTreeMap<&String, usize> =
+                    all_domains.iter().map(|&domain| (domain, 0)).collect();
+
+                for (bin, domain) in self.problem.bins.iter().zip(&self.domains) {
+                    let Some(domain) = domain else { continue };
+                    if let Some(count) = bin.groups.get(group) {
+                        *counts.entry(domain).or_default() += count;
+                    }
+                }
+                for (item, bin) in self.problem.items.iter().zip(assignment) {
+                    let Some(bin) = bin else { continue };
+                    let Some(domain) = self.domains[*bin] else { continue };
+                    if item.group.as_ref() == Some(group) {
+                        *counts.entry(domain).or_default() += 1;
+                    }
+                }
+
+                let total: usize = counts.values().sum();
+                let max = counts.values().max().copied().unwrap_or_default();
+                let min = counts.values().min().copied().unwrap_or_default();
+                if total == 0 {
+                    return 0.0;
+                }
+
+                (max - min) as f64 / total as f64
+            })
+            .sum();
+
+        skews / self.groups.len() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+
+    use crate::{
+        config::{AnnealingObjective, BinPackingArgs, BinPackingOrdering},
+        scheduler::{
+            capacity::HeldCapacity,
+            testing::{annotated, claim, node, pod, reservation, volume, with_claims, world},
+            volumes::VolumeState,
+        },
+    };
+
+    use super::*;
+
+    fn profile(annealing: AnnealingArgs) -> Profile {
+        Profile {
+            bin_packing: BinPackingArgs {
+                ordering: BinPackingOrdering::Qos,
+            },
+            annealing: AnnealingArgs {
+                seed: Some(7),
+                ..annealing
+            },
+            ..Default::default()
+        }
+    }
+
+    fn used_nodes(target: &TargetState) -> usize {
+        target
+            .state
+            .values()
+            .filter(|node_pods| !node_pods.is_empty())
+            .count()
+    }
+
+    // Greedy placement puts the small pods onto one node and needs a node for each of the
+    // large pods, while moving one small pod next to each large pod frees a node
+    fn mixed_batch() -> WorldState {
+        let allocatable = [("cpu", "10"), ("memory", "10Gi")];
+
+        world(
+            vec![
+                node("a", &allocatable),
+                node("b", &allocatable),
+                node("c", &allocatable),
+            ],
+            vec![
+                pod("small-1", &[("cpu", "2")]),
+                pod("small-2", &[("cpu", "2")]),
+                pod("small-3", &[("cpu", "2")]),
+                pod("large-1", &[("cpu", "6")]),
+                pod("large-2", &[("cpu", "6")]),
+            ],
+        )
+    }
+
+    #[tokio::test]
+    async fn test_schedule_uses_fewer_nodes_than_greedy() {
+        let profile = profile(AnnealingArgs::default());
+
+        let greedy = bin_packing::schedule(mixed_batch(), &profile)
+            .await
+            .unwrap();
+        assert_eq!(used_nodes(&greedy), 3);
+
+        let target = schedule(mixed_batch(), &profile).await.unwrap();
+        assert!(target.unscheduled_pods.is_empty());
+        assert_eq!(used_nodes(&target), 2);
+    }
+
+    #[test]
+    fn test_anneal_is_reproducible_with_seed() {
+        let world = mixed_batch();
+        let pods = world.unscheduled_pods.items;
+        let state: BTreeMap<String, Vec<Pod>> =
+            world.state.into_iter().map(|(k, v)| (k, v.items)).collect();
+        let problem = Problem::new(
+            &world.nodes.items,
+            &pods,
+            &state,
+            &world.volumes,
+            &world.capacity,
+            &[],
+        );
+
+        let args = AnnealingArgs {
+            max_iterations: 500,
+            objective: AnnealingObjective {
+                balanced_utilization: 1.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let run = |seed| {
+            anneal(
+                &problem,
+                vec![None; pods.len()],
+                &args,
+                &mut StdRng::seed_from_u64(seed),
+            )
+            .assignment
+        };
+
+        assert_eq!(run(1), run(1));
+        assert!(run(1).iter().all(Option::is_some));
+    }
+
+    #[tokio::test]
+    async fn test_schedule_spreads_workload_across_zones() {
+        let zone = |name: &str, zone: &str| {
+            let mut node = node(name, &[("cpu", "4"), ("memory", "4Gi")]);
+            node.metadata.labels = Some(BTreeMap::from_iter(vec![(
+                "topology.kubernetes.io/zone".to_string(),
+                zone.to_string(),
+            )]));
+            node
+        };
+        let replica = |name: &str| {
+            let mut pod = pod(name, &[("cpu", "1")]);
+            pod.metadata.owner_references = Some(vec![OwnerReference {
+                controller: Some(true),
+                kind: "ReplicaSet".to_string(),
+                name: "web".to_string(),
+                ..Default::default()
+            }]);
+            pod
+        };
+
+        let target = schedule(
+            world(
+                vec![zone("a", "zone-1"), zone("b", "zone-2")],
+                vec![replica("web-1"), replica("web-2")],
+            ),
+            &profile(AnnealingArgs {
+                objective: AnnealingObjective {
+                    active_nodes: 0.0,
+                    balanced_utilization: 0.0,
+                    topology_spread: 1.0,
+                },
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(target.state["a"].len(), 1);
+        assert_eq!(target.state["b"].len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_shares_bandwidth_within_batch() {
+        // Each pod fits into the bandwidth of the node on its own, but not next to another
+        let streaming = |name| {
+            annotated(
+                pod(name, &[("cpu", "1")]),
+                &[("kubernetes.io/ingress-bandwidth", "600M")],
+            )
+        };
+        let node = annotated(
+            node("a", &[("cpu", "4")]),
+            &[("network.scheduling/ingress-bandwidth", "1G")],
+        );
+
+        let target = schedule(
+            world(
+                vec![node],
+                vec![streaming("one"), streaming("two"), streaming("three")],
+            ),
+            &profile(AnnealingArgs::default()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(target.state["a"].len(), 1);
+        assert_eq!(target.unscheduled_pods.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_places_one_pod_per_exclusive_claim() {
+        let mut world = world(
+            vec![node("a", &[("cpu", "4")]), node("b", &[("cpu", "4")])],
+            vec![
+                with_claims(pod("one", &[("cpu", "1")]), &["exclusive"]),
+                with_claims(pod("two", &[("cpu", "1")]), &["exclusive"]),
+            ],
+        );
+        world.volumes = VolumeState::new(
+            vec![claim("exclusive", "pv-exclusive", "ReadWriteOncePod")],
+            vec![volume("pv-exclusive", None)],
+            vec![],
+            vec![],
+        );
+
+        let target = schedule(world, &profile(AnnealingArgs::default()))
+            .await
+            .unwrap();
+
+        assert_eq!(target.state.values().flatten().count(), 1);
+        assert_eq!(target.unscheduled_pods.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_keeps_reservation_from_batch() {
+        let mut world = world(
+            vec![node("a", &[("cpu", "4")])],
+            vec![
+                pod("one", &[("cpu", "1")]),
+                pod("two", &[("cpu", "1")]),
+                pod("three", &[("cpu", "1")]),
+            ],
+        );
+        world.capacity = HeldCapacity::new(
+            &[],
+            vec![reservation("data", "ingest", "a", &[("cpu", "2")])],
+        );
+
+        let target = schedule(world, &profile(AnnealingArgs::default()))
+            .await
+            .unwrap();
+
+        assert_eq!(target.state["a"].len(), 2);
+        assert_eq!(target.unscheduled_pods.len(), 1);
+    }
+}
@@ -4,134 +4,116 @@ use color_eyre::Result;
 use k8s_openapi::api::core::v1::{Node, Pod};
 use kube_quantity::ParsedQuantity;
 
-use crate::scheduler::{
-    filters::{
-        is_node_schedulable, is_pod_affinity_fulfilled, is_pod_allocatable,
-        is_pod_anti_affinity_fulfilled, is_pod_taint_toleration_fulfilled,
+use crate::{
+    config::{BinPackingOrdering, Profile},
+    scheduler::{
+        algorithms::{plugin_scores, select_node, sort_unscheduled_pods},
+        extenders,
+        filters::{self, feasible_nodes, FilterContext},
+        resources::{average_utilization_after_placement, node_allocatable, pod_requests},
+        scores::{self, ScoreContext, MAX_NODE_SCORE},
+        Reason, TargetState, WorldState,
     },
-    Reason, TargetState, WorldState,
 };
 
-pub(crate) async fn schedule(params: WorldState) -> Result<TargetState> {
+pub(crate) async fn schedule(params: WorldState, profile: &Profile) -> Result<TargetState> {
     let WorldState {
         nodes,
         unscheduled_pods,
         state,
+        volumes,
+        load,
+        capacity,
     } = params;
 
     let mut state: BTreeMap<String, Vec<Pod>> =
         state.into_iter().map(|(k, v)| (k, v.items)).collect();
 
-    // TODO: Sort unscheduled pods
-    // Potentially make use of priority classes here
-    // as of now resort to a pod's QoS
-    // https://kubernetes.io/docs/concepts/scheduling-eviction/node-pressure-eviction/#node-out-of-memory-behavior
-
-    let unscheduled_pods: Vec<Pod> = {
-        let mut unscheduled_pods: Vec<(i32, Pod)> = unscheduled_pods
-            .into_iter()
-            .map(|pod| {
-                if let Some(status) = &pod.status {
-                    match &status.qos_class {
-                        Some(qos_class) if qos_class == "Guaranteed" => (-997, pod),
-                        Some(qos_class) if qos_class == "BestEffort" => (1000, pod),
-                        // TODO: Calculate a score for burstable workloads
-                        Some(qos_class) if qos_class == "Burstable" => (0, pod),
-                        Some(_) => (1000, pod),
-                        None => (1000, pod),
-                    }
-                } else {
-                    // Handle the same as BestEffort pods
-                    (1000, pod)
-                }
-            })
-            .collect();
-        unscheduled_pods.sort_by(|a, b| a.0.cmp(&b.0));
-
-        unscheduled_pods.into_iter().map(|(_, pod)| pod).collect()
+    let unscheduled_pods = match profile.bin_packing.ordering {
+        BinPackingOrdering::Qos => sort_unscheduled_pods(unscheduled_pods),
+        BinPackingOrdering::FirstFitDecreasing | BinPackingOrdering::BestFitDecreasing => {
+            sort_by_dominant_share(sort_unscheduled_pods(unscheduled_pods), &nodes.items)
+        }
     };
 
+    let filter_plugins = filters::plugins(profile, &load);
+    let plugins = scores::plugins(profile, &load);
+    let extenders = extenders::extenders(profile);
+
     let mut newly_unscheduled_pods: Vec<(Pod, Reason)> = vec![];
 
     for pod in unscheduled_pods {
-        // Filter out unfeasible nodes
-        let feasible_nodes: Vec<&Node> = nodes
-            .iter()
-            // Filter schedulable nodes
-            .filter(|node| is_node_schedulable(node))
-            // Filter nodes that have enough allocatable resources for pod
-            .filter(|node| is_pod_allocatable(node, &pod))
-            // Filter nodes fulfilling taint toleration
-            .filter(|node| is_pod_taint_toleration_fulfilled(node, &pod))
-            // Filter nodes fulfilling affinities
-            .filter(|node| is_pod_affinity_fulfilled(node, &pod))
-            // Filter nodes fulfilling anti-affinities
-            .filter(|node| is_pod_anti_affinity_fulfilled(node, &pod))
-            .collect();
+        // Filter out unfeasible nodes, first by the filter pipeline, then by the extenders
+        let ctx = FilterContext {
+            state: &state,
+            volumes: &volumes,
+            capacity: &capacity,
+        };
+        let feasible_nodes = feasible_nodes(&nodes.items, &pod, &ctx, &filter_plugins);
+        let feasible_nodes = match extenders::filter(&extenders, &pod, feasible_nodes).await {
+            Ok(feasible_nodes) => feasible_nodes,
+            Err(err) => {
+                log::warn!("Extender failed to filter nodes: {err}");
+                newly_unscheduled_pods.push((pod, Reason::ExtenderFailed));
+                continue;
+            }
+        };
 
         if feasible_nodes.is_empty() {
             newly_unscheduled_pods.push((pod, Reason::NoFeasibleNode));
             continue;
         }
 
-        // Score nodes
-
         // Score each feasible node based on the following criteria:
-        // - Number of pods on node
-        // - Resource availabilities on node
-        struct ScoreCriteria {
-            number_of_pods: u64,
-            resource_availabilities: BTreeMap<String, ParsedQuantity>,
-        }
-
-        let node_scores: Vec<(&Node, ScoreCriteria)> = feasible_nodes
+        // - Resource utilization on node after placing the pod, preferring fuller nodes
+        // - Weighted scores of the score plugins configured in the profile
+        // - Weighted priorities of the extenders configured in the profile
+        let requests = pod_requests(&pod);
+        let node_scores: Vec<(&Node, f64)> = feasible_nodes
             .into_iter()
             .filter_map(|node| {
-                let Some(node_name) = &node.metadata.name else { return None; };
-                let number_of_pods = state.get(node_name)?.len().try_into().ok()?;
-
-                let Some(node_status) = &node.status else { return None; };
-                let Some(allocatable) = &node_status.allocatable else { return None };
-
-                let resource_availabilities = allocatable
-                    .iter()
-                    .filter_map(|(k, v)| {
-                        let v = v.try_into().ok()?;
-
-                        Some((k.clone(), v))
-                    })
-                    .collect();
-
-                Some((
-                    node,
-                    ScoreCriteria {
-                        number_of_pods,
-                        resource_availabilities,
-                    },
-                ))
+                let node_name = node.metadata.name.as_ref()?;
+
+                Some((node, score(node, state.get(node_name)?, &requests)?))
             })
             .collect();
 
-        // TODO: Normalize scores
+        let node = match profile.bin_packing.ordering {
+            // Take the first node the pod fits onto
+            BinPackingOrdering::FirstFitDecreasing => node_scores.first().map(|(node, _)| *node),
+            // Take the tightest fitting node
+            BinPackingOrdering::Qos | BinPackingOrdering::BestFitDecreasing => {
+                let candidates: Vec<&Node> = node_scores.iter().map(|(node, _)| *node).collect();
+                let plugin_scores = plugin_scores(
+                    &plugins,
+                    &extenders,
+                    &ScoreContext {
+                        nodes: &nodes.items,
+                        state: &state,
+                    },
+                    &pod,
+                    &candidates,
+                )
+                .await;
 
-        // Normalize the scores of each node based on the following criteria:
-        // - Number of pods on node normalized by maximum amount of pods on node
-        // - Resource availabilities on node normalized by maximum resource availabilities on node
+                select_node(node_scores, plugin_scores)
+            }
+        };
 
         // Update state of scheduled pods to nodes
-        let Some((node, _)) = node_scores.get(0) else {
-			newly_unscheduled_pods.push((pod, Reason::NoFeasibleNode));
-			continue;
-		};
+        let Some(node) = node else {
+            newly_unscheduled_pods.push((pod, Reason::NoFeasibleNode));
+            continue;
+        };
         let Some(node_name) = &node.metadata.name else {
-			newly_unscheduled_pods.push((pod, Reason::NodeName));
-			continue;
-		};
+            newly_unscheduled_pods.push((pod, Reason::NodeName));
+            continue;
+        };
         let Some(node_pods) = state.get_mut(node_name) else {
-			newly_unscheduled_pods.push((pod, Reason::NodePods));
-			// TODO: Potentially add a verbose error message
-			continue;
-		};
+            newly_unscheduled_pods.push((pod, Reason::NodePods));
+            // TODO: Potentially add a verbose error message
+            continue;
+        };
         node_pods.push(pod);
     }
 
@@ -140,3 +122,218 @@ pub(crate) async fn schedule(params: WorldState) -> Result<TargetState> {
         state,
     })
 }
+
+// Average utilization of the node once the pod is placed, scaled to 0..=100. Returns None if
+// the pod would not fit onto the node next to its current pods.
+pub(crate) fn score(
+    node: &Node,
+    node_pods: &[Pod],
+    requests: &BTreeMap<String, ParsedQuantity>,
+) -> Option<f64> {
+    let utilization = average_utilization_after_placement(node, node_pods, requests)?;
+
+    Some(utilization * MAX_NODE_SCORE as f64)
+}
+
+// Sort pods by their dominant resource share, i.e. the largest fraction of the cluster's
+// allocatable capacity of any resource they request, largest first. Pods with equal shares
+// keep their relative order.
+fn sort_by_dominant_share(pods: Vec<Pod>, nodes: &[Node]) -> Vec<Pod> {
+    let mut capacity: BTreeMap<String, f64> = BTreeMap::new();
+    for allocatable in nodes.iter().filter_map(node_allocatable) {
+        for (resource_name, quantity) in allocatable {
+            *capacity.entry(resource_name).or_default() +=
+                quantity.to_bytes_f64().unwrap_or_default();
+        }
+    }
+
+    let mut pods: Vec<(f64, Pod)> = pods
+        .into_iter()
+        .map(|pod| {
+            let share = pod_requests(&pod)
+                .iter()
+                .filter_map(|(resource_name, quantity)| {
+                    let capacity = capacity.get(resource_name).filter(|c| **c > 0.0)?;
+
+                    Some(quantity.to_bytes_f64()? / capacity)
+                })
+                .fold(0.0, f64::max);
+
+            (share, pod)
+        })
+        .collect();
+    pods.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    pods.into_iter().map(|(_, pod)| pod).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::{BalancedAllocationArgs, BinPackingArgs, ScorePlugin, ScorePluginConfig},
+        scheduler::testing::{node, pod, world},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_schedule_packs_pods() {
+        let allocatable = [("cpu", "4"), ("memory", "4Gi")];
+        let requests = [("cpu", "1"), ("memory", "1Gi")];
+
+        let target = schedule(
+            world(
+                vec![node("a", &allocatable), node("b", &allocatable)],
+                vec![pod("p1", &requests), pod("p2", &requests)],
+            ),
+            &Profile::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(target.unscheduled_pods.is_empty());
+        assert_eq!(target.state["a"].len(), 2);
+        assert!(target.state["b"].is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_schedule_combines_balanced_allocation() {
+        let world = || {
+            let allocatable = [("cpu", "4"), ("memory", "4Gi")];
+
+            let mut world = world(
+                vec![node("a", &allocatable), node("b", &allocatable)],
+                vec![pod("memory-heavy", &[("cpu", "0"), ("memory", "2Gi")])],
+            );
+            if let Some(pods) = world.state.get_mut("a") {
+                pods.items.push(pod("cpu-hog", &[("cpu", "1")]));
+            }
+            if let Some(pods) = world.state.get_mut("b") {
+                pods.items.push(pod("memory-hog", &[("memory", "2Gi")]));
+            }
+
+            world
+        };
+
+        // Plain bin packing prefers the fuller node b, which would end up memory-full but
+        // CPU-empty
+        let target = schedule(world(), &Profile::default()).await.unwrap();
+        assert_eq!(target.state["b"].len(), 2);
+
+        // A strongly weighted balanced allocation prefers a, whose cpu and memory
+        // utilization stay closer together
+        let profile = Profile {
+            score_plugins: vec![ScorePluginConfig {
+                plugin: ScorePlugin::BalancedAllocation {
+                    args: BalancedAllocationArgs::default(),
+                },
+                weight: 5,
+            }],
+            ..Default::default()
+        };
+
+        let target = schedule(world(), &profile).await.unwrap();
+        assert_eq!(target.state["a"].len(), 2);
+    }
+
+    fn profile(ordering: BinPackingOrdering) -> Profile {
+        Profile {
+            bin_packing: BinPackingArgs { ordering },
+            ..Default::default()
+        }
+    }
+
+    fn used_nodes(target: &TargetState) -> usize {
+        target
+            .state
+            .values()
+            .filter(|node_pods| !node_pods.is_empty())
+            .count()
+    }
+
+    // Small pods arriving first spread the large ones across an additional node unless the
+    // batch is sorted by size
+    fn mixed_batch() -> WorldState {
+        let allocatable = [("cpu", "10"), ("memory", "10Gi")];
+
+        world(
+            vec![
+                node("a", &allocatable),
+                node("b", &allocatable),
+                node("c", &allocatable),
+            ],
+            vec![
+                pod("small-1", &[("cpu", "2")]),
+                pod("small-2", &[("cpu", "2")]),
+                pod("small-3", &[("cpu", "2")]),
+                pod("large-1", &[("cpu", "6")]),
+                pod("large-2", &[("cpu", "6")]),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_sort_by_dominant_share() {
+        let nodes = [node("a", &[("cpu", "4"), ("memory", "4Gi")])];
+        let pods = vec![
+            pod("cpu-small", &[("cpu", "1")]),
+            pod("memory-large", &[("memory", "3Gi")]),
+            pod("cpu-large", &[("cpu", "2")]),
+        ];
+
+        let names: Vec<String> = sort_by_dominant_share(pods, &nodes)
+            .into_iter()
+            .filter_map(|pod| pod.metadata.name)
+            .collect();
+
+        assert_eq!(names, vec!["memory-large", "cpu-large", "cpu-small"]);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_decreasing_orderings_pack_tighter() {
+        let target = schedule(mixed_batch(), &profile(BinPackingOrdering::Qos))
+            .await
+            .unwrap();
+        assert_eq!(used_nodes(&target), 3);
+
+        let target = schedule(
+            mixed_batch(),
+            &profile(BinPackingOrdering::FirstFitDecreasing),
+        )
+        .await
+        .unwrap();
+        assert!(target.unscheduled_pods.is_empty());
+        assert_eq!(used_nodes(&target), 2);
+
+        let target = schedule(
+            mixed_batch(),
+            &profile(BinPackingOrdering::BestFitDecreasing),
+        )
+        .await
+        .unwrap();
+        assert!(target.unscheduled_pods.is_empty());
+        assert_eq!(used_nodes(&target), 2);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_best_fit_picks_tightest_node() {
+        let allocatable = [("cpu", "4"), ("memory", "4Gi")];
+
+        let mut world = world(
+            vec![node("a", &allocatable), node("b", &allocatable)],
+            vec![pod("new", &[("cpu", "1")])],
+        );
+        if let Some(pods) = world.state.get_mut("a") {
+            pods.items.push(pod("existing", &[("cpu", "1")]));
+        }
+        if let Some(pods) = world.state.get_mut("b") {
+            pods.items.push(pod("existing", &[("cpu", "3")]));
+        }
+
+        let target = schedule(world, &profile(BinPackingOrdering::BestFitDecreasing))
+            .await
+            .unwrap();
+
+        assert_eq!(target.state["b"].len(), 2);
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+
+use color_eyre::Result;
+use k8s_openapi::api::core::v1::{Node, Pod};
+use kube_quantity::ParsedQuantity;
+
+use crate::{
+    config::Profile,
+    scheduler::{
+        algorithms::{plugin_scores, select_node, sort_unscheduled_pods},
+        extenders,
+        filters::{self, feasible_nodes, FilterContext},
+        resources::{average_utilization_after_placement, pod_requests},
+        scores::{self, ScoreContext, MAX_NODE_SCORE},
+        Reason, TargetState, WorldState,
+    },
+};
+
+// Spread pods across nodes by placing each pod onto the node with the most remaining
+// capacity after placement
+pub(crate) async fn schedule(params: WorldState, profile: &Profile) -> Result<TargetState> {
+    let WorldState {
+        nodes,
+        unscheduled_pods,
+        state,
+        volumes,
+        load,
+        capacity,
+    } = params;
+
+    let mut state: BTreeMap<String, Vec<Pod>> =
+        state.into_iter().map(|(k, v)| (k, v.items)).collect();
+
+    let unscheduled_pods = sort_unscheduled_pods(unscheduled_pods);
+
+    let filter_plugins = filters::plugins(profile, &load);
+    let plugins = scores::plugins(profile, &load);
+    let extenders = extenders::extenders(profile);
+
+    let mut newly_unscheduled_pods: Vec<(Pod, Reason)> = vec![];
+
+    for pod in unscheduled_pods {
+        // Filter out unfeasible nodes, first by the filter pipeline, then by the extenders
+        let ctx = FilterContext {
+            state: &state,
+            volumes: &volumes,
+            capacity: &capacity,
+        };
+        let feasible_nodes = feasible_nodes(&nodes.items, &pod, &ctx, &filter_plugins);
+        let feasible_nodes = match extenders::filter(&extenders, &pod, feasible_nodes).await {
+            Ok(feasible_nodes) => feasible_nodes,
+            Err(err) => {
+                log::warn!("Extender failed to filter nodes: {err}");
+                newly_unscheduled_pods.push((pod, Reason::ExtenderFailed));
+                continue;
+            }
+        };
+
+        if feasible_nodes.is_empty() {
+            newly_unscheduled_pods.push((pod, Reason::NoFeasibleNode));
+            continue;
+        }
+
+        let requests = pod_requests(&pod);
+
+        // Score nodes by their remaining capacity after placing the pod onto them
+        let node_scores: Vec<(&Node, f64)> = feasible_nodes
+            .into_iter()
+            .filter_map(|node| {
+                let node_name = node.metadata.name.as_ref()?;
+                let node_pods = state.get(node_name)?;
+
+                Some((node, score(node, node_pods, &requests)?))
+            })
+            .collect();
+
+        // Add up the weighted scores of the score plugins and the weighted priorities of
+        // the extenders configured in the profile
+        let candidates: Vec<&Node> = node_scores.iter().map(|(node, _)| *node).collect();
+        let plugin_scores = plugin_scores(
+            &plugins,
+            &extenders,
+            &ScoreContext {
+                nodes: &nodes.items,
+                state: &state,
+            },
+            &pod,
+            &candidates,
+        )
+        .await;
+
+        // Update state of scheduled pods to nodes
+        let Some(node) = select_node(node_scores, plugin_scores) else {
+            newly_unscheduled_pods.push((pod, Reason::NoFeasibleNode));
+            continue;
+        };
+        let Some(node_name) = &node.metadata.name else {
+            newly_unscheduled_pods.push((pod, Reason::NodeName));
+            continue;
+        };
+        let Some(node_pods) = state.get_mut(node_name) else {
+            newly_unscheduled_pods.push((pod, Reason::NodePods));
+            continue;
+        };
+        node_pods.push(pod);
+    }
+
+    Ok(TargetState {
+        unscheduled_pods: newly_unscheduled_pods,
+        state,
+    })
+}
+
+// Average fraction of capacity left on the node once the pod is placed, scaled to 0..=100.
+// Returns None if the pod would not fit onto the node next to its current pods.
+pub(crate) fn score(
+    node: &Node,
+    node_pods: &[Pod],
+    requests: &BTreeMap<String, ParsedQuantity>,
+) -> Option<f64> {
+    let utilization = average_utilization_after_placement(node, node_pods, requests)?;
+
+    Some((1.0 - utilization) * MAX_NODE_SCORE as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scheduler::testing::{node, pod, world};
+
+    use super::*;
+
+    #[test]
+    fn test_score_prefers_emptier_node() {
+        let requests = pod_requests(&pod("new", &[("cpu", "1"), ("memory", "1Gi")]));
+        let allocatable = [("cpu", "4"), ("memory", "4Gi")];
+
+        let empty = score(&node("a", &allocatable), &[], &requests).unwrap();
+        let busy = score(
+            &node("b", &allocatable),
+            &[pod("existing", &[("cpu", "2"), ("memory", "2Gi")])],
+            &requests,
+        )
+        .unwrap();
+
+        assert!(empty > busy);
+        assert_eq!(empty, 75.0);
+    }
+
+    #[test]
+    fn test_score_pod_does_not_fit_next_to_existing_pods() {
+        let requests = pod_requests(&pod("new", &[("cpu", "3")]));
+
+        assert_eq!(
+            score(
+                &node("a", &[("cpu", "4"), ("memory", "4Gi")]),
+                &[pod("existing", &[("cpu", "2")])],
+                &requests
+            ),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_schedule_spreads_pods() {
+        let allocatable = [("cpu", "4"), ("memory", "4Gi")];
+        let requests = [("cpu", "1"), ("memory", "1Gi")];
+
+        let target = schedule(
+            world(
+                vec![node("a", &allocatable), node("b", &allocatable)],
+                vec![pod("p1", &requests), pod("p2", &requests)],
+            ),
+            &Profile::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(target.unscheduled_pods.is_empty());
+        assert_eq!(target.state["a"].len(), 1);
+        assert_eq!(target.state["b"].len(), 1);
+    }
+}
@@ -1 +1,77 @@
+pub(crate) mod annealing;
 pub(crate) mod bin_packing;
+pub(crate) mod least_allocated;
+mod model;
+pub(crate) mod optimal;
+
+pub(crate) use model::clone_world;
+
+use k8s_openapi::api::core::v1::{Node, Pod};
+
+use crate::scheduler::{
+    extenders::{self, Extender},
+    scores::{score_nodes, ScoreContext, WeightedScorePlugin},
+};
+
+// TODO: Sort unscheduled pods
+// Potentially make use of priority classes here
+// as of now resort to a pod's QoS
+// https://kubernetes.io/docs/concepts/scheduling-eviction/node-pressure-eviction/#node-out-of-memory-behavior
+pub(crate) fn sort_unscheduled_pods(unscheduled_pods: impl IntoIterator<Item = Pod>) -> Vec<Pod> {
+    let mut unscheduled_pods: Vec<(i32, Pod)> = unscheduled_pods
+        .into_iter()
+        .map(|pod| {
+            if let Some(status) = &pod.status {
+                match &status.qos_class {
+                    Some(qos_class) if qos_class == "Guaranteed" => (-997, pod),
+                    Some(qos_class) if qos_class == "BestEffort" => (1000, pod),
+                    // TODO: Calculate a score for burstable workloads
+                    Some(qos_class) if qos_class == "Burstable" => (0, pod),
+                    Some(_) => (1000, pod),
+                    None => (1000, pod),
+                }
+            } else {
+                // Handle the same as BestEffort pods
+                (1000, pod)
+            }
+        })
+        .collect();
+    unscheduled_pods.sort_by(|a, b| a.0.cmp(&b.0));
+
+    unscheduled_pods.into_iter().map(|(_, pod)| pod).collect()
+}
+
+// Weighted scores of the score plugins plus the weighted priorities of the extenders for
+// each of the candidate nodes, added to the scores of the algorithm
+pub(crate) async fn plugin_scores(
+    plugins: &[WeightedScorePlugin],
+    extenders: &[Extender],
+    ctx: &ScoreContext<'_>,
+    pod: &Pod,
+    candidates: &[&Node],
+) -> Vec<i64> {
+    let mut plugin_scores = score_nodes(plugins, ctx, pod, candidates);
+    let priorities = extenders::prioritize(extenders, pod, candidates).await;
+    for (score, priority) in plugin_scores.iter_mut().zip(priorities) {
+        *score += priority;
+    }
+
+    plugin_scores
+}
+
+// Pick the first of the nodes with the highest combined algorithm and score plugin score
+pub(crate) fn select_node(
+    node_scores: Vec<(&Node, f64)>,
+    plugin_scores: Vec<i64>,
+) -> Option<&Node> {
+    let mut best: Option<(&Node, f64)> = None;
+    for ((node, score), plugin_score) in node_scores.into_iter().zip(plugin_scores) {
+        let score = score + plugin_score as f64;
+
+        if !matches!(best, Some((_, best_score)) if best_score >= score) {
+            best = Some((node, score));
+        }
+    }
+
+    best.map(|(node, _)| node)
+}
@@ -0,0 +1,728 @@
+// Batch placement problem shared by the algorithms that place a whole batch of pods at once
+// instead of one pod after another
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use k8s_openapi::api::core::v1::{Node, Pod};
+use kube::core::ObjectList;
+
+use crate::scheduler::{
+    bandwidth,
+    capacity::{reservation_workload, HeldCapacity},
+    extenders::{self, Extender},
+    filters::{feasible_nodes, FilterContext, FilterPlugin},
+    resources::{node_allocatable, pod_requests, pods_requests, DEFAULT_RESOURCES},
+    volumes::VolumeState,
+    Reason, TargetState, WorldState,
+};
+
+// Tolerance used when comparing floating point resource quantities
+pub(crate) const EPSILON: f64 = 1e-9;
+
+pub(crate) struct Item {
+    // Resource requests, indexed like the resources collected in Problem::new
+    pub(crate) requests: Vec<f64>,
+    // Contribution to the objective when the pod gets placed
+    pub(crate) value: i64,
+    // Bins the pod passes the filter pipeline and the filtering extenders for
+    pub(crate) feasible_bins: Vec<usize>,
+    // Workload the pod belongs to, used to spread replicas of the same workload
+    pub(crate) group: Option<String>,
+    // Network bandwidth requested in both directions
+    pub(crate) bandwidth: [f64; 2],
+    // Workload the pod takes up the capacity reservations hold for
+    pub(crate) reservation_workload: Option<String>,
+    // CSI volumes the pod attaches to its node, by driver
+    pub(crate) csi_volumes: BTreeMap<String, BTreeSet<String>>,
+    // ReadWriteOncePod claims no other pod may use
+    pub(crate) exclusive_claims: Vec<String>,
+}
+
+pub(crate) struct Bin {
+    pub(crate) name: String,
+    pub(crate) labels: BTreeMap<String, String>,
+    // Allocatable capacity, indexed like Item::requests
+    pub(crate) capacity: Vec<f64>,
+    // Capacity left next to the pods already bound to the node and the capacity held back
+    pub(crate) remaining: Vec<f64>,
+    // Whether pods are bound to the node already
+    pub(crate) active: bool,
+    // Number of pods of each workload already bound to the node
+    pub(crate) groups: BTreeMap<String, usize>,
+    // Network bandwidth left in both directions, unlimited where the node advertises none
+    pub(crate) bandwidth: [f64; 2],
+    // Capacity the reservations on the node still hold for each workload, indexed like
+    // Item::requests. It is held back in remaining and given back to the pods of the workload.
+    pub(crate) reserved: BTreeMap<String, Vec<f64>>,
+    // Volumes the pods bound to the node attach and the number of volumes the drivers
+    // reporting a limit can attach, by CSI driver
+    pub(crate) csi_volumes: BTreeMap<String, BTreeSet<String>>,
+    pub(crate) csi_volume_limits: BTreeMap<String, usize>,
+}
+
+pub(crate) struct Problem {
+    pub(crate) items: Vec<Item>,
+    pub(crate) bins: Vec<Bin>,
+}
+
+// Pod to bin assignment, indexed like Problem::items
+pub(crate) type Assignment = Vec<Option<usize>>;
+
+impl Problem {
+    pub(crate) fn new(
+        nodes: &[Node],
+        pods: &[Pod],
+        state: &BTreeMap<String, Vec<Pod>>,
+        volumes: &VolumeState,
+        capacity: &HeldCapacity,
+        filter_plugins: &[Box<dyn FilterPlugin>],
+    ) -> Self {
+        let requests: Vec<_> = pods.iter().map(pod_requests).collect();
+
+        let mut resources: Vec<String> = DEFAULT_RESOURCES.map(String::from).to_vec();
+        for resource in requests.iter().flat_map(|requests| requests.keys()) {
+            if !resources.contains(resource) {
+                resources.push(resource.clone());
+            }
+        }
+
+        let bins: Vec<(&Node, Bin)> = nodes
+            .iter()
+            .filter_map(|node| {
+                let name = node.metadata.name.clone()?;
+                let node_pods = state.get(&name)?;
+                let allocatable = node_allocatable(node)?;
+                let requested = pods_requests(node_pods);
+                let headroom = capacity.headroom(node);
+                let reserved: BTreeMap<String, Vec<f64>> = capacity
+                    .reserved(node, node_pods)
+                    .into_iter()
+                    .map(|(workload, left)| {
+                        let left = resources
+                            .iter()
+                            .map(|resource| left.get(resource).copied().unwrap_or_default())
+                            .collect();
+                        (workload, left)
+                    })
+                    .collect();
+
+                let capacity: Vec<f64> = resources
+                    .iter()
+                    .map(|resource| {
+                        allocatable
+                            .get(resource)
+                            .and_then(|quantity| quantity.to_bytes_f64())
+                            .unwrap_or_default()
+                    })
+                    .collect();
+                let remaining = resources
+                    .iter()
+                    .zip(&capacity)
+                    .enumerate()
+                    .map(|(index, (resource, capacity))| {
+                        let requested = requested
+                            .get(resource)
+                            .and_then(|quantity| quantity.to_bytes_f64())
+                            .unwrap_or_default();
+                        let reserved: f64 = reserved.values().map(|left| left[index]).sum();
+
+                        capacity
+                            - requested
+                            - headroom.get(resource).copied().unwrap_or_default()
+                            - reserved
+                    })
+                    .collect();
+
+                let mut csi_volumes: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+                for (driver, node_pod_volumes) in node_pods
+                    .iter()
+                    .flat_map(|node_pod| volumes.csi_volumes(node_pod))
+                {
+                    csi_volumes
+                        .entry(driver)
+                        .or_default()
+                        .extend(node_pod_volumes);
+                }
+
+                let csi_volume_limits = volumes.csi_volume_limits(&name);
+
+                let mut groups: BTreeMap<String, usize> = BTreeMap::new();
+                for group in node_pods.iter().filter_map(group) {
+                    *groups.entry(group).or_default() += 1;
+                }
+
+                Some((
+                    node,
+                    Bin {
+                        name,
+                        labels: node.metadata.labels.clone().unwrap_or_default(),
+                        capacity,
+                        remaining,
+                        active: !node_pods.is_empty(),
+                        groups,
+                        bandwidth: bandwidth::left(node, node_pods),
+                        reserved,
+                        csi_volumes,
+                        csi_volume_limits,
+                    },
+                ))
+            })
+            .collect();
+
+        let min_priority = pods.iter().map(priority).min().unwrap_or_default();
+
+        let ctx = FilterContext {
+            state,
+            volumes,
+            capacity,
+        };
+
+        let items = pods
+            .iter()
+            .zip(requests)
+            .map(|(pod, requests)| {
+                let feasible = feasible_nodes(nodes, pod, &ctx, filter_plugins);
+
+                Item {
+                    requests: resources
+                        .iter()
+                        .map(|resource| {
+                            requests
+                                .get(resource)
+                                .and_then(|quantity| quantity.to_bytes_f64())
+                                .unwrap_or_default()
+                        })
+                        .collect(),
+                    value: priority(pod) - min_priority + 1,
+                    feasible_bins: bins
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, (node, _))| {
+                            feasible
+                                .iter()
+                                .any(|feasible| std::ptr::eq(*feasible, *node))
+                        })
+                        .map(|(index, _)| index)
+                        .collect(),
+                    group: group(pod),
+                    bandwidth: bandwidth::requested(pod),
+                    reservation_workload: reservation_workload(pod),
+                    csi_volumes: volumes.csi_volumes(pod),
+                    exclusive_claims: volumes.exclusive_claims(pod),
+                }
+            })
+            .collect();
+
+        Self {
+            items,
+            bins: bins.into_iter().map(|(_, bin)| bin).collect(),
+        }
+    }
+
+    // Narrow the feasible bins of each item down to the nodes the filtering extenders accept
+    // for its pod. Items an extender failed to filter the nodes for are left without feasible
+    // bins, as the greedy algorithms leave their pods unscheduled.
+    pub(crate) async fn apply_extenders(
+        &mut self,
+        extenders: &[Extender],
+        nodes: &[Node],
+        pods: &[Pod],
+    ) {
+        for (item, pod) in self.items.iter_mut().zip(pods) {
+            let candidates: Vec<&Node> = item
+                .feasible_bins
+                .iter()
+                .filter_map(|bin| {
+                    nodes
+                        .iter()
+                        .find(|node| node.metadata.name.as_ref() == Some(&self.bins[*bin].name))
+                })
+                .collect();
+
+            let accepted: Vec<String> = match extenders::filter(extenders, pod, candidates).await {
+                Ok(accepted) => accepted
+                    .into_iter()
+                    .filter_map(|node| node.metadata.name.clone())
+                    .collect(),
+                Err(err) => {
+                    log::warn!("Extender failed to filter nodes: {err}");
+                    vec![]
+                }
+            };
+
+            item.feasible_bins
+                .retain(|bin| accepted.contains(&self.bins[*bin].name));
+        }
+    }
+
+    // Translate the placement of another algorithm into an assignment of this problem
+    pub(crate) fn assignment_from(&self, target: &TargetState, pods: &[Pod]) -> Assignment {
+        pods.iter()
+            .map(|pod| {
+                let (node_name, _) = target.state.iter().find(|(_, node_pods)| {
+                    node_pods.iter().any(|node_pod| {
+                        node_pod.metadata.namespace == pod.metadata.namespace
+                            && node_pod.metadata.name == pod.metadata.name
+                    })
+                })?;
+
+                self.bins.iter().position(|bin| &bin.name == node_name)
+            })
+            .collect()
+    }
+
+    // Packed value and number of nodes in use of an assignment
+    pub(crate) fn objective(&self, assignment: &Assignment) -> (i64, usize) {
+        let value = assignment
+            .iter()
+            .zip(&self.items)
+            .filter(|(bin, _)| bin.is_some())
+            .map(|(_, item)| item.value)
+            .sum();
+        let active = self
+            .bins
+            .iter()
+            .enumerate()
+            .filter(|(index, bin)| bin.active || assignment.contains(&Some(*index)))
+            .count();
+
+        (value, active)
+    }
+
+    // Build the target state by adding the assigned pods to the pods bound to each node
+    pub(crate) fn into_target_state(
+        self,
+        pods: Vec<Pod>,
+        mut state: BTreeMap<String, Vec<Pod>>,
+        assignment: Assignment,
+    ) -> TargetState {
+        let mut unscheduled_pods: Vec<(Pod, Reason)> = vec![];
+        for (pod, bin) in pods.into_iter().zip(assignment) {
+            let Some(bin) = bin else {
+                unscheduled_pods.push((pod, Reason::NoFeasibleNode));
+                continue;
+            };
+            let Some(node_pods) = state.get_mut(&self.bins[bin].name) else {
+                unscheduled_pods.push((pod, Reason::NodePods));
+                continue;
+            };
+            node_pods.push(pod);
+        }
+
+        TargetState {
+            unscheduled_pods,
+            state,
+        }
+    }
+}
+
+// What the items assigned so far take up of the bins and of the cluster. The filter pipeline
+// only checked the feasible bins of each item against the pods bound before the batch, so
+// next to the resource requests the usage checks the constraints depending on the other pods
+// of the batch: network bandwidth, the capacity reservations hold, the attach limits of CSI
+// drivers and ReadWriteOncePod claims.
+#[derive(Clone)]
+pub(crate) struct Usage {
+    // Capacity left on each bin, indexed like Bin::remaining
+    pub(crate) remaining: Vec<Vec<f64>>,
+    // Network bandwidth left on each bin
+    bandwidth: Vec<[f64; 2]>,
+    // Requests of the assigned items of each reserved workload on each bin
+    taken_up: Vec<BTreeMap<String, Vec<f64>>>,
+    // Number of assigned items on each bin attaching each volume the bin does not attach
+    // already, by CSI driver
+    attaching: Vec<BTreeMap<String, BTreeMap<String, usize>>>,
+    // Number of assigned items using each ReadWriteOncePod claim
+    claimed: BTreeMap<String, usize>,
+}
+
+impl Usage {
+    pub(crate) fn new(problem: &Problem) -> Self {
+        Self {
+            remaining: problem
+                .bins
+                .iter()
+                .map(|bin| bin.remaining.clone())
+                .collect(),
+            bandwidth: problem.bins.iter().map(|bin| bin.bandwidth).collect(),
+            taken_up: vec![BTreeMap::new(); problem.bins.len()],
+            attaching: vec![BTreeMap::new(); problem.bins.len()],
+            claimed: BTreeMap::new(),
+        }
+    }
+
+    // Usage once the assigned items are placed
+    pub(crate) fn of(problem: &Problem, assignment: &Assignment) -> Self {
+        let mut usage = Self::new(problem);
+        for (item, bin) in assignment.iter().enumerate() {
+            if let Some(bin) = bin {
+                usage.assign(problem, item, *bin);
+            }
+        }
+
+        usage
+    }
+
+    // Whether the item can be placed onto the bin next to the items assigned so far
+    pub(crate) fn fits(&self, problem: &Problem, item: usize, bin: usize) -> bool {
+        let demand = self.demand(problem, item, bin);
+        let item = &problem.items[item];
+        let target = &problem.bins[bin];
+
+        let resources = demand
+            .iter()
+            .zip(&self.remaining[bin])
+            .all(|(demand, remaining)| *demand <= remaining + EPSILON);
+        // Only the directions the pod requests bandwidth in are limited
+        let bandwidth = item
+            .bandwidth
+            .iter()
+            .zip(&self.bandwidth[bin])
+            .all(|(requested, left)| *requested <= 0.0 || *requested <= left + EPSILON);
+        let csi_volumes = item.csi_volumes.iter().all(|(driver, volumes)| {
+            let Some(limit) = target.csi_volume_limits.get(driver) else { return true };
+            let attached = target.csi_volumes.get(driver);
+            let attaching = self.attaching[bin].get(driver);
+
+            let new = volumes
+                .iter()
+                .filter(|volume| {
+                    !matches!(attached, Some(attached) if attached.contains(*volume))
+                        && !matches!(attaching, Some(attaching) if attaching.contains_key(*volume))
+                })
+                .count();
+
+            attached.map(BTreeSet::len).unwrap_or_default()
+                + attaching.map(BTreeMap::len).unwrap_or_default()
+                + new
+                <= *limit
+        });
+        let claims = item
+            .exclusive_claims
+            .iter()
+            .all(|claim| !self.claimed.contains_key(claim));
+
+        resources && bandwidth && csi_volumes && claims
+    }
+
+    pub(crate) fn assign(&mut self, problem: &Problem, item: usize, bin: usize) {
+        let demand = self.demand(problem, item, bin);
+        for (remaining, demand) in self.remaining[bin].iter_mut().zip(demand) {
+            *remaining -= demand;
+        }
+
+        let item = &problem.items[item];
+        for (left, requested) in self.bandwidth[bin].iter_mut().zip(item.bandwidth) {
+            *left -= requested;
+        }
+        if let Some(taken_up) = self.taken_up(problem, item, bin) {
+            for (taken_up, request) in taken_up.iter_mut().zip(&item.requests) {
+                *taken_up += request;
+            }
+        }
+        for (driver, volume) in new_volumes(item, &problem.bins[bin]) {
+            *self.attaching[bin]
+                .entry(driver)
+                .or_default()
+                .entry(volume)
+                .or_default() += 1;
+        }
+        for claim in &item.exclusive_claims {
+            *self.claimed.entry(claim.clone()).or_default() += 1;
+        }
+    }
+
+    pub(crate) fn unassign(&mut self, problem: &Problem, item_index: usize, bin: usize) {
+        let item = &problem.items[item_index];
+        for (left, requested) in self.bandwidth[bin].iter_mut().zip(item.bandwidth) {
+            *left += requested;
+        }
+        if let Some(taken_up) = self.taken_up(problem, item, bin) {
+            for (taken_up, request) in taken_up.iter_mut().zip(&item.requests) {
+                *taken_up -= request;
+            }
+        }
+        for (driver, volume) in new_volumes(item, &problem.bins[bin]) {
+            let Some(attaching) = self.attaching[bin].get_mut(&driver) else { continue };
+            if let Some(count) = attaching.get_mut(&volume) {
+                *count -= 1;
+                if *count == 0 {
+                    attaching.remove(&volume);
+                }
+            }
+        }
+        for claim in &item.exclusive_claims {
+            if let Some(count) = self.claimed.get_mut(claim) {
+                *count -= 1;
+                if *count == 0 {
+                    self.claimed.remove(claim);
+                }
+            }
+        }
+
+        // The demand of the item is measured once the item is gone, like when it was assigned
+        let demand = self.demand(problem, item_index, bin);
+        for (remaining, demand) in self.remaining[bin].iter_mut().zip(demand) {
+            *remaining += demand;
+        }
+    }
+
+    // Whether two bins without items assigned accept the same items in the same way
+    pub(crate) fn interchangeable(&self, problem: &Problem, a: usize, b: usize) -> bool {
+        let (bin_a, bin_b) = (&problem.bins[a], &problem.bins[b]);
+
+        self.remaining[a] == self.remaining[b]
+            && self.bandwidth[a] == self.bandwidth[b]
+            && bin_a.reserved.is_empty()
+            && bin_b.reserved.is_empty()
+            && bin_a.csi_volumes == bin_b.csi_volumes
+            && bin_a.csi_volume_limits == bin_b.csi_volume_limits
+    }
+
+    // Capacity the item takes up of the remaining capacity of the bin. Pods of a workload
+    // reservations hold capacity for on the node first take up what the reservations hold.
+    fn demand(&self, problem: &Problem, item: usize, bin: usize) -> Vec<f64> {
+        let item = &problem.items[item];
+        let Some(reserved) = item
+            .reservation_workload
+            .as_ref()
+            .and_then(|workload| problem.bins[bin].reserved.get(workload))
+        else {
+            return item.requests.clone();
+        };
+        let taken_up = item
+            .reservation_workload
+            .as_ref()
+            .and_then(|workload| self.taken_up[bin].get(workload));
+
+        item.requests
+            .iter()
+            .zip(reserved)
+            .enumerate()
+            .map(|(index, (request, reserved))| {
+                let taken_up = taken_up.map(|taken_up| taken_up[index]).unwrap_or_default();
+
+                (taken_up + request - reserved).max(0.0) - (taken_up - reserved).max(0.0)
+            })
+            .collect()
+    }
+
+    fn taken_up(&mut self, problem: &Problem, item: &Item, bin: usize) -> Option<&mut Vec<f64>> {
+        let workload = item.reservation_workload.as_ref()?;
+        problem.bins[bin].reserved.get(workload)?;
+
+        Some(
+            self.taken_up[bin]
+                .entry(workload.clone())
+                .or_insert_with(|| vec![0.0; item.requests.len()]),
+        )
+    }
+}
+
+pub(crate) fn clone_world(world: &WorldState) -> WorldState {
+    fn clone_list<T: Clone>(list: &ObjectList<T>) -> ObjectList<T> {
+        ObjectList {
+            metadata: list.metadata.clone(),
+            items: list.items.clone(),
+        }
+    }
+
+    WorldState {
+        nodes: clone_list(&world.nodes),
+        unscheduled_pods: clone_list(&world.unscheduled_pods),
+        state: world
+            .state
+            .iter()
+            .map(|(k, v)| (k.clone(), clone_list(v)))
+            .collect(),
+        volumes: world.volumes.clone(),
+        load: world.load.clone(),
+        capacity: world.capacity.clone(),
+    }
+}
+
+// CSI volumes of the item the bin does not attach already
+fn new_volumes(item: &Item, bin: &Bin) -> Vec<(String, String)> {
+    item.csi_volumes
+        .iter()
+        .flat_map(|(driver, volumes)| {
+            let attached = bin.csi_volumes.get(driver);
+
+            volumes
+                .iter()
+                .filter(
+                    move |volume| !matches!(attached, Some(attached) if attached.contains(*volume)),
+                )
+                .map(|volume| (driver.clone(), volume.clone()))
+        })
+        .collect()
+}
+
+fn priority(pod: &Pod) -> i64 {
+    pod.spec
+        .as_ref()
+        .and_then(|spec| spec.priority)
+        .unwrap_or_default()
+        .into()
+}
+
+// Workload of a pod, identified by its controlling owner
+fn group(pod: &Pod) -> Option<String> {
+    let owner = pod
+        .metadata
+        .owner_references
+        .as_ref()?
+        .iter()
+        .find(|owner| owner.controller.unwrap_or(false))?;
+
+    Some(format!(
+        "{}/{}/{}",
+        pod.metadata.namespace.as_deref().unwrap_or_default(),
+        owner.kind,
+        owner.name
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scheduler::testing::{
+        annotated, claim, csi_node, in_workload, node, pod, reservation, volume, with_claims,
+    };
+
+    use super::*;
+
+    fn problem(
+        nodes: &[Node],
+        pods: &[Pod],
+        volumes: &VolumeState,
+        capacity: &HeldCapacity,
+    ) -> Problem {
+        let state = nodes
+            .iter()
+            .filter_map(|node| Some((node.metadata.name.clone()?, vec![])))
+            .collect();
+
+        Problem::new(nodes, pods, &state, volumes, capacity, &[])
+    }
+
+    #[test]
+    fn test_usage_limits_bandwidth() {
+        let nodes = [annotated(
+            node("a", &[("cpu", "4")]),
+            &[("network.scheduling/ingress-bandwidth", "1G")],
+        )];
+        let streaming = |name| {
+            annotated(
+                pod(name, &[]),
+                &[("kubernetes.io/ingress-bandwidth", "600M")],
+            )
+        };
+        let pods = [streaming("one"), streaming("two"), pod("quiet", &[])];
+        let problem = problem(
+            &nodes,
+            &pods,
+            &VolumeState::default(),
+            &HeldCapacity::default(),
+        );
+
+        let mut usage = Usage::new(&problem);
+        assert!(usage.fits(&problem, 0, 0));
+        usage.assign(&problem, 0, 0);
+        assert!(!usage.fits(&problem, 1, 0));
+        assert!(usage.fits(&problem, 2, 0));
+
+        usage.unassign(&problem, 0, 0);
+        assert!(usage.fits(&problem, 1, 0));
+    }
+
+    #[test]
+    fn test_usage_holds_reservations() {
+        let nodes = [node("a", &[("cpu", "4")])];
+        let pods = [
+            pod("other-1", &[("cpu", "1")]),
+            pod("other-2", &[("cpu", "1")]),
+            pod("other-3", &[("cpu", "1")]),
+            in_workload(pod("ingest-1", &[("cpu", "2")]), "data", "ingest"),
+            in_workload(pod("ingest-2", &[("cpu", "3")]), "data", "ingest"),
+        ];
+        let capacity = HeldCapacity::new(
+            &[],
+            vec![reservation("data", "ingest", "a", &[("cpu", "2")])],
+        );
+        let problem = problem(&nodes, &pods, &VolumeState::default(), &capacity);
+
+        // Other pods cannot take up the reservation between them
+        let mut usage = Usage::new(&problem);
+        usage.assign(&problem, 0, 0);
+        usage.assign(&problem, 1, 0);
+        assert!(!usage.fits(&problem, 2, 0));
+
+        // Pods of the workload take up the reservation first
+        assert!(usage.fits(&problem, 3, 0));
+        assert!(!usage.fits(&problem, 4, 0));
+        usage.unassign(&problem, 1, 0);
+        assert!(usage.fits(&problem, 4, 0));
+        usage.assign(&problem, 4, 0);
+        assert!(!usage.fits(&problem, 1, 0));
+
+        usage.unassign(&problem, 4, 0);
+        usage.unassign(&problem, 0, 0);
+        assert_eq!(usage.remaining, Usage::new(&problem).remaining);
+    }
+
+    #[test]
+    fn test_usage_limits_csi_volumes() {
+        let nodes = [node("a", &[("cpu", "4")])];
+        let pods = [
+            with_claims(pod("one", &[]), &["one"]),
+            with_claims(pod("two", &[]), &["two"]),
+            with_claims(pod("sharing", &[]), &["one"]),
+        ];
+        let volumes = VolumeState::new(
+            vec![
+                claim("one", "pv-one", "ReadWriteMany"),
+                claim("two", "pv-two", "ReadWriteMany"),
+            ],
+            vec![volume("pv-one", None), volume("pv-two", None)],
+            vec![],
+            vec![csi_node("a", 1)],
+        );
+        let problem = problem(&nodes, &pods, &volumes, &HeldCapacity::default());
+
+        let mut usage = Usage::new(&problem);
+        usage.assign(&problem, 0, 0);
+        assert!(!usage.fits(&problem, 1, 0));
+        // The volume attached for another pod of the batch does not count twice
+        assert!(usage.fits(&problem, 2, 0));
+
+        usage.assign(&problem, 2, 0);
+        usage.unassign(&problem, 0, 0);
+        assert!(!usage.fits(&problem, 1, 0));
+        usage.unassign(&problem, 2, 0);
+        assert!(usage.fits(&problem, 1, 0));
+    }
+
+    #[test]
+    fn test_usage_claims_exclusive_volumes() {
+        let nodes = [node("a", &[("cpu", "4")]), node("b", &[("cpu", "4")])];
+        let pods = [
+            with_claims(pod("one", &[]), &["exclusive"]),
+            with_claims(pod("two", &[]), &["exclusive"]),
+        ];
+        let volumes = VolumeState::new(
+            vec![claim("exclusive", "pv-exclusive", "ReadWriteOncePod")],
+            vec![volume("pv-exclusive", None)],
+            vec![],
+            vec![],
+        );
+        let problem = problem(&nodes, &pods, &volumes, &HeldCapacity::default());
+
+        let mut usage = Usage::new(&problem);
+        usage.assign(&problem, 0, 0);
+        assert!(!usage.fits(&problem, 1, 0));
+        assert!(!usage.fits(&problem, 1, 1));
+
+        usage.unassign(&problem, 0, 0);
+        assert!(usage.fits(&problem, 1, 1));
+    }
+}
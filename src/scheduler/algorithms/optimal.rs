@@ -0,0 +1,407 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use color_eyre::Result;
+use k8s_openapi::api::core::v1::Pod;
+
+use crate::{
+    config::Profile,
+    scheduler::{
+        algorithms::{
+            bin_packing,
+            model::{clone_world, Assignment, Item, Problem, Usage},
+            sort_unscheduled_pods,
+        },
+        extenders, filters, TargetState, WorldState,
+    },
+};
+
+// Number of search nodes expanded between two checks of the time budget
+const BUDGET_CHECK_INTERVAL: u64 = 1024;
+
+// Place the whole batch of unscheduled pods at once by solving the multi-dimensional bin
+// packing problem with a branch and bound search. Solutions are compared by the packed
+// priority first and by the number of nodes in use second. The search starts from the
+// greedy bin packing placement and only replaces it by strictly better solutions, so when
+// the time budget expires the greedy placement, or the best improvement found so far, is
+// used.
+pub(crate) async fn schedule(params: WorldState, profile: &Profile) -> Result<TargetState> {
+    let greedy = bin_packing::schedule(clone_world(&params), profile).await?;
+
+    let WorldState {
+        nodes,
+        unscheduled_pods,
+        state,
+        volumes,
+        load,
+        capacity,
+    } = params;
+
+    let unscheduled_pods = sort_unscheduled_pods(unscheduled_pods);
+    let state: BTreeMap<String, Vec<Pod>> = state.into_iter().map(|(k, v)| (k, v.items)).collect();
+
+    let mut problem = Problem::new(
+        &nodes.items,
+        &unscheduled_pods,
+        &state,
+        &volumes,
+        &capacity,
+        &filters::plugins(profile, &load),
+    );
+    problem
+        .apply_extenders(
+            &extenders::extenders(profile),
+            &nodes.items,
+            &unscheduled_pods,
+        )
+        .await;
+    let incumbent = problem.assignment_from(&greedy, &unscheduled_pods);
+
+    let time_budget = Duration::from_millis(profile.optimal.time_budget_ms);
+    let solution = solve(&problem, incumbent, time_budget);
+    if solution.timed_out {
+        log::info!(
+            "Optimal solver exceeded its time budget of {time_budget:?}, using the best placement found"
+        );
+    }
+
+    Ok(problem.into_target_state(unscheduled_pods, state, solution.assignment))
+}
+
+struct Solution {
+    assignment: Assignment,
+    timed_out: bool,
+}
+
+fn solve(problem: &Problem, incumbent: Assignment, time_budget: Duration) -> Solution {
+    // Search the items with the highest value and the largest requests first, as they
+    // are the hardest to place and constrain the search the most
+    let mut order: Vec<usize> = (0..problem.items.len()).collect();
+    order.sort_by(|&a, &b| {
+        let size = |item: &Item| item.requests.iter().sum::<f64>();
+
+        problem.items[b]
+            .value
+            .cmp(&problem.items[a].value)
+            .then(size(&problem.items[b]).total_cmp(&size(&problem.items[a])))
+    });
+
+    // Highest value that can still be gained from the items at and after each position
+    let mut remaining_value = vec![0; order.len() + 1];
+    for position in (0..order.len()).rev() {
+        remaining_value[position] =
+            remaining_value[position + 1] + problem.items[order[position]].value;
+    }
+
+    let (best_value, best_active) = problem.objective(&incumbent);
+    let mut search = Search {
+        problem,
+        order,
+        remaining_value,
+        usage: Usage::new(problem),
+        load: vec![0; problem.bins.len()],
+        current: vec![None; problem.items.len()],
+        best: incumbent,
+        best_value,
+        best_active,
+        deadline: Instant::now() + time_budget,
+        expanded: 0,
+        timed_out: false,
+    };
+    let active = problem.bins.iter().filter(|bin| bin.active).count();
+    search.branch(0, 0, active);
+
+    Solution {
+        assignment: search.best,
+        timed_out: search.timed_out,
+    }
+}
+
+struct Search<'a> {
+    problem: &'a Problem,
+    order: Vec<usize>,
+    remaining_value: Vec<i64>,
+    usage: Usage,
+    // Number of batch items assigned to each bin
+    load: Vec<usize>,
+    current: Assignment,
+    best: Assignment,
+    best_value: i64,
+    best_active: usize,
+    deadline: Instant,
+    expanded: u64,
+    timed_out: bool,
+}
+
+impl Search<'_> {
+    fn branch(&mut self, position: usize, value: i64, active: usize) {
+        if self.timed_out {
+            return;
+        }
+        self.expanded += 1;
+        if self.expanded % BUDGET_CHECK_INTERVAL == 1 && Instant::now() >= self.deadline {
+            self.timed_out = true;
+            return;
+        }
+
+        // Prune branches that cannot beat the best solution found so far. The number of
+        // nodes in use never decreases further down a branch.
+        let bound = value + self.remaining_value[position];
+        if bound < self.best_value || (bound == self.best_value && active >= self.best_active) {
+            return;
+        }
+
+        let Some(&item_index) = self.order.get(position) else {
+            self.best = self.current.clone();
+            self.best_value = value;
+            self.best_active = active;
+            return;
+        };
+        let item = &self.problem.items[item_index];
+
+        // Prefer bins that are in use already, as opening a new one worsens the objective
+        let mut bins = item.feasible_bins.clone();
+        bins.sort_by_key(|&bin| !self.is_active(bin));
+
+        let mut tried_empty: Vec<usize> = vec![];
+        for bin in bins {
+            if !self.usage.fits(self.problem, item_index, bin) {
+                continue;
+            }
+
+            // Empty bins with the same remaining capacity are interchangeable
+            let opens_bin = !self.is_active(bin);
+            if opens_bin {
+                if tried_empty
+                    .iter()
+                    .any(|&tried| self.usage.interchangeable(self.problem, tried, bin))
+                {
+                    continue;
+                }
+                tried_empty.push(bin);
+            }
+
+            self.assign(item_index, bin);
+            self.branch(
+                position + 1,
+                value + item.value,
+                active + usize::from(opens_bin),
+            );
+            self.unassign(item_index, bin);
+
+            if self.timed_out {
+                return;
+            }
+        }
+
+        // Leave the pod unscheduled
+        self.branch(position + 1, value, active);
+    }
+
+    fn is_active(&self, bin: usize) -> bool {
+        self.problem.bins[bin].active || self.load[bin] > 0
+    }
+
+    fn assign(&mut self, item_index: usize, bin: usize) {
+        self.usage.assign(self.problem, item_index, bin);
+        self.load[bin] += 1;
+        self.current[item_index] = Some(bin);
+    }
+
+    fn unassign(&mut self, item_index: usize, bin: usize) {
+        self.usage.unassign(self.problem, item_index, bin);
+        self.load[bin] -= 1;
+        self.current[item_index] = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scheduler::{
+        capacity::HeldCapacity,
+        testing::{annotated, claim, csi_node, node, pod, reservation, volume, with_claims, world},
+        volumes::VolumeState,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_schedule_packs_batch_tighter_than_greedy() {
+        // Greedy placement puts the 2 cpu pods onto separate nodes first and then has to
+        // open a third node for the 3 cpu pods, while 2 + 3 fit onto one node each
+        let allocatable = [("cpu", "5"), ("memory", "10Gi")];
+
+        let target = schedule(
+            world(
+                vec![
+                    node("a", &allocatable),
+                    node("b", &allocatable),
+                    node("c", &allocatable),
+                ],
+                vec![
+                    pod("small-1", &[("cpu", "2")]),
+                    pod("small-2", &[("cpu", "2")]),
+                    pod("large-1", &[("cpu", "3")]),
+                    pod("large-2", &[("cpu", "3")]),
+                ],
+            ),
+            &Profile::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(target.unscheduled_pods.is_empty());
+        let used_nodes = target
+            .state
+            .values()
+            .filter(|node_pods| !node_pods.is_empty())
+            .count();
+        assert_eq!(used_nodes, 2);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_prefers_higher_priority() {
+        let mut low = pod("low", &[("cpu", "2")]);
+        let mut high = pod("high", &[("cpu", "2")]);
+        if let Some(spec) = low.spec.as_mut() {
+            spec.priority = Some(0);
+        }
+        if let Some(spec) = high.spec.as_mut() {
+            spec.priority = Some(1000);
+        }
+
+        let target = schedule(
+            world(vec![node("a", &[("cpu", "3")])], vec![low, high]),
+            &Profile::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(target.state["a"].len(), 1);
+        assert_eq!(target.state["a"][0].metadata.name.as_deref(), Some("high"));
+        assert_eq!(target.unscheduled_pods.len(), 1);
+    }
+
+    #[test]
+    fn test_solve_expired_budget_keeps_incumbent() {
+        let nodes = [node("a", &[("cpu", "4")])];
+        let pods = [pod("p", &[("cpu", "1")])];
+        let state = BTreeMap::from_iter(vec![("a".to_string(), vec![])]);
+
+        let problem = Problem::new(
+            &nodes,
+            &pods,
+            &state,
+            &VolumeState::default(),
+            &HeldCapacity::default(),
+            &[],
+        );
+
+        let solution = solve(&problem, vec![None], Duration::ZERO);
+        assert!(solution.timed_out);
+        assert_eq!(solution.assignment, vec![None]);
+
+        let solution = solve(&problem, vec![None], Duration::from_secs(1));
+        assert!(!solution.timed_out);
+        assert_eq!(solution.assignment, vec![Some(0)]);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_shares_bandwidth_within_batch() {
+        // Each pod fits into the bandwidth of the node on its own, but not next to another
+        let streaming = |name| {
+            annotated(
+                pod(name, &[("cpu", "1")]),
+                &[("kubernetes.io/ingress-bandwidth", "600M")],
+            )
+        };
+        let node = annotated(
+            node("a", &[("cpu", "4")]),
+            &[("network.scheduling/ingress-bandwidth", "1G")],
+        );
+
+        let target = schedule(
+            world(
+                vec![node],
+                vec![streaming("one"), streaming("two"), streaming("three")],
+            ),
+            &Profile::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(target.state["a"].len(), 1);
+        assert_eq!(target.unscheduled_pods.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_keeps_reservation_from_batch() {
+        let mut world = world(
+            vec![node("a", &[("cpu", "4")])],
+            vec![
+                pod("one", &[("cpu", "1")]),
+                pod("two", &[("cpu", "1")]),
+                pod("three", &[("cpu", "1")]),
+            ],
+        );
+        world.capacity = HeldCapacity::new(
+            &[],
+            vec![reservation("data", "ingest", "a", &[("cpu", "2")])],
+        );
+
+        let target = schedule(world, &Profile::default()).await.unwrap();
+
+        assert_eq!(target.state["a"].len(), 2);
+        assert_eq!(target.unscheduled_pods.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_respects_csi_volume_limit_within_batch() {
+        // The node attaches a single volume, so only one of the pods fits next to the other
+        let mut world = world(
+            vec![node("a", &[("cpu", "4")])],
+            vec![
+                with_claims(pod("one", &[("cpu", "1")]), &["one"]),
+                with_claims(pod("two", &[("cpu", "1")]), &["two"]),
+            ],
+        );
+        world.volumes = VolumeState::new(
+            vec![
+                claim("one", "pv-one", "ReadWriteMany"),
+                claim("two", "pv-two", "ReadWriteMany"),
+            ],
+            vec![volume("pv-one", None), volume("pv-two", None)],
+            vec![],
+            vec![csi_node("a", 1)],
+        );
+
+        let target = schedule(world, &Profile::default()).await.unwrap();
+
+        assert_eq!(target.state["a"].len(), 1);
+        assert_eq!(target.unscheduled_pods.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_places_one_pod_per_exclusive_claim() {
+        let mut world = world(
+            vec![node("a", &[("cpu", "4")]), node("b", &[("cpu", "4")])],
+            vec![
+                with_claims(pod("one", &[("cpu", "1")]), &["exclusive"]),
+                with_claims(pod("two", &[("cpu", "1")]), &["exclusive"]),
+            ],
+        );
+        world.volumes = VolumeState::new(
+            vec![claim("exclusive", "pv-exclusive", "ReadWriteOncePod")],
+            vec![volume("pv-exclusive", None)],
+            vec![],
+            vec![],
+        );
+
+        let target = schedule(world, &Profile::default()).await.unwrap();
+
+        assert_eq!(target.state.values().flatten().count(), 1);
+        assert_eq!(target.unscheduled_pods.len(), 1);
+    }
+}
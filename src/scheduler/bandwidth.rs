@@ -0,0 +1,162 @@
+// Network bandwidth: nodes advertise the bandwidth of their network through annotations and
+// pods request bandwidth through the annotations the bandwidth CNI plugin shapes their
+// traffic by. Quantities are in bits per second, e.g. 10M or 1G.
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::{
+    api::core::v1::{Node, Pod},
+    apimachinery::pkg::api::resource::Quantity,
+};
+use kube_quantity::ParsedQuantity;
+
+const NODE_INGRESS_BANDWIDTH_ANNOTATION: &str = "network.scheduling/ingress-bandwidth";
+const NODE_EGRESS_BANDWIDTH_ANNOTATION: &str = "network.scheduling/egress-bandwidth";
+const POD_INGRESS_BANDWIDTH_ANNOTATION: &str = "kubernetes.io/ingress-bandwidth";
+const POD_EGRESS_BANDWIDTH_ANNOTATION: &str = "kubernetes.io/egress-bandwidth";
+
+// Bandwidth in both directions, missing where nothing is advertised or requested
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Bandwidth {
+    ingress: Option<f64>,
+    egress: Option<f64>,
+}
+
+fn annotated(
+    annotations: Option<&BTreeMap<String, String>>,
+    ingress: &str,
+    egress: &str,
+) -> Bandwidth {
+    let parse = |key: &str| {
+        let value = annotations?.get(key)?;
+        let Ok(quantity) = ParsedQuantity::try_from(&Quantity(value.clone())) else {
+            log::warn!("Ignoring invalid bandwidth {value} in annotation {key}");
+            return None;
+        };
+
+        quantity.to_bytes_f64()
+    };
+
+    Bandwidth {
+        ingress: parse(ingress),
+        egress: parse(egress),
+    }
+}
+
+// Bandwidth the node advertises
+fn node_bandwidth(node: &Node) -> Bandwidth {
+    annotated(
+        node.metadata.annotations.as_ref(),
+        NODE_INGRESS_BANDWIDTH_ANNOTATION,
+        NODE_EGRESS_BANDWIDTH_ANNOTATION,
+    )
+}
+
+// Bandwidth the pod requests
+fn pod_bandwidth(pod: &Pod) -> Bandwidth {
+    annotated(
+        pod.metadata.annotations.as_ref(),
+        POD_INGRESS_BANDWIDTH_ANNOTATION,
+        POD_EGRESS_BANDWIDTH_ANNOTATION,
+    )
+}
+
+// Bandwidth reserved by the given pods, e.g. all pods currently bound to a node
+fn reserved_bandwidth<'a>(pods: impl IntoIterator<Item = &'a Pod>) -> Bandwidth {
+    let mut reserved = Bandwidth::default();
+    for pod in pods {
+        let requested = pod_bandwidth(pod);
+        if let Some(ingress) = requested.ingress {
+            *reserved.ingress.get_or_insert(0.0) += ingress;
+        }
+        if let Some(egress) = requested.egress {
+            *reserved.egress.get_or_insert(0.0) += egress;
+        }
+    }
+
+    reserved
+}
+
+// Bandwidth the pod requests in both directions, zero where it requests none
+pub(crate) fn requested(pod: &Pod) -> [f64; 2] {
+    let requested = pod_bandwidth(pod);
+
+    [requested.ingress, requested.egress].map(Option::unwrap_or_default)
+}
+
+// Bandwidth of both directions left on the node next to the given pods, unlimited where the
+// node advertises none
+pub(crate) fn left(node: &Node, node_pods: &[Pod]) -> [f64; 2] {
+    let capacity = node_bandwidth(node);
+    let reserved = reserved_bandwidth(node_pods);
+
+    [
+        (capacity.ingress, reserved.ingress),
+        (capacity.egress, reserved.egress),
+    ]
+    .map(|(capacity, reserved)| match capacity {
+        Some(capacity) => capacity - reserved.unwrap_or_default(),
+        None => f64::INFINITY,
+    })
+}
+
+// Fraction of the advertised bandwidth of each direction the pod requests that is left once
+// the pod is placed next to the given pods, missing for directions the node does not
+// advertise. Negative fractions mean the node is oversubscribed.
+pub(crate) fn free_after_placement(node: &Node, node_pods: &[Pod], pod: &Pod) -> Vec<Option<f64>> {
+    let capacity = node_bandwidth(node);
+    let reserved = reserved_bandwidth(node_pods);
+    let requested = pod_bandwidth(pod);
+
+    [
+        (capacity.ingress, reserved.ingress, requested.ingress),
+        (capacity.egress, reserved.egress, requested.egress),
+    ]
+    .into_iter()
+    .filter_map(|(capacity, reserved, requested)| {
+        let requested = requested?;
+        Some(capacity.map(|capacity| {
+            if capacity <= 0.0 {
+                return -1.0;
+            }
+
+            (capacity - reserved.unwrap_or_default() - requested) / capacity
+        }))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scheduler::testing::{annotated, node, pod};
+
+    use super::*;
+
+    #[test]
+    fn test_free_after_placement() {
+        let node = annotated(node("a", &[]), &[(NODE_INGRESS_BANDWIDTH_ANNOTATION, "1G")]);
+
+        let existing = annotated(
+            pod("existing", &[]),
+            &[
+                (POD_INGRESS_BANDWIDTH_ANNOTATION, "500M"),
+                (POD_EGRESS_BANDWIDTH_ANNOTATION, "100M"),
+            ],
+        );
+        let incoming = annotated(
+            pod("incoming", &[]),
+            &[
+                (POD_INGRESS_BANDWIDTH_ANNOTATION, "250M"),
+                (POD_EGRESS_BANDWIDTH_ANNOTATION, "10M"),
+            ],
+        );
+
+        // A quarter of the ingress bandwidth is left, egress is not advertised
+        assert_eq!(
+            free_after_placement(&node, &[existing], &incoming),
+            [Some(0.25), None]
+        );
+        // Pods not requesting bandwidth have no stake in it
+        assert!(free_after_placement(&node, &[], &pod("other", &[])).is_empty());
+    }
+}
@@ -0,0 +1,304 @@
+// Assumed pods: placements of a scheduler run recorded before their binding is observed, so
+// the following runs neither place the pods again nor hand out the capacity they will take
+// up. An assumption ends once the watch of bound pods reports the binding, or expires after
+// its time to live if the binding never shows up. Members of pod groups waiting for their
+// permit are assumed the same way without being bound, until the permit expires.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, Instant},
+};
+
+use k8s_openapi::api::core::v1::Pod;
+use kube::{core::ObjectList, runtime::watcher};
+
+use crate::scheduler::{queue::pod_key, TargetState};
+
+struct AssumedPod {
+    pod: Pod,
+    node_name: String,
+    expires_at: Instant,
+    // Whether the pod holds its node for a pod group waiting for its permit, instead of
+    // waiting for its binding
+    reserved: bool,
+}
+
+pub(crate) struct AssumeCache {
+    ttl: Duration,
+    assumed: BTreeMap<String, AssumedPod>,
+}
+
+impl AssumeCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            assumed: BTreeMap::new(),
+        }
+    }
+
+    // Assume the pods newly placed by a scheduler run to be bound to their nodes
+    pub(crate) fn assume(&mut self, target: &TargetState, now: Instant) {
+        for (node_name, pod) in target.placements() {
+            self.assumed.insert(
+                pod_key(pod),
+                AssumedPod {
+                    pod: pod.clone(),
+                    node_name: node_name.to_owned(),
+                    expires_at: now + self.ttl,
+                    reserved: false,
+                },
+            );
+        }
+    }
+
+    // Assume a member of a pod group waiting for its permit to stay on its node until the
+    // permit expires. Once the group is admitted, its placement is assumed like any other.
+    pub(crate) fn reserve(&mut self, node_name: &str, pod: &Pod, expires_at: Instant) {
+        self.assumed.insert(
+            pod_key(pod),
+            AssumedPod {
+                pod: pod.clone(),
+                node_name: node_name.to_owned(),
+                expires_at,
+                reserved: true,
+            },
+        );
+    }
+
+    // Drop an assumption, e.g. because binding the pod failed
+    pub(crate) fn forget(&mut self, pod: &Pod) {
+        self.assumed.remove(&pod_key(pod));
+    }
+
+    // Remove the assumptions whose binding was not observed in time and the reservations
+    // whose permit expired, and return their pods
+    pub(crate) fn expire(&mut self, now: Instant) -> Vec<Pod> {
+        let expired: Vec<String> = self
+            .assumed
+            .iter()
+            .filter(|(_, assumed)| assumed.expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|key| {
+                let assumed = self.assumed.remove(&key)?;
+                if assumed.reserved {
+                    log::info!(
+                        "Releasing pod {key} from node {}, its pod group was not admitted in time",
+                        assumed.node_name
+                    );
+                } else {
+                    log::warn!(
+                        "Binding of pod {key} to node {} was not observed in time",
+                        assumed.node_name
+                    );
+                }
+
+                Some(assumed.pod)
+            })
+            .collect()
+    }
+
+    // Confirm the assumptions of pods the watch of bound pods reports as bound, and drop the
+    // ones of deleted pods
+    pub(crate) fn observe_bound(&mut self, event: &watcher::Event<Pod>) {
+        match event {
+            watcher::Event::Applied(pod) | watcher::Event::Deleted(pod) => self.forget(pod),
+            watcher::Event::Restarted(pods) => {
+                for pod in pods {
+                    self.forget(pod);
+                }
+            }
+        }
+    }
+
+    // Drop assumed pods from an event of the watch of unscheduled pods, as they are
+    // reported unscheduled until their binding is observed. Deleted pods are not assumed
+    // anymore, so their expiry does not schedule them again.
+    pub(crate) fn filter_unscheduled(
+        &mut self,
+        event: watcher::Event<Pod>,
+    ) -> Option<watcher::Event<Pod>> {
+        match event {
+            watcher::Event::Applied(pod) if self.assumed.contains_key(&pod_key(&pod)) => None,
+            watcher::Event::Deleted(pod) => {
+                self.forget(&pod);
+                Some(watcher::Event::Deleted(pod))
+            }
+            watcher::Event::Restarted(pods) => Some(watcher::Event::Restarted(
+                pods.into_iter()
+                    .filter(|pod| !self.assumed.contains_key(&pod_key(pod)))
+                    .collect(),
+            )),
+            event => Some(event),
+        }
+    }
+
+    // Add the assumed pods to the pods bound to their nodes, so resource accounting counts
+    // them like bound pods. They carry their node name like bound pods, so the run does not
+    // report them as its own placements and assume or bind them again.
+    pub(crate) fn add_to_state(&self, state: &mut BTreeMap<String, ObjectList<Pod>>) {
+        for (key, assumed) in &self.assumed {
+            let Some(node_pods) = state.get_mut(&assumed.node_name) else { continue };
+
+            let listed: BTreeSet<String> = node_pods.items.iter().map(pod_key).collect();
+            if !listed.contains(key) {
+                let mut pod = assumed.pod.clone();
+                pod.spec.get_or_insert_with(Default::default).node_name =
+                    Some(assumed.node_name.clone());
+                node_pods.items.push(pod);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::Profile,
+        scheduler::{
+            schedule,
+            testing::{list, node, pod, world},
+        },
+        Algorithm,
+    };
+
+    use super::*;
+
+    fn target() -> TargetState {
+        TargetState {
+            unscheduled_pods: vec![],
+            state: BTreeMap::from_iter(vec![("a".to_string(), vec![pod("p", &[("cpu", "1")])])]),
+        }
+    }
+
+    fn state() -> BTreeMap<String, ObjectList<Pod>> {
+        BTreeMap::from_iter(vec![("a".to_string(), list(vec![]))])
+    }
+
+    #[test]
+    fn test_assumed_pods_count_as_bound() {
+        let mut cache = AssumeCache::new(Duration::from_secs(30));
+        cache.assume(&target(), Instant::now());
+
+        let mut state = state();
+        cache.add_to_state(&mut state);
+        cache.add_to_state(&mut state);
+        assert_eq!(state["a"].items.len(), 1);
+
+        assert!(cache
+            .filter_unscheduled(watcher::Event::Applied(pod("p", &[])))
+            .is_none());
+    }
+
+    #[test]
+    fn test_assumption_expires_without_binding() {
+        let mut cache = AssumeCache::new(Duration::from_secs(30));
+        let now = Instant::now();
+        cache.assume(&target(), now);
+
+        assert!(cache.expire(now + Duration::from_secs(29)).is_empty());
+        assert_eq!(cache.expire(now + Duration::from_secs(30)).len(), 1);
+
+        let mut state = state();
+        cache.add_to_state(&mut state);
+        assert!(state["a"].items.is_empty());
+    }
+
+    #[test]
+    fn test_assumption_is_not_renewed_by_later_runs() {
+        let mut cache = AssumeCache::new(Duration::from_secs(30));
+        let now = Instant::now();
+        cache.assume(&target(), now);
+
+        // Two following runs see the assumed pod in their state and keep it in place
+        for run in 1..=2 {
+            let mut state = state();
+            cache.add_to_state(&mut state);
+            let target = TargetState {
+                unscheduled_pods: vec![],
+                state: state
+                    .into_iter()
+                    .map(|(node_name, node_pods)| (node_name, node_pods.items))
+                    .collect(),
+            };
+            assert_eq!(target.placements().count(), 0);
+            cache.assume(&target, now + Duration::from_secs(10 * run));
+        }
+
+        assert_eq!(cache.expire(now + Duration::from_secs(30)).len(), 1);
+    }
+
+    #[test]
+    fn test_reservation_holds_node_until_permit_expires() {
+        let mut cache = AssumeCache::new(Duration::from_secs(30));
+        let now = Instant::now();
+        cache.reserve(
+            "a",
+            &pod("p", &[("cpu", "1")]),
+            now + Duration::from_secs(60),
+        );
+
+        let mut state = state();
+        cache.add_to_state(&mut state);
+        assert_eq!(state["a"].items.len(), 1);
+        assert!(cache
+            .filter_unscheduled(watcher::Event::Applied(pod("p", &[])))
+            .is_none());
+
+        // The reservation outlives the time to live of assumptions
+        assert!(cache.expire(now + Duration::from_secs(59)).is_empty());
+        assert_eq!(cache.expire(now + Duration::from_secs(60)).len(), 1);
+    }
+
+    #[test]
+    fn test_deleted_pods_are_not_requeued() {
+        let now = Instant::now();
+        let mut cache = AssumeCache::new(Duration::from_secs(30));
+
+        cache.assume(&target(), now);
+        cache.observe_bound(&watcher::Event::Deleted(pod("p", &[])));
+        assert!(cache.expire(now + Duration::from_secs(30)).is_empty());
+
+        cache.assume(&target(), now);
+        assert!(cache
+            .filter_unscheduled(watcher::Event::Deleted(pod("p", &[])))
+            .is_some());
+        assert!(cache.expire(now + Duration::from_secs(30)).is_empty());
+    }
+
+    #[test]
+    fn test_observed_binding_ends_assumption() {
+        let mut cache = AssumeCache::new(Duration::from_secs(30));
+        let now = Instant::now();
+        cache.assume(&target(), now);
+
+        cache.observe_bound(&watcher::Event::Applied(pod("p", &[])));
+
+        assert!(cache.expire(now + Duration::from_secs(30)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pod_is_placed_once_across_runs() {
+        let mut cache = AssumeCache::new(Duration::from_secs(30));
+        let nodes = vec![node("a", &[("cpu", "4"), ("memory", "4Gi")])];
+        let profile = Profile::default();
+
+        // The first run places p, the second one only q while p is still assumed
+        let mut placed = vec![];
+        for name in ["p", "q"] {
+            let mut world = world(nodes.clone(), vec![pod(name, &[("cpu", "1")])]);
+            cache.add_to_state(&mut world.state);
+
+            let target = schedule(&Algorithm::BinPacking, world, &profile)
+                .await
+                .unwrap();
+            placed.extend(target.placements().map(|(_, pod)| pod_key(pod)));
+            cache.assume(&target, Instant::now());
+        }
+
+        assert_eq!(placed, [pod_key(&pod("p", &[])), pod_key(&pod("q", &[]))]);
+    }
+}
@@ -0,0 +1,396 @@
+// Effective capacity of nodes: the allocatable resources of the nodes in a world state are
+// rewritten by the overcommit ratios before the algorithm runs, so that every filter, score
+// and algorithm reading them via node_allocatable sees the same capacity. Out of that
+// capacity, the headroom of a node and the capacity reservations hold for workloads yet to
+// arrive are held back from pods when filtering.
+
+use std::collections::BTreeMap;
+
+use color_eyre::{eyre::eyre, Result};
+use k8s_openapi::{
+    api::core::v1::{Node, Pod},
+    apimachinery::pkg::api::resource::Quantity,
+};
+use kube::{runtime::reflector::Store, Api, Client, CustomResource};
+use kube_quantity::ParsedQuantity;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{HeadroomConfig, OvercommitConfig, Profile},
+    scheduler::{
+        resources::{node_allocatable, pod_requests},
+        selectors::labels_match_selector,
+        volumes::reflect,
+    },
+};
+
+// Label of pods joining the workload a reservation holds capacity for
+pub(crate) const RESERVATION_WORKLOAD_LABEL: &str = "reservation.scheduling/workload";
+
+// Capacity held on a node for the pods of a workload in the namespace of the reservation.
+// Pods of the workload landing on the node take up the reservation, once their requests
+// cover it nothing is held anymore.
+#[derive(CustomResource, Debug, Clone, Deserialize, Serialize)]
+#[kube(
+    group = "scheduling.kube-scheduler-rs.io",
+    version = "v1alpha1",
+    kind = "Reservation",
+    namespaced,
+    schema = "disabled"
+)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ReservationSpec {
+    pub(crate) node_name: String,
+    pub(crate) workload: String,
+    pub(crate) resources: BTreeMap<String, Quantity>,
+}
+
+// Scale the allocatable resources of each node by the ratios of the first overcommit entry
+// selecting it
+pub(crate) fn apply_overcommit(nodes: &mut [Node], overcommit: &[OvercommitConfig]) {
+    let empty = BTreeMap::new();
+
+    for node in nodes {
+        let labels = node.metadata.labels.as_ref().unwrap_or(&empty);
+        let Some(config) = overcommit
+            .iter()
+            .find(|config| labels_match_selector(labels, &config.node_selector))
+        else {
+            continue;
+        };
+
+        let Some(allocatable) = node
+            .status
+            .as_mut()
+            .and_then(|status| status.allocatable.as_mut())
+        else {
+            continue;
+        };
+
+        for (resource, ratio) in &config.ratios {
+            let Some(quantity) = allocatable.get_mut(resource) else { continue };
+            let Some(value) = ParsedQuantity::try_from(&*quantity)
+                .ok()
+                .and_then(|parsed| parsed.to_bytes_f64())
+            else {
+                continue;
+            };
+
+            *quantity = Quantity(format_quantity(value * ratio));
+        }
+    }
+}
+
+// Quantities are rounded to thousandths, the precision of millicores
+fn format_quantity(value: f64) -> String {
+    format!("{}", (value * 1000.0).round() / 1000.0)
+}
+
+// Headroom of a resource, absolute or relative to the allocatable quantity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Amount {
+    Absolute(f64),
+    Percentage(f64),
+}
+
+impl Amount {
+    pub(crate) fn parse(value: &str) -> Result<Self> {
+        if let Some(percentage) = value.strip_suffix('%') {
+            let percentage: f64 = percentage.trim().parse()?;
+            if !(0.0..=100.0).contains(&percentage) {
+                return Err(eyre!("percentage {value} is not between 0% and 100%"));
+            }
+
+            return Ok(Self::Percentage(percentage));
+        }
+
+        let quantity = ParsedQuantity::try_from(&Quantity(value.to_owned()))
+            .map_err(|err| eyre!("invalid quantity {value}: {err}"))?;
+        let Some(absolute) = quantity.to_bytes_f64() else {
+            return Err(eyre!("quantity {value} is out of range"));
+        };
+
+        Ok(Self::Absolute(absolute))
+    }
+
+    fn of(self, allocatable: f64) -> f64 {
+        match self {
+            Self::Absolute(absolute) => absolute,
+            Self::Percentage(percentage) => allocatable * percentage / 100.0,
+        }
+    }
+}
+
+// Reflected reservations of the cluster, only watched if the profile enables them
+#[derive(Clone, Default)]
+pub(crate) struct ReservationCache {
+    reservations: Option<Store<Reservation>>,
+}
+
+impl ReservationCache {
+    pub(crate) fn start(client: &Client, profile: &Profile) -> Self {
+        Self {
+            reservations: profile
+                .reservations
+                .then(|| reflect(Api::all(client.clone()))),
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> Vec<Reservation> {
+        self.reservations
+            .iter()
+            .flat_map(Store::state)
+            .map(|reservation| (*reservation).clone())
+            .collect()
+    }
+}
+
+// Capacity held back from pods at the start of a scheduler run
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HeldCapacity {
+    headroom: Vec<HeadroomConfig>,
+    reservations: Vec<Reservation>,
+}
+
+impl HeldCapacity {
+    pub(crate) fn new(headroom: &[HeadroomConfig], reservations: Vec<Reservation>) -> Self {
+        Self {
+            headroom: headroom.to_vec(),
+            reservations,
+        }
+    }
+
+    // Headroom of the first entry selecting the node, kept free from all pods
+    pub(crate) fn headroom(&self, node: &Node) -> BTreeMap<String, f64> {
+        let empty = BTreeMap::new();
+        let labels = node.metadata.labels.as_ref().unwrap_or(&empty);
+        let Some(config) = self
+            .headroom
+            .iter()
+            .find(|config| labels_match_selector(labels, &config.node_selector))
+        else {
+            return BTreeMap::new();
+        };
+
+        let allocatable = node_allocatable(node).unwrap_or_default();
+        config
+            .resources
+            .iter()
+            .filter_map(|(resource, amount)| {
+                let amount = Amount::parse(amount).ok()?;
+                let allocatable = allocatable
+                    .get(resource)
+                    .and_then(ParsedQuantity::to_bytes_f64)
+                    .unwrap_or_default();
+
+                Some((resource.clone(), amount.of(allocatable)))
+            })
+            .collect()
+    }
+
+    // Capacity of the node held back from the pod: the headroom of the node plus what the
+    // reservations of other workloads on the node hold beyond the requests of their pods
+    // already on it
+    pub(crate) fn held(&self, node: &Node, node_pods: &[Pod], pod: &Pod) -> BTreeMap<String, f64> {
+        let mut held = self.headroom(node);
+
+        let workload = reservation_workload(pod);
+        for (_, left) in self
+            .reserved(node, node_pods)
+            .into_iter()
+            .filter(|(reserved, _)| Some(reserved) != workload.as_ref())
+        {
+            for (resource, left) in left {
+                *held.entry(resource).or_default() += left;
+            }
+        }
+
+        held
+    }
+
+    // Capacity the reservations on the node hold beyond the requests of the pods of their
+    // workloads already on it, by the workload as returned by reservation_workload
+    pub(crate) fn reserved(
+        &self,
+        node: &Node,
+        node_pods: &[Pod],
+    ) -> BTreeMap<String, BTreeMap<String, f64>> {
+        let mut reserved: BTreeMap<String, BTreeMap<String, f64>> = BTreeMap::new();
+
+        let node_name = node.metadata.name.as_deref().unwrap_or_default();
+        for reservation in &self.reservations {
+            if reservation.spec.node_name != node_name {
+                continue;
+            }
+            let workload = format!(
+                "{}/{}",
+                reservation
+                    .metadata
+                    .namespace
+                    .as_deref()
+                    .unwrap_or_default(),
+                reservation.spec.workload
+            );
+
+            let mut taken_up: BTreeMap<String, f64> = BTreeMap::new();
+            for workload_pod in node_pods
+                .iter()
+                .filter(|node_pod| reservation_workload(node_pod).as_ref() == Some(&workload))
+            {
+                for (resource, quantity) in pod_requests(workload_pod) {
+                    *taken_up.entry(resource).or_default() +=
+                        quantity.to_bytes_f64().unwrap_or_default();
+                }
+            }
+
+            let left = reserved.entry(workload).or_default();
+            for (resource, quantity) in &reservation.spec.resources {
+                let Some(quantity) = ParsedQuantity::try_from(quantity)
+                    .ok()
+                    .and_then(|parsed| parsed.to_bytes_f64())
+                else {
+                    continue;
+                };
+
+                *left.entry(resource.clone()).or_default() +=
+                    (quantity - taken_up.get(resource).copied().unwrap_or_default()).max(0.0);
+            }
+        }
+
+        reserved
+    }
+}
+
+// Workload a reservation in the namespace of the pod holds capacity for the pod as part of,
+// as namespace/workload
+pub(crate) fn reservation_workload(pod: &Pod) -> Option<String> {
+    let workload = pod
+        .metadata
+        .labels
+        .as_ref()?
+        .get(RESERVATION_WORKLOAD_LABEL)?;
+
+    Some(format!(
+        "{}/{workload}",
+        pod.metadata.namespace.as_deref().unwrap_or_default()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+
+    use crate::scheduler::{
+        filters::is_pod_allocatable,
+        resources::node_allocatable,
+        testing::{in_workload, node, pod, reservation},
+    };
+
+    use super::*;
+
+    fn batch_overcommit() -> Vec<OvercommitConfig> {
+        vec![OvercommitConfig {
+            node_selector: LabelSelector {
+                match_labels: Some(BTreeMap::from_iter(vec![(
+                    "pool".to_string(),
+                    "batch".to_string(),
+                )])),
+                ..Default::default()
+            },
+            ratios: BTreeMap::from_iter(vec![("cpu".to_string(), 1.5)]),
+        }]
+    }
+
+    #[test]
+    fn test_overcommit_selected_nodes() {
+        let mut batch = node("batch", &[("cpu", "4"), ("memory", "8Gi")]);
+        batch.metadata.labels = Some(BTreeMap::from_iter(vec![(
+            "pool".to_string(),
+            "batch".to_string(),
+        )]));
+        let mut nodes = [batch, node("web", &[("cpu", "4"), ("memory", "8Gi")])];
+
+        apply_overcommit(&mut nodes, &batch_overcommit());
+
+        let batch = node_allocatable(&nodes[0]).unwrap();
+        assert_eq!(batch["cpu"].to_bytes_f64(), Some(6.0));
+        assert_eq!(
+            batch["memory"].to_bytes_f64(),
+            Some(8.0 * 1024.0 * 1024.0 * 1024.0)
+        );
+        assert_eq!(
+            node_allocatable(&nodes[1]).unwrap()["cpu"].to_bytes_f64(),
+            Some(4.0)
+        );
+
+        // The pod only fits onto the overcommitted node
+        let pod = pod("p", &[("cpu", "5")]);
+        assert!(is_pod_allocatable(&nodes[0], &pod));
+        assert!(!is_pod_allocatable(&nodes[1], &pod));
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(Amount::parse("10%").unwrap(), Amount::Percentage(10.0));
+        assert_eq!(Amount::parse("500m").unwrap(), Amount::Absolute(0.5));
+        assert!(Amount::parse("150%").is_err());
+        assert!(Amount::parse("lots").is_err());
+    }
+
+    #[test]
+    fn test_held_capacity() {
+        let capacity = HeldCapacity::new(
+            &[HeadroomConfig {
+                node_selector: LabelSelector::default(),
+                resources: BTreeMap::from_iter(vec![
+                    ("cpu".to_string(), "10%".to_string()),
+                    ("memory".to_string(), "1Gi".to_string()),
+                ]),
+            }],
+            vec![reservation("data", "ingest", "a", &[("cpu", "2")])],
+        );
+        let node = node("a", &[("cpu", "10"), ("memory", "8Gi")]);
+        let gib = 1024.0 * 1024.0 * 1024.0;
+
+        // Other pods see the headroom and the whole reservation
+        let other = pod("other", &[]);
+        let held = capacity.held(&node, &[], &other);
+        assert_eq!(held["cpu"], 3.0);
+        assert_eq!(held["memory"], gib);
+
+        // Pods of the workload only see the headroom
+        let ingest = in_workload(pod("ingest", &[("cpu", "1")]), "data", "ingest");
+        assert_eq!(capacity.held(&node, &[], &ingest)["cpu"], 1.0);
+
+        // Arrived pods of the workload take up the reservation, namesakes elsewhere do not
+        let arrived = [
+            ingest.clone(),
+            in_workload(pod("namesake", &[("cpu", "1")]), "other", "ingest"),
+        ];
+        assert_eq!(capacity.held(&node, &arrived, &other)["cpu"], 2.0);
+        assert_eq!(
+            capacity.held(&node, &[ingest.clone(), ingest], &other)["cpu"],
+            1.0
+        );
+
+        // Reservations only hold capacity on their node
+        assert_eq!(
+            capacity.held(&node_with_name("b", &node), &[], &other)["cpu"],
+            1.0
+        );
+    }
+
+    fn node_with_name(name: &str, node: &Node) -> Node {
+        let mut node = node.clone();
+        node.metadata.name = Some(name.to_string());
+
+        node
+    }
+
+    #[test]
+    fn test_format_quantity() {
+        assert_eq!(format_quantity(1.5 * 0.25), "0.375");
+        assert_eq!(format_quantity(12.0), "12");
+        assert_eq!(format_quantity(2.0 / 3.0), "0.667");
+    }
+}
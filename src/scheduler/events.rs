@@ -0,0 +1,258 @@
+// Cluster events derived from the watches of nodes and bound pods. Each event names a change
+// that could make pods schedulable which failed to schedule before.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use k8s_openapi::api::core::v1::{Node, Pod};
+use kube::runtime::watcher;
+
+use crate::scheduler::{
+    filters::is_node_schedulable, gang::pod_group, queue::pod_key, resources::node_allocatable,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ClusterEvent {
+    NodeAdded,
+    // A node became ready or was uncordoned
+    NodeBecameSchedulable,
+    NodeAllocatableIncreased,
+    NodeLabelsChanged,
+    NodeTaintsChanged,
+    // A bound pod was deleted or terminated, freeing its resources
+    AssignedPodRemoved,
+    // A member of the pod group was bound or created
+    PodGroupMemberAdded(String),
+}
+
+// Last seen nodes, to tell which parts of a node changed
+#[derive(Debug, Default)]
+pub(crate) struct NodeTracker {
+    nodes: BTreeMap<String, Node>,
+}
+
+impl NodeTracker {
+    pub(crate) fn handle(&mut self, event: watcher::Event<Node>) -> Vec<ClusterEvent> {
+        match event {
+            watcher::Event::Applied(node) => self.apply(node),
+            watcher::Event::Deleted(node) => {
+                if let Some(name) = &node.metadata.name {
+                    self.nodes.remove(name);
+                }
+                vec![]
+            }
+            watcher::Event::Restarted(nodes) => {
+                let names: BTreeSet<String> = nodes
+                    .iter()
+                    .filter_map(|node| node.metadata.name.clone())
+                    .collect();
+                self.nodes.retain(|name, _| names.contains(name));
+
+                nodes
+                    .into_iter()
+                    .flat_map(|node| self.apply(node))
+                    .collect()
+            }
+        }
+    }
+
+    fn apply(&mut self, node: Node) -> Vec<ClusterEvent> {
+        let Some(name) = node.metadata.name.clone() else { return vec![] };
+
+        let events = node_events(self.nodes.get(&name), &node);
+        self.nodes.insert(name, node);
+
+        events
+    }
+}
+
+fn node_events(previous: Option<&Node>, node: &Node) -> Vec<ClusterEvent> {
+    let Some(previous) = previous else { return vec![ClusterEvent::NodeAdded] };
+
+    let mut events = vec![];
+    if !is_node_ready(previous) && is_node_ready(node) {
+        events.push(ClusterEvent::NodeBecameSchedulable);
+    }
+    if previous.metadata.labels != node.metadata.labels {
+        events.push(ClusterEvent::NodeLabelsChanged);
+    }
+
+    let taints = |node: &Node| node.spec.as_ref().and_then(|spec| spec.taints.clone());
+    if taints(previous) != taints(node) {
+        events.push(ClusterEvent::NodeTaintsChanged);
+    }
+
+    let previous_allocatable = node_allocatable(previous).unwrap_or_default();
+    let allocatable = node_allocatable(node).unwrap_or_default();
+    if allocatable.iter().any(|(resource_name, quantity)| {
+        !matches!(previous_allocatable.get(resource_name), Some(previous) if quantity <= previous)
+    }) {
+        events.push(ClusterEvent::NodeAllocatableIncreased);
+    }
+
+    events
+}
+
+// A node accepts new pods when it is not cordoned and its Ready condition, if reported, is
+// true
+fn is_node_ready(node: &Node) -> bool {
+    let ready = node
+        .status
+        .as_ref()
+        .and_then(|status| status.conditions.as_ref())
+        .and_then(|conditions| {
+            conditions
+                .iter()
+                .find(|condition| condition.type_ == "Ready")
+        });
+
+    !matches!(ready, Some(condition) if condition.status != "True") && is_node_schedulable(node)
+}
+
+// Bound pods still holding on to the resources of their node
+#[derive(Debug, Default)]
+pub(crate) struct AssignedPodTracker {
+    pods: BTreeSet<String>,
+}
+
+impl AssignedPodTracker {
+    pub(crate) fn handle(&mut self, event: watcher::Event<Pod>) -> Vec<ClusterEvent> {
+        match event {
+            watcher::Event::Applied(pod) => self.apply(&pod),
+            watcher::Event::Deleted(pod) => {
+                if self.pods.remove(&pod_key(&pod)) {
+                    vec![ClusterEvent::AssignedPodRemoved]
+                } else {
+                    vec![]
+                }
+            }
+            watcher::Event::Restarted(pods) => {
+                let keys: BTreeSet<String> = pods.iter().map(pod_key).collect();
+
+                let mut events = vec![];
+                if self.pods.iter().any(|key| !keys.contains(key)) {
+                    events.push(ClusterEvent::AssignedPodRemoved);
+                }
+                self.pods.retain(|key| keys.contains(key));
+
+                for pod in &pods {
+                    events.extend(self.apply(pod));
+                }
+                events.dedup();
+
+                events
+            }
+        }
+    }
+
+    fn apply(&mut self, pod: &Pod) -> Vec<ClusterEvent> {
+        let key = pod_key(pod);
+
+        if is_terminated(pod) {
+            return if self.pods.remove(&key) {
+                vec![ClusterEvent::AssignedPodRemoved]
+            } else {
+                vec![]
+            };
+        }
+
+        if !self.pods.insert(key) {
+            return vec![];
+        }
+
+        pod_group(pod)
+            .map(|(group, _)| vec![ClusterEvent::PodGroupMemberAdded(group)])
+            .unwrap_or_default()
+    }
+}
+
+fn is_terminated(pod: &Pod) -> bool {
+    matches!(
+        pod.status
+            .as_ref()
+            .and_then(|status| status.phase.as_deref()),
+        Some("Succeeded" | "Failed")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::{NodeCondition, PodStatus};
+
+    use crate::scheduler::testing::{node, pod};
+
+    use super::*;
+
+    fn with_ready(mut node: Node, status: &str) -> Node {
+        if let Some(node_status) = node.status.as_mut() {
+            node_status.conditions = Some(vec![NodeCondition {
+                type_: "Ready".to_string(),
+                status: status.to_string(),
+                ..Default::default()
+            }]);
+        }
+        node
+    }
+
+    #[test]
+    fn test_node_events() {
+        let mut tracker = NodeTracker::default();
+
+        let not_ready = with_ready(node("a", &[("cpu", "2")]), "False");
+        assert_eq!(
+            tracker.handle(watcher::Event::Applied(not_ready.clone())),
+            vec![ClusterEvent::NodeAdded]
+        );
+        assert!(tracker
+            .handle(watcher::Event::Applied(not_ready))
+            .is_empty());
+
+        assert_eq!(
+            tracker.handle(watcher::Event::Applied(with_ready(
+                node("a", &[("cpu", "4")]),
+                "True"
+            ))),
+            vec![
+                ClusterEvent::NodeBecameSchedulable,
+                ClusterEvent::NodeAllocatableIncreased
+            ]
+        );
+
+        // Shrinking allocatable cannot make any pod schedulable
+        assert!(tracker
+            .handle(watcher::Event::Applied(with_ready(
+                node("a", &[("cpu", "1")]),
+                "True"
+            )))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_assigned_pod_events() {
+        let mut tracker = AssignedPodTracker::default();
+        let running = pod("p", &[]);
+
+        assert!(tracker
+            .handle(watcher::Event::Applied(running.clone()))
+            .is_empty());
+
+        let mut succeeded = running.clone();
+        succeeded.status = Some(PodStatus {
+            phase: Some("Succeeded".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(
+            tracker.handle(watcher::Event::Applied(succeeded.clone())),
+            vec![ClusterEvent::AssignedPodRemoved]
+        );
+        // Deleting the terminated pod frees nothing anymore
+        assert!(tracker
+            .handle(watcher::Event::Deleted(succeeded))
+            .is_empty());
+
+        tracker.handle(watcher::Event::Applied(running));
+        assert_eq!(
+            tracker.handle(watcher::Event::Restarted(vec![])),
+            vec![ClusterEvent::AssignedPodRemoved]
+        );
+    }
+}
@@ -0,0 +1,506 @@
+// Scheduler extenders: HTTP services implementing the kube-scheduler extender protocol.
+// Extenders narrow down the nodes left by the built-in filters, add weighted priorities to
+// the scores of the remaining nodes and may take over binding pods to their nodes.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+use hyper::{client::HttpConnector, header, Body, Client, Method, Request, StatusCode};
+use k8s_openapi::{
+    api::core::v1::{Node, Pod},
+    apimachinery::pkg::apis::meta::v1::ObjectMeta,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    config::{ExtenderConfig, Profile},
+    scheduler::scores::MAX_NODE_SCORE,
+};
+
+// Upper bound of the priority an extender may assign to a node
+const MAX_EXTENDER_PRIORITY: i64 = 10;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ExtenderError {
+    #[error("extender {url} did not respond within {timeout:?}")]
+    Timeout { url: String, timeout: Duration },
+    #[error("request to extender {url} failed: {source}")]
+    Http {
+        url: String,
+        #[source]
+        source: hyper::Error,
+    },
+    #[error("invalid request to extender {url}: {source}")]
+    Request {
+        url: String,
+        #[source]
+        source: hyper::http::Error,
+    },
+    #[error("extender {url} responded with status {status}")]
+    Status { url: String, status: StatusCode },
+    #[error("failed to encode or decode a message of extender {url}: {source}")]
+    Json {
+        url: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    // The extender reported an error in its response
+    #[error("extender {url} failed: {message}")]
+    Extender { url: String, message: String },
+}
+
+impl ExtenderError {
+    // Whether a retry could succeed
+    pub(crate) fn is_transient(&self) -> bool {
+        matches!(self, Self::Timeout { .. } | Self::Http { .. })
+    }
+}
+
+// Messages of the extender protocol, which uses the Go field names
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ExtenderArgs<'a> {
+    pod: &'a Pod,
+    nodes: NodeList<'a>,
+}
+
+#[derive(Serialize)]
+struct NodeList<'a> {
+    items: &'a [&'a Node],
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+struct ExtenderFilterResult {
+    nodes: Option<NamedObjectList>,
+    node_names: Option<Vec<String>>,
+    failed_nodes: BTreeMap<String, String>,
+    failed_and_unresolvable_nodes: BTreeMap<String, String>,
+    error: String,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct NamedObjectList {
+    items: Vec<NamedObject>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct NamedObject {
+    metadata: ObjectMeta,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HostPriority {
+    host: String,
+    score: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ExtenderBindingArgs<'a> {
+    pod_name: &'a str,
+    pod_namespace: &'a str,
+    #[serde(rename = "PodUID")]
+    pod_uid: &'a str,
+    node: &'a str,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+struct ExtenderBindingResult {
+    error: String,
+}
+
+pub(crate) struct Extender {
+    config: ExtenderConfig,
+    client: Client<HttpConnector>,
+}
+
+// Instantiate the extenders configured in a profile
+pub(crate) fn extenders(profile: &Profile) -> Vec<Extender> {
+    profile
+        .extenders
+        .iter()
+        .cloned()
+        .map(Extender::new)
+        .collect()
+}
+
+// Extender the binding of pods is delegated to, the first one with a bind verb
+pub(crate) fn binder(profile: &Profile) -> Option<Extender> {
+    profile
+        .extenders
+        .iter()
+        .find(|config| config.bind_verb.is_some())
+        .cloned()
+        .map(Extender::new)
+}
+
+// Narrow the nodes down to the ones accepted by all filtering extenders. Failures of
+// ignorable extenders are skipped, any other failure fails the filtering.
+pub(crate) async fn filter<'a>(
+    extenders: &[Extender],
+    pod: &Pod,
+    mut nodes: Vec<&'a Node>,
+) -> Result<Vec<&'a Node>, ExtenderError> {
+    for extender in extenders {
+        let Some(verb) = &extender.config.filter_verb else { continue };
+        if nodes.is_empty() {
+            break;
+        }
+
+        match extender.filter(verb, pod, &nodes).await {
+            Ok(accepted) => nodes.retain(|node| {
+                matches!(&node.metadata.name, Some(node_name) if accepted.contains(node_name))
+            }),
+            Err(err) if extender.config.ignorable => {
+                log::warn!("Skipping ignorable extender: {err}");
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(nodes)
+}
+
+// Sum of the weighted priorities of all prioritizing extenders for each of the given nodes,
+// scaled to the range of the score plugins. Like upstream, failing extenders are skipped
+// as the other scores still rank the nodes.
+pub(crate) async fn prioritize(extenders: &[Extender], pod: &Pod, nodes: &[&Node]) -> Vec<i64> {
+    let mut totals = vec![0; nodes.len()];
+
+    for extender in extenders {
+        let Some(verb) = &extender.config.prioritize_verb else { continue };
+        if nodes.is_empty() {
+            break;
+        }
+
+        let priorities = match extender.prioritize(verb, pod, nodes).await {
+            Ok(priorities) => priorities,
+            Err(err) => {
+                log::warn!("Skipping priorities of extender: {err}");
+                continue;
+            }
+        };
+
+        for (total, node) in totals.iter_mut().zip(nodes) {
+            let Some(node_name) = &node.metadata.name else { continue };
+            let priority = priorities.get(node_name).copied().unwrap_or_default();
+
+            *total += priority.clamp(0, MAX_EXTENDER_PRIORITY)
+                * extender.config.weight
+                * (MAX_NODE_SCORE / MAX_EXTENDER_PRIORITY);
+        }
+    }
+
+    totals
+}
+
+impl Extender {
+    fn new(config: ExtenderConfig) -> Self {
+        Self {
+            config,
+            client: Client::new(),
+        }
+    }
+
+    async fn filter(
+        &self,
+        verb: &str,
+        pod: &Pod,
+        nodes: &[&Node],
+    ) -> Result<BTreeSet<String>, ExtenderError> {
+        let result: ExtenderFilterResult = self
+            .call(
+                verb,
+                &ExtenderArgs {
+                    pod,
+                    nodes: NodeList { items: nodes },
+                },
+            )
+            .await?;
+        if !result.error.is_empty() {
+            return Err(self.error(result.error));
+        }
+
+        for (node_name, reason) in result
+            .failed_nodes
+            .iter()
+            .chain(&result.failed_and_unresolvable_nodes)
+        {
+            log::debug!(
+                "Extender {} rejected node {node_name}: {reason}",
+                self.config.url_prefix
+            );
+        }
+
+        // Extenders answer with either the nodes or only their names
+        let accepted = match (result.nodes, result.node_names) {
+            (Some(nodes), _) => nodes
+                .items
+                .into_iter()
+                .filter_map(|node| node.metadata.name)
+                .collect(),
+            (None, Some(node_names)) => node_names.into_iter().collect(),
+            (None, None) => BTreeSet::new(),
+        };
+
+        Ok(accepted)
+    }
+
+    async fn prioritize(
+        &self,
+        verb: &str,
+        pod: &Pod,
+        nodes: &[&Node],
+    ) -> Result<BTreeMap<String, i64>, ExtenderError> {
+        let priorities: Vec<HostPriority> = self
+            .call(
+                verb,
+                &ExtenderArgs {
+                    pod,
+                    nodes: NodeList { items: nodes },
+                },
+            )
+            .await?;
+
+        Ok(priorities
+            .into_iter()
+            .map(|priority| (priority.host, priority.score))
+            .collect())
+    }
+
+    // Bind the pod to the node through the extender
+    pub(crate) async fn bind(&self, pod: &Pod, node_name: &str) -> Result<(), ExtenderError> {
+        let Some(verb) = &self.config.bind_verb else {
+            return Err(self.error("extender has no bind verb".to_owned()));
+        };
+
+        let result: ExtenderBindingResult = self
+            .call(
+                verb,
+                &ExtenderBindingArgs {
+                    pod_name: pod.metadata.name.as_deref().unwrap_or_default(),
+                    pod_namespace: pod.metadata.namespace.as_deref().unwrap_or_default(),
+                    pod_uid: pod.metadata.uid.as_deref().unwrap_or_default(),
+                    node: node_name,
+                },
+            )
+            .await?;
+        if !result.error.is_empty() {
+            return Err(self.error(result.error));
+        }
+
+        Ok(())
+    }
+
+    // POST the arguments as JSON to the verb of the extender and decode the response
+    async fn call<A: Serialize, R: DeserializeOwned>(
+        &self,
+        verb: &str,
+        args: &A,
+    ) -> Result<R, ExtenderError> {
+        let url = format!("{}/{verb}", self.config.url_prefix.trim_end_matches('/'));
+
+        let body = serde_json::to_vec(args).map_err(|source| ExtenderError::Json {
+            url: url.clone(),
+            source,
+        })?;
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(&url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .map_err(|source| ExtenderError::Request {
+                url: url.clone(),
+                source,
+            })?;
+
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let exchange = async {
+            let response = self.client.request(request).await?;
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await?;
+
+            Ok((status, body))
+        };
+        let (status, body) = tokio::time::timeout(timeout, exchange)
+            .await
+            .map_err(|_| ExtenderError::Timeout {
+                url: url.clone(),
+                timeout,
+            })?
+            .map_err(|source| ExtenderError::Http {
+                url: url.clone(),
+                source,
+            })?;
+
+        if !status.is_success() {
+            return Err(ExtenderError::Status { url, status });
+        }
+
+        serde_json::from_slice(&body).map_err(|source| ExtenderError::Json { url, source })
+    }
+
+    fn error(&self, message: String) -> ExtenderError {
+        ExtenderError::Extender {
+            url: self.config.url_prefix.clone(),
+            message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::scheduler::{
+        algorithms::{annealing, bin_packing, optimal},
+        testing::{node, pod, stand_in, stand_in_after, world},
+    };
+
+    use super::*;
+
+    fn node_names(args: &Value) -> Vec<String> {
+        args["Nodes"]["items"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|node| node["metadata"]["name"].as_str())
+            .map(str::to_owned)
+            .collect()
+    }
+
+    // Accepts all nodes but "a" and prefers "b"
+    fn handler(path: &str, args: Value) -> Value {
+        match path {
+            "/filter" => json!({
+                "NodeNames": node_names(&args).into_iter().filter(|name| name != "a").collect::<Vec<_>>(),
+                "FailedNodes": { "a": "rejected" },
+            }),
+            "/prioritize" => Value::Array(
+                node_names(&args)
+                    .into_iter()
+                    .map(|name| json!({ "Host": name, "Score": if name == "b" { 10 } else { 0 } }))
+                    .collect(),
+            ),
+            "/bind" if args["Node"] == "a" => json!({ "Error": "node a is reserved" }),
+            _ => json!({}),
+        }
+    }
+
+    fn config(url_prefix: String) -> ExtenderConfig {
+        ExtenderConfig {
+            url_prefix,
+            filter_verb: Some("filter".to_owned()),
+            prioritize_verb: Some("prioritize".to_owned()),
+            bind_verb: Some("bind".to_owned()),
+            weight: 2,
+            timeout_ms: 1000,
+            ignorable: false,
+        }
+    }
+
+    fn nodes() -> Vec<Node> {
+        ["a", "b", "c"]
+            .into_iter()
+            .map(|name| node(name, &[("cpu", "4"), ("memory", "4Gi")]))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_filter_and_prioritize() {
+        let extenders = vec![Extender::new(config(stand_in(handler)))];
+        let nodes = nodes();
+        let pod = pod("p", &[]);
+
+        let feasible = filter(&extenders, &pod, nodes.iter().collect())
+            .await
+            .unwrap();
+        let feasible_names: Vec<_> = feasible
+            .iter()
+            .filter_map(|node| node.metadata.name.as_deref())
+            .collect();
+        assert_eq!(feasible_names, ["b", "c"]);
+
+        assert_eq!(prioritize(&extenders, &pod, &feasible).await, [200, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_fails_unless_ignorable() {
+        let url = stand_in_after(Duration::from_millis(500), handler);
+        let nodes = nodes();
+        let pod = pod("p", &[]);
+
+        let mut config = config(url);
+        config.timeout_ms = 50;
+        let extenders = vec![Extender::new(config.clone())];
+        let result = filter(&extenders, &pod, nodes.iter().collect()).await;
+        assert!(matches!(result, Err(ExtenderError::Timeout { .. })));
+
+        config.ignorable = true;
+        let extenders = vec![Extender::new(config)];
+        let feasible = filter(&extenders, &pod, nodes.iter().collect())
+            .await
+            .unwrap();
+        assert_eq!(feasible.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_bind() {
+        let extender = Extender::new(config(stand_in(handler)));
+        let pod = pod("p", &[]);
+
+        extender.bind(&pod, "b").await.unwrap();
+        assert!(matches!(
+            extender.bind(&pod, "a").await,
+            Err(ExtenderError::Extender { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_bin_packing_honors_extenders() {
+        let mut profile = Profile::default();
+        profile.extenders.push(config(stand_in(handler)));
+
+        // Without the extender the pod would join the pod on the fuller node "a"
+        let mut world = world(nodes(), vec![pod("p", &[("cpu", "1")])]);
+        if let Some(pods) = world.state.get_mut("a") {
+            pods.items.push(pod("existing", &[("cpu", "2")]));
+        }
+
+        let target = bin_packing::schedule(world, &profile).await.unwrap();
+        assert!(target.state["b"]
+            .iter()
+            .any(|pod| pod.metadata.name.as_deref() == Some("p")));
+    }
+
+    #[tokio::test]
+    async fn test_batch_algorithms_honor_extenders() {
+        let mut profile = Profile::default();
+        profile.extenders.push(config(stand_in(handler)));
+        profile.annealing.seed = Some(7);
+
+        // Without the extender the pod would be moved onto "a" to save a node
+        let world = || {
+            let mut world = world(nodes(), vec![pod("p", &[("cpu", "1")])]);
+            if let Some(pods) = world.state.get_mut("a") {
+                pods.items.push(pod("existing", &[("cpu", "2")]));
+            }
+            world
+        };
+
+        for target in [
+            optimal::schedule(world(), &profile).await.unwrap(),
+            annealing::schedule(world(), &profile).await.unwrap(),
+        ] {
+            assert_eq!(target.state["a"].len(), 1);
+            assert!(target.unscheduled_pods.is_empty());
+        }
+    }
+}
@@ -1,46 +1,124 @@
 use std::collections::BTreeMap;
 
 use k8s_openapi::api::core::v1::{Node, Pod};
-use kube_quantity::{ParseQuantityError, ParsedQuantity};
-
-pub(crate) fn is_pod_allocatable(node: &Node, pod: &Pod) -> bool {
-    let Some(status) = &node.status else { return false };
-    let Some(allocatable) = &status.allocatable else { return false };
+use kube_quantity::ParsedQuantity;
+
+use crate::{
+    config::{FilterPlugin as FilterPluginConfig, Profile},
+    scheduler::{
+        bandwidth::free_after_placement,
+        capacity::HeldCapacity,
+        load::{LoadThreshold, NodeLoad},
+        resources::{node_allocatable, pod_requests, requested_after_placement},
+        volumes::{
+            is_pod_csi_volume_limit_fulfilled, is_pod_volume_access_fulfilled,
+            is_pod_volume_binding_fulfilled, is_pod_volume_topology_fulfilled, VolumeState,
+        },
+        wasm::{RejectAll, WasmPlugin},
+    },
+};
+
+// Cluster state the built-in filters may take into account next to the pod and node they
+// check
+pub(crate) struct FilterContext<'a> {
+    // Pods bound, or already assigned during this run, to each node
+    pub(crate) state: &'a BTreeMap<String, Vec<Pod>>,
+    pub(crate) volumes: &'a VolumeState,
+    // Headroom and reservations held back from pods
+    pub(crate) capacity: &'a HeldCapacity,
+}
 
-    // TODO: Extract network bandwidth information from annotations
-    // if let Some(annotations) = &node.metadata.annotations {
-    // };
+impl<'a> FilterContext<'a> {
+    pub(crate) fn node_pods(&self, node: &Node) -> &'a [Pod] {
+        node.metadata
+            .name
+            .as_ref()
+            .and_then(|node_name| self.state.get(node_name))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
 
-    // If there is no pod.spec one cannot make any allocation related decisions
-    let Some(pod_spec) = &pod.spec else { return false };
+// Filter configured in a profile, run after the built-in filters
+pub(crate) trait FilterPlugin: Send + Sync {
+    fn filter(&self, pod: &Pod, node: &Node) -> bool;
+}
 
-    // Parse allocatable quantities
-    let allocatable: BTreeMap<String, ParsedQuantity> = allocatable
+// Instantiate the filter plugins configured in a profile
+pub(crate) fn plugins(profile: &Profile, load: &NodeLoad) -> Vec<Box<dyn FilterPlugin>> {
+    profile
+        .filter_plugins
         .iter()
-        .filter_map(|(k, v)| {
-			let Ok(v): Result<ParsedQuantity, ParseQuantityError> = v.try_into() else { return None };
+        .filter_map(|config| match config {
+            FilterPluginConfig::LoadAware { args } => {
+                Some(Box::new(LoadThreshold::new(args, load)) as Box<dyn FilterPlugin>)
+            }
+            FilterPluginConfig::Wasm { args } => match WasmPlugin::load(args) {
+                Ok(plugin) => Some(Box::new(plugin) as Box<dyn FilterPlugin>),
+                Err(err) if args.ignorable => {
+                    log::error!("Skipping filter plugin: {err}");
+                    None
+                }
+                Err(err) => {
+                    log::error!("Rejecting all nodes for filter plugin: {err}");
+                    Some(Box::new(RejectAll) as Box<dyn FilterPlugin>)
+                }
+            },
+        })
+        .collect()
+}
 
-			Some((k.clone(), v))
-		})
-        .collect();
+// Run the filter pipeline shared by all algorithms and return the nodes a pod could be
+// placed on
+pub(crate) fn feasible_nodes<'a>(
+    nodes: &'a [Node],
+    pod: &Pod,
+    ctx: &FilterContext,
+    plugins: &[Box<dyn FilterPlugin>],
+) -> Vec<&'a Node> {
+    // Claims used exclusively by another pod rule out every node
+    if !is_pod_volume_access_fulfilled(pod, ctx) {
+        return vec![];
+    }
 
-    // Combine all container requests into a map of resource names to quantities
-    let container_requests: BTreeMap<String, ParsedQuantity> = pod_spec
-        .containers
+    nodes
         .iter()
-        .filter_map(|container| {
-            let Some(resources) = &container.resources else { return None; };
-            let Some(requests) = &resources.requests else { return None; };
+        // Filter schedulable nodes
+        .filter(|node| is_node_schedulable(node))
+        // Filter nodes that have enough allocatable resources for pod
+        .filter(|node| is_pod_allocatable(node, pod))
+        // Filter nodes with room for pod next to their headroom and reservations
+        .filter(|node| is_pod_held_capacity_fulfilled(node, pod, ctx))
+        // Filter nodes that have enough network bandwidth left for pod
+        .filter(|node| is_pod_bandwidth_allocatable(node, pod, ctx))
+        // Filter nodes fulfilling taint toleration
+        .filter(|node| is_pod_taint_toleration_fulfilled(node, pod))
+        // Filter nodes fulfilling affinities
+        .filter(|node| is_pod_affinity_fulfilled(node, pod))
+        // Filter nodes fulfilling anti-affinities
+        .filter(|node| is_pod_anti_affinity_fulfilled(node, pod))
+        // Filter nodes the bound volumes of the pod can be attached to
+        .filter(|node| is_pod_volume_topology_fulfilled(node, pod, ctx.volumes))
+        // Filter nodes the unbound claims of the pod can get a volume on
+        .filter(|node| is_pod_volume_binding_fulfilled(node, pod, ctx.volumes))
+        // Filter nodes with room for the CSI volumes of the pod
+        .filter(|node| is_pod_csi_volume_limit_fulfilled(node, pod, ctx))
+        // Filter nodes passing the filter plugins of the profile
+        .filter(|node| plugins.iter().all(|plugin| plugin.filter(pod, node)))
+        .collect()
+}
 
-            Some(requests.iter()
-				.filter_map(|(k, v)| {
-					let Ok(v): Result<ParsedQuantity, ParseQuantityError> = v.try_into() else { return None };
+pub(crate) fn is_pod_allocatable(node: &Node, pod: &Pod) -> bool {
+    // Parse allocatable quantities
+    let Some(allocatable) = node_allocatable(node) else { return false };
 
-					Some((k.clone(), v))
-				}))
-        })
-        .flatten()
-        .collect();
+    // If there is no pod.spec one cannot make any allocation related decisions
+    if pod.spec.is_none() {
+        return false;
+    }
+
+    // Combine all container requests into a map of resource names to quantities
+    let container_requests = pod_requests(pod);
 
     // If all container requests quantities are smaller than the corresponding
     // allocatable quantities, then the pod is schedulable to the node
@@ -55,6 +133,41 @@ pub(crate) fn is_pod_allocatable(node: &Node, pod: &Pod) -> bool {
     true
 }
 
+// The bandwidth the pod requests must fit into the bandwidth the node advertises next to
+// the bandwidth reserved by the pods on the node. Nodes advertising no bandwidth are not
+// limited.
+pub(crate) fn is_pod_bandwidth_allocatable(node: &Node, pod: &Pod, ctx: &FilterContext) -> bool {
+    free_after_placement(node, ctx.node_pods(node), pod)
+        .into_iter()
+        .flatten()
+        .all(|free| free >= 0.0)
+}
+
+// The pod must fit next to the pods on the node into the allocatable resources less the
+// capacity held back from it. Nodes holding nothing back are left to the algorithms, which
+// account for the pods on the node themselves.
+pub(crate) fn is_pod_held_capacity_fulfilled(node: &Node, pod: &Pod, ctx: &FilterContext) -> bool {
+    let node_pods = ctx.node_pods(node);
+    let held = ctx.capacity.held(node, node_pods, pod);
+    if held.is_empty() {
+        return true;
+    }
+
+    let Some(allocatable) = node_allocatable(node) else { return false };
+    let requested = requested_after_placement(node_pods, &pod_requests(pod));
+
+    held.iter().all(|(resource, held)| {
+        let quantity = |quantities: &BTreeMap<String, ParsedQuantity>| {
+            quantities
+                .get(resource)
+                .and_then(ParsedQuantity::to_bytes_f64)
+                .unwrap_or_default()
+        };
+
+        quantity(&requested) + held <= quantity(&allocatable)
+    })
+}
+
 pub(crate) fn is_node_schedulable(node: &Node) -> bool {
     let Some(spec) = &node.spec else { return false };
     !spec.unschedulable.unwrap_or(false)
@@ -81,11 +194,18 @@ pub(crate) fn is_pod_affinity_fulfilled(node: &Node, pod: &Pod) -> bool {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use k8s_openapi::{
         api::core::v1::{Container, NodeSpec, NodeStatus, PodSpec, ResourceRequirements},
         apimachinery::pkg::api::resource::Quantity,
     };
 
+    use crate::{
+        config::HeadroomConfig,
+        scheduler::testing::{annotated, node, pod},
+    };
+
     use super::*;
 
     #[test]
@@ -236,4 +356,74 @@ mod tests {
 
         assert!(!is_pod_allocatable(&node, &pod));
     }
+
+    #[test]
+    fn test_pod_held_capacity_fulfilled() {
+        let node = node("a", &[("cpu", "4"), ("memory", "4Gi")]);
+        let state = BTreeMap::from_iter(vec![(
+            "a".to_string(),
+            vec![pod("existing", &[("cpu", "2")])],
+        )]);
+        let volumes = VolumeState::default();
+        let capacity = HeldCapacity::new(
+            &[HeadroomConfig {
+                node_selector: Default::default(),
+                resources: BTreeMap::from_iter(vec![("cpu".to_string(), "25%".to_string())]),
+            }],
+            vec![],
+        );
+        let ctx = FilterContext {
+            state: &state,
+            volumes: &volumes,
+            capacity: &capacity,
+        };
+
+        assert!(is_pod_held_capacity_fulfilled(
+            &node,
+            &pod("fitting", &[("cpu", "1")]),
+            &ctx
+        ));
+        assert!(!is_pod_held_capacity_fulfilled(
+            &node,
+            &pod("exceeding", &[("cpu", "1500m")]),
+            &ctx
+        ));
+    }
+
+    #[test]
+    fn test_pod_bandwidth_allocatable() {
+        let node = annotated(
+            node("a", &[]),
+            &[("network.scheduling/ingress-bandwidth", "1G")],
+        );
+        let existing = annotated(
+            pod("existing", &[]),
+            &[("kubernetes.io/ingress-bandwidth", "600M")],
+        );
+        let state = BTreeMap::from_iter(vec![("a".to_string(), vec![existing])]);
+        let volumes = VolumeState::default();
+        let capacity = HeldCapacity::default();
+        let ctx = FilterContext {
+            state: &state,
+            volumes: &volumes,
+            capacity: &capacity,
+        };
+
+        let fitting = annotated(
+            pod("fitting", &[]),
+            &[("kubernetes.io/ingress-bandwidth", "400M")],
+        );
+        let exceeding = annotated(
+            pod("exceeding", &[]),
+            &[("kubernetes.io/ingress-bandwidth", "500M")],
+        );
+
+        assert!(is_pod_bandwidth_allocatable(&node, &fitting, &ctx));
+        assert!(!is_pod_bandwidth_allocatable(&node, &exceeding, &ctx));
+        assert!(is_pod_bandwidth_allocatable(
+            &Node::default(),
+            &exceeding,
+            &ctx
+        ));
+    }
 }
@@ -0,0 +1,540 @@
+// Gang scheduling of pod groups: the pods of a group are only bound once at least the
+// minimum number of members of the group can be placed, so a distributed job never ends up
+// with a partial set of workers holding on to resources
+//
+// Pods join a group through labels:
+//   pod-group.scheduling/name: <name of the group within the namespace of the pod>
+//   pod-group.scheduling/min-available: <number of members that have to be placed together>
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use color_eyre::Result;
+use k8s_openapi::api::core::v1::Pod;
+
+use crate::{
+    config::Profile,
+    scheduler::{
+        algorithms::clone_world, lock, queue::pod_key, schedule_blocking, Reason, TargetState,
+        WorldState,
+    },
+    Algorithm,
+};
+
+pub(crate) const POD_GROUP_NAME_LABEL: &str = "pod-group.scheduling/name";
+pub(crate) const POD_GROUP_MIN_AVAILABLE_LABEL: &str = "pod-group.scheduling/min-available";
+
+// Pod groups waiting for enough members to be placed, similar to the Permit stage of the
+// upstream scheduling framework. A group waits from the first run it could not be admitted
+// in until the permit waiting time expires. Meanwhile its placed members are reserved on
+// their nodes without being bound, and once enough members are placed the group is admitted
+// along with them. When the waiting time expires the group is rejected, its reservations are
+// released and it starts waiting anew on its next attempt.
+#[derive(Debug, Default)]
+pub(crate) struct PodGroupPermits {
+    waiting: BTreeMap<String, WaitingGroup>,
+}
+
+#[derive(Debug)]
+struct WaitingGroup {
+    expires_at: Instant,
+    // Members reserved on their nodes
+    reserved: BTreeSet<String>,
+}
+
+// Member of a waiting pod group reserved on its node until the permit of the group expires
+pub(crate) struct ReservedMember {
+    pub(crate) node_name: String,
+    pub(crate) pod: Pod,
+    pub(crate) expires_at: Instant,
+}
+
+impl PodGroupPermits {
+    // Reserve the members of the batch placed by this run for every pod group with fewer
+    // than min-available members placed, taking them out of the target state, and move them
+    // back to the unscheduled pods once the group waited too long. The members reserved by
+    // earlier runs are placed along with admitted groups.
+    pub(crate) fn permit(
+        &mut self,
+        target: TargetState,
+        batch: &[Pod],
+        waiting_time: Duration,
+        now: Instant,
+    ) -> (TargetState, Vec<ReservedMember>) {
+        let batch: BTreeSet<String> = batch.iter().map(pod_key).collect();
+        let pending = pending_groups(&target, &batch);
+
+        let mut admitted: BTreeSet<String> = BTreeSet::new();
+        let mut waiting: BTreeSet<String> = BTreeSet::new();
+        let mut rejected: BTreeMap<String, Reason> = BTreeMap::new();
+        for (group, (placed, min_available)) in &pending {
+            if placed >= min_available {
+                if let Some(waiting) = self.waiting.remove(group) {
+                    admitted.extend(waiting.reserved);
+                }
+                continue;
+            }
+
+            let expires_at = self
+                .waiting
+                .entry(group.clone())
+                .or_insert_with(|| WaitingGroup {
+                    expires_at: now + waiting_time,
+                    reserved: BTreeSet::new(),
+                })
+                .expires_at;
+            if expires_at <= now {
+                log::info!(
+                    "Rejecting pod group {group}: {placed} of {min_available} members placed within {waiting_time:?}"
+                );
+                self.waiting.remove(group);
+                rejected.insert(group.clone(), Reason::PodGroupTimeout);
+            } else {
+                log::debug!(
+                    "Pod group {group} waiting for members: {placed} of {min_available} placed"
+                );
+                waiting.insert(group.clone());
+            }
+        }
+
+        // Forget groups that are neither pending nor holding reservations anymore
+        self.waiting.retain(|group, waiting| {
+            pending.contains_key(group)
+                || (!waiting.reserved.is_empty() && waiting.expires_at > now)
+        });
+
+        let TargetState {
+            unscheduled_pods,
+            mut state,
+        } = hold_back(target, &batch, &rejected);
+
+        let mut reserved = vec![];
+        for (node_name, node_pods) in &mut state {
+            let mut kept = vec![];
+            for mut pod in std::mem::take(node_pods) {
+                let key = pod_key(&pod);
+                let group = pod_group(&pod)
+                    .map(|(group, _)| group)
+                    .filter(|group| batch.contains(&key) && waiting.contains(group));
+
+                if let Some(group) = group.and_then(|group| self.waiting.get_mut(&group)) {
+                    group.reserved.insert(key);
+                    pod.spec.get_or_insert_with(Default::default).node_name =
+                        Some(node_name.clone());
+                    reserved.push(ReservedMember {
+                        node_name: node_name.clone(),
+                        pod,
+                        expires_at: group.expires_at,
+                    });
+                    continue;
+                }
+
+                // Reserved members of admitted groups are placed like the rest of the group
+                if admitted.contains(&key) {
+                    if let Some(spec) = pod.spec.as_mut() {
+                        spec.node_name = None;
+                    }
+                }
+                kept.push(pod);
+            }
+            *node_pods = kept;
+        }
+
+        (
+            TargetState {
+                unscheduled_pods,
+                state,
+            },
+            reserved,
+        )
+    }
+}
+
+// Place the batch of the world and reserve or reject the members of pod groups that cannot
+// be placed as a whole yet. When groups are rejected, the pods of the batch outside of pod
+// groups are placed again without them, so they are not kept from the capacity the members
+// of the rejected groups took up.
+pub(crate) async fn place_batch(
+    algorithm: &Algorithm,
+    world: WorldState,
+    profile: &Profile,
+    permits: &Mutex<PodGroupPermits>,
+) -> Result<(TargetState, Vec<ReservedMember>)> {
+    let batch = world.unscheduled_pods.items.clone();
+    let mut retry = clone_world(&world);
+    let waiting_time = Duration::from_secs(profile.pod_group.permit_waiting_time_seconds);
+
+    let target = schedule_blocking(algorithm, world, profile).await?;
+    let (permitted, reserved) = lock(permits)?.permit(target, &batch, waiting_time, Instant::now());
+
+    let keys: BTreeSet<String> = batch.iter().map(pod_key).collect();
+    let is_ungrouped = |pod: &Pod| pod_group(pod).is_none() && keys.contains(&pod_key(pod));
+    let rejected = permitted
+        .unscheduled_pods
+        .iter()
+        .any(|(_, reason)| matches!(reason, Reason::PodGroupTimeout));
+    if !rejected || !batch.iter().any(is_ungrouped) {
+        return Ok((permitted, reserved));
+    }
+
+    // Members of the other groups keep their placements and reservations
+    for (node_name, node_pods) in &mut retry.state {
+        node_pods.items = permitted
+            .state
+            .get(node_name)
+            .into_iter()
+            .flatten()
+            .filter(|pod| !is_ungrouped(pod))
+            .chain(
+                reserved
+                    .iter()
+                    .filter(|member| member.node_name == *node_name)
+                    .map(|member| &member.pod),
+            )
+            .cloned()
+            .collect();
+    }
+    retry.unscheduled_pods.items = batch
+        .iter()
+        .filter(|pod| is_ungrouped(pod))
+        .cloned()
+        .collect();
+
+    let mut target = schedule_blocking(algorithm, retry, profile).await?;
+    target.unscheduled_pods.extend(
+        permitted
+            .unscheduled_pods
+            .into_iter()
+            .filter(|(pod, _)| !is_ungrouped(pod)),
+    );
+
+    Ok((target, reserved))
+}
+
+// Pod group of a pod, qualified by its namespace, and the minimum number of its members
+// that have to be placed together
+pub(crate) fn pod_group(pod: &Pod) -> Option<(String, usize)> {
+    let labels = pod.metadata.labels.as_ref()?;
+    let name = labels.get(POD_GROUP_NAME_LABEL)?;
+
+    let min_available = match labels.get(POD_GROUP_MIN_AVAILABLE_LABEL) {
+        Some(value) => value.parse().unwrap_or_else(|_| {
+            log::warn!("Invalid {POD_GROUP_MIN_AVAILABLE_LABEL} label {value:?} on pod group {name}, assuming 1");
+            1
+        }),
+        None => 1,
+    };
+
+    Some((
+        format!(
+            "{}/{}",
+            pod.metadata.namespace.as_deref().unwrap_or_default(),
+            name
+        ),
+        min_available,
+    ))
+}
+
+// Members placed onto a node, bound already or placed by this run, and min-available of each
+// pod group with members in the batch
+fn pending_groups(
+    target: &TargetState,
+    batch: &BTreeSet<String>,
+) -> BTreeMap<String, (usize, usize)> {
+    let mut placed: BTreeMap<String, usize> = BTreeMap::new();
+    let mut min_available: BTreeMap<String, usize> = BTreeMap::new();
+    for pod in target.state.values().flatten() {
+        let Some((group, min)) = pod_group(pod) else { continue };
+        *placed.entry(group.clone()).or_default() += 1;
+        min_available.insert(group, min);
+    }
+
+    let batch_pods = target
+        .state
+        .values()
+        .flatten()
+        .chain(target.unscheduled_pods.iter().map(|(pod, _)| pod))
+        .filter(|pod| batch.contains(&pod_key(pod)));
+
+    let mut pending: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for pod in batch_pods {
+        let Some((group, min)) = pod_group(pod) else { continue };
+        let placed = placed.get(&group).copied().unwrap_or_default();
+        let min_available = min_available.get(&group).copied().unwrap_or(min);
+        pending.insert(group, (placed, min_available));
+    }
+
+    pending
+}
+
+// Move the members of the rejected groups the run placed back to the unscheduled pods
+fn hold_back(
+    target: TargetState,
+    batch: &BTreeSet<String>,
+    rejected: &BTreeMap<String, Reason>,
+) -> TargetState {
+    let TargetState {
+        mut unscheduled_pods,
+        mut state,
+    } = target;
+
+    for node_pods in state.values_mut() {
+        let mut kept = vec![];
+        for pod in std::mem::take(node_pods) {
+            let reason = pod_group(&pod)
+                .filter(|_| batch.contains(&pod_key(&pod)))
+                .and_then(|(group, _)| rejected.get(&group));
+
+            match reason {
+                Some(reason) => unscheduled_pods.push((pod, reason.clone())),
+                None => kept.push(pod),
+            }
+        }
+        *node_pods = kept;
+    }
+
+    TargetState {
+        unscheduled_pods,
+        state,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::PodGroupArgs,
+        scheduler::testing::{node, pod, world},
+    };
+
+    use super::*;
+
+    fn member(name: &str, group: &str, min_available: &str) -> Pod {
+        let mut pod = pod(name, &[("cpu", "1")]);
+        pod.metadata.labels = Some(BTreeMap::from_iter(vec![
+            (POD_GROUP_NAME_LABEL.to_string(), group.to_string()),
+            (
+                POD_GROUP_MIN_AVAILABLE_LABEL.to_string(),
+                min_available.to_string(),
+            ),
+        ]));
+        pod
+    }
+
+    fn bound(mut pod: Pod, node_name: &str) -> Pod {
+        if let Some(spec) = pod.spec.as_mut() {
+            spec.node_name = Some(node_name.to_string());
+        }
+        pod
+    }
+
+    // Target state with two of three workers of a job placed and the third left over
+    fn partial_target() -> TargetState {
+        TargetState {
+            unscheduled_pods: vec![(member("worker-3", "job", "3"), Reason::NoFeasibleNode)],
+            state: BTreeMap::from_iter(vec![
+                ("a".to_string(), vec![member("worker-1", "job", "3")]),
+                (
+                    "b".to_string(),
+                    vec![member("worker-2", "job", "3"), pod("other", &[])],
+                ),
+            ]),
+        }
+    }
+
+    // Pods of the partial target the run placed or failed to place
+    fn batch() -> Vec<Pod> {
+        ["worker-1", "worker-2", "worker-3"]
+            .into_iter()
+            .map(|name| member(name, "job", "3"))
+            .chain([pod("other", &[])])
+            .collect()
+    }
+
+    fn placed(target: &TargetState) -> Vec<String> {
+        target
+            .state
+            .values()
+            .flatten()
+            .filter_map(|pod| pod.metadata.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_permit_reserves_members_of_incomplete_group() {
+        let mut permits = PodGroupPermits::default();
+
+        let (target, reserved) = permits.permit(
+            partial_target(),
+            &batch(),
+            Duration::from_secs(60),
+            Instant::now(),
+        );
+
+        assert_eq!(placed(&target), vec!["other"]);
+        assert_eq!(target.unscheduled_pods.len(), 1);
+        let reserved: Vec<(&str, &str)> = reserved
+            .iter()
+            .map(|member| {
+                (
+                    member.node_name.as_str(),
+                    member.pod.metadata.name.as_deref().unwrap_or_default(),
+                )
+            })
+            .collect();
+        assert_eq!(reserved, [("a", "worker-1"), ("b", "worker-2")]);
+    }
+
+    #[test]
+    fn test_permit_places_reserved_members_of_admitted_group() {
+        let mut permits = PodGroupPermits::default();
+        let now = Instant::now();
+        let waiting_time = Duration::from_secs(60);
+
+        let (_, reserved) = permits.permit(partial_target(), &batch(), waiting_time, now);
+
+        // The next run finds the reserved members on their nodes and places the third one
+        let mut state: BTreeMap<String, Vec<Pod>> = BTreeMap::from_iter(vec![
+            ("a".to_string(), vec![member("worker-3", "job", "3")]),
+            ("b".to_string(), vec![]),
+        ]);
+        for member in reserved {
+            if let Some(node_pods) = state.get_mut(&member.node_name) {
+                node_pods.push(member.pod);
+            }
+        }
+        let (target, reserved) = permits.permit(
+            TargetState {
+                unscheduled_pods: vec![],
+                state,
+            },
+            &[member("worker-3", "job", "3")],
+            waiting_time,
+            now + Duration::from_secs(10),
+        );
+
+        assert!(reserved.is_empty());
+        let mut placements: Vec<&str> = target
+            .placements()
+            .filter_map(|(_, pod)| pod.metadata.name.as_deref())
+            .collect();
+        placements.sort();
+        assert_eq!(placements, ["worker-1", "worker-2", "worker-3"]);
+    }
+
+    #[test]
+    fn test_permit_admits_group_with_min_available_members() {
+        let mut permits = PodGroupPermits::default();
+        let mut target = partial_target();
+        target.unscheduled_pods.clear();
+        target
+            .state
+            .get_mut("a")
+            .unwrap()
+            .push(member("worker-3", "job", "3"));
+
+        let (target, reserved) =
+            permits.permit(target, &batch(), Duration::from_secs(60), Instant::now());
+
+        assert_eq!(placed(&target).len(), 4);
+        assert!(target.unscheduled_pods.is_empty());
+        assert!(reserved.is_empty());
+    }
+
+    #[test]
+    fn test_permit_counts_bound_members() {
+        let mut permits = PodGroupPermits::default();
+        let mut target = partial_target();
+        target.unscheduled_pods.clear();
+        target
+            .state
+            .get_mut("a")
+            .unwrap()
+            .push(bound(member("worker-0", "job", "3"), "a"));
+
+        let (target, _) = permits.permit(target, &batch(), Duration::from_secs(60), Instant::now());
+
+        assert_eq!(placed(&target).len(), 4);
+    }
+
+    #[test]
+    fn test_permit_rejects_group_after_waiting_time() {
+        let mut permits = PodGroupPermits::default();
+        let start = Instant::now();
+        let waiting_time = Duration::from_secs(60);
+
+        permits.permit(partial_target(), &batch(), waiting_time, start);
+        let (target, reserved) = permits.permit(
+            partial_target(),
+            &batch(),
+            waiting_time,
+            start + waiting_time,
+        );
+        assert!(reserved.is_empty());
+        assert!(target
+            .unscheduled_pods
+            .iter()
+            .any(|(_, reason)| matches!(reason, Reason::PodGroupTimeout)));
+
+        // The next attempt waits anew
+        let (target, _) = permits.permit(
+            partial_target(),
+            &batch(),
+            waiting_time,
+            start + waiting_time + Duration::from_secs(1),
+        );
+        assert!(!target
+            .unscheduled_pods
+            .iter()
+            .any(|(_, reason)| matches!(reason, Reason::PodGroupTimeout)));
+    }
+
+    #[test]
+    fn test_permit_keeps_members_placed_by_earlier_runs() {
+        let mut permits = PodGroupPermits::default();
+
+        // worker-1 was placed by an earlier run and is still assumed, this run only tried
+        // worker-3
+        let (target, _) = permits.permit(
+            partial_target(),
+            &[member("worker-3", "job", "3")],
+            Duration::from_secs(60),
+            Instant::now(),
+        );
+
+        assert_eq!(placed(&target), vec!["worker-1", "worker-2", "other"]);
+        assert_eq!(target.unscheduled_pods.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_place_batch_gives_back_capacity_of_rejected_groups() {
+        let allocatable = [("cpu", "4"), ("memory", "4Gi")];
+        let world = world(
+            vec![node("a", &allocatable), node("b", &allocatable)],
+            vec![
+                member("worker-1", "job", "3"),
+                member("worker-2", "job", "3"),
+                pod("other", &[("cpu", "3")]),
+            ],
+        );
+        let permits = Mutex::new(PodGroupPermits::default());
+        let profile = Profile {
+            pod_group: PodGroupArgs {
+                permit_waiting_time_seconds: 0,
+            },
+            ..Default::default()
+        };
+
+        let (target, reserved) = place_batch(&Algorithm::BinPacking, world, &profile, &permits)
+            .await
+            .unwrap();
+        assert!(reserved.is_empty());
+
+        // Without the workers holding on to node a, the other pod is packed onto it
+        assert_eq!(placed(&target), vec!["other"]);
+        assert_eq!(target.state["a"].len(), 1);
+        assert_eq!(target.unscheduled_pods.len(), 2);
+    }
+}
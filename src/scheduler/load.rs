@@ -0,0 +1,323 @@
+// Actual usage of nodes as reported by the metrics.k8s.io API of the metrics server,
+// averaged over a window, so that the LoadAware plugins can avoid nodes running hot no
+// matter how little the pods placed on them request
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use color_eyre::Result;
+use k8s_openapi::{
+    api::core::v1::{Node, Pod},
+    apimachinery::pkg::{api::resource::Quantity, apis::meta::v1::ObjectMeta},
+};
+use kube_quantity::ParsedQuantity;
+use serde::Deserialize;
+
+use crate::{
+    config::{LoadArgs, LoadThresholdArgs, Profile},
+    scheduler::{filters::FilterPlugin, resources::node_allocatable},
+};
+
+const NODE_METRICS_PATH: &str = "/apis/metrics.k8s.io/v1beta1/nodes";
+
+// Usage of each resource of a node, in the units of its allocatable resources
+type Usage = BTreeMap<String, f64>;
+
+#[derive(Debug, Deserialize)]
+struct NodeMetricsList {
+    items: Vec<NodeMetrics>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodeMetrics {
+    metadata: ObjectMeta,
+    #[serde(default)]
+    usage: BTreeMap<String, Quantity>,
+}
+
+// Where the usage of nodes is polled from
+pub(crate) enum MetricsSource {
+    // The metrics.k8s.io API of the cluster
+    Api(kube::Client),
+    // Any URL serving a NodeMetricsList
+    Url(String),
+}
+
+impl MetricsSource {
+    pub(crate) fn new(client: &kube::Client, args: &LoadArgs) -> Self {
+        match &args.metrics_url {
+            Some(url) => Self::Url(url.clone()),
+            None => Self::Api(client.clone()),
+        }
+    }
+
+    // Current usage of each node
+    async fn fetch(&self) -> Result<BTreeMap<String, Usage>> {
+        let list: NodeMetricsList = match self {
+            Self::Api(client) => {
+                let request = hyper::Request::get(NODE_METRICS_PATH).body(vec![])?;
+                client.request(request).await?
+            }
+            Self::Url(url) => {
+                let response = hyper::Client::new().get(url.parse()?).await?;
+                if !response.status().is_success() {
+                    color_eyre::eyre::bail!(
+                        "Node metrics request failed with status {}",
+                        response.status()
+                    );
+                }
+                let body = hyper::body::to_bytes(response.into_body()).await?;
+                serde_json::from_slice(&body)?
+            }
+        };
+
+        Ok(list
+            .items
+            .into_iter()
+            .filter_map(|metrics| {
+                let usage = metrics
+                    .usage
+                    .iter()
+                    .filter_map(|(resource, quantity)| {
+                        let quantity = ParsedQuantity::try_from(quantity).ok()?;
+                        Some((resource.clone(), quantity.to_bytes_f64()?))
+                    })
+                    .collect();
+
+                Some((metrics.metadata.name?, usage))
+            })
+            .collect())
+    }
+}
+
+// Usage samples of each node within the window
+pub(crate) struct LoadTracker {
+    window: Duration,
+    samples: BTreeMap<String, VecDeque<(Instant, Usage)>>,
+}
+
+impl LoadTracker {
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: BTreeMap::new(),
+        }
+    }
+
+    // Record the usage of the polled nodes and drop the samples that left the window, along
+    // with nodes no longer reported
+    pub(crate) fn record(&mut self, usage: BTreeMap<String, Usage>, now: Instant) {
+        for (node_name, usage) in usage {
+            self.samples
+                .entry(node_name)
+                .or_default()
+                .push_back((now, usage));
+        }
+
+        for samples in self.samples.values_mut() {
+            while matches!(samples.front(), Some((at, _)) if now.duration_since(*at) > self.window)
+            {
+                samples.pop_front();
+            }
+        }
+        self.samples.retain(|_, samples| !samples.is_empty());
+    }
+
+    // Average usage of each node over the window
+    pub(crate) fn snapshot(&self) -> NodeLoad {
+        let usage = self
+            .samples
+            .iter()
+            .map(|(node_name, samples)| {
+                let mut sums: BTreeMap<String, (f64, usize)> = BTreeMap::new();
+                for (_, usage) in samples {
+                    for (resource, value) in usage {
+                        let (sum, count) = sums.entry(resource.clone()).or_default();
+                        *sum += value;
+                        *count += 1;
+                    }
+                }
+
+                let averages = sums
+                    .into_iter()
+                    .map(|(resource, (sum, count))| (resource, sum / count as f64))
+                    .collect();
+
+                (node_name.clone(), averages)
+            })
+            .collect();
+
+        NodeLoad { usage }
+    }
+}
+
+// Averaged usage of each node at the start of a scheduler run
+#[derive(Debug, Clone, Default)]
+pub(crate) struct NodeLoad {
+    usage: BTreeMap<String, Usage>,
+}
+
+impl NodeLoad {
+    // Usage of the resource on the node as a fraction of its allocatable capacity, missing
+    // if the node reported no usage yet
+    pub(crate) fn utilization(&self, node: &Node, resource: &str) -> Option<f64> {
+        let usage = self
+            .usage
+            .get(node.metadata.name.as_ref()?)?
+            .get(resource)?;
+        let allocatable = node_allocatable(node)?.get(resource)?.to_bytes_f64()?;
+        if allocatable <= 0.0 {
+            return None;
+        }
+
+        Some(usage / allocatable)
+    }
+}
+
+// Usage of nodes polled in the background, shared by the scheduler runs
+#[derive(Clone)]
+pub(crate) struct LoadCache {
+    tracker: Arc<Mutex<LoadTracker>>,
+}
+
+impl LoadCache {
+    // Start polling the usage of nodes, unless no plugin of the profile needs it
+    pub(crate) fn start(client: &kube::Client, profile: &Profile) -> Self {
+        let args = &profile.load;
+        let cache = Self {
+            tracker: Arc::new(Mutex::new(LoadTracker::new(Duration::from_secs(
+                args.window_seconds,
+            )))),
+        };
+        if !profile.uses_load() {
+            return cache;
+        }
+
+        let source = MetricsSource::new(client, args);
+        let tracker = cache.tracker.clone();
+        let mut interval = tokio::time::interval(Duration::from_secs(args.interval_seconds));
+        tokio::spawn(async move {
+            loop {
+                interval.tick().await;
+                match source.fetch().await {
+                    Ok(usage) => match tracker.lock() {
+                        Ok(mut tracker) => tracker.record(usage, Instant::now()),
+                        Err(err) => log::error!("Failed to lock node load: {err}"),
+                    },
+                    Err(err) => log::warn!("Failed to fetch node metrics: {err:#}"),
+                }
+            }
+        });
+
+        cache
+    }
+
+    pub(crate) fn snapshot(&self) -> NodeLoad {
+        match self.tracker.lock() {
+            Ok(tracker) => tracker.snapshot(),
+            Err(err) => {
+                log::error!("Failed to lock node load: {err}");
+                NodeLoad::default()
+            }
+        }
+    }
+}
+
+// Reject nodes whose usage of a resource exceeds its threshold. Nodes without usage pass, so
+// new nodes are not held back until their first metrics arrive.
+pub(crate) struct LoadThreshold {
+    thresholds: BTreeMap<String, f64>,
+    load: NodeLoad,
+}
+
+impl LoadThreshold {
+    pub(crate) fn new(args: &LoadThresholdArgs, load: &NodeLoad) -> Self {
+        Self {
+            thresholds: args.thresholds.clone(),
+            load: load.clone(),
+        }
+    }
+}
+
+impl FilterPlugin for LoadThreshold {
+    fn filter(&self, _pod: &Pod, node: &Node) -> bool {
+        self.thresholds.iter().all(|(resource, threshold)| {
+            !matches!(self.load.utilization(node, resource), Some(utilization) if utilization > *threshold)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::scheduler::testing::{node, pod, stand_in};
+
+    use super::*;
+
+    fn usage(cpu: f64) -> BTreeMap<String, Usage> {
+        BTreeMap::from_iter(vec![(
+            "a".to_string(),
+            BTreeMap::from_iter(vec![("cpu".to_string(), cpu)]),
+        )])
+    }
+
+    #[test]
+    fn test_usage_is_averaged_over_window() {
+        let mut tracker = LoadTracker::new(Duration::from_secs(60));
+        let now = Instant::now();
+        let node = node("a", &[("cpu", "4")]);
+
+        tracker.record(usage(1.0), now);
+        tracker.record(usage(3.0), now + Duration::from_secs(30));
+        assert_eq!(tracker.snapshot().utilization(&node, "cpu"), Some(0.5));
+
+        // The first sample leaves the window
+        tracker.record(usage(4.0), now + Duration::from_secs(90));
+        assert_eq!(tracker.snapshot().utilization(&node, "cpu"), Some(0.875));
+        assert_eq!(tracker.snapshot().utilization(&node, "memory"), None);
+    }
+
+    #[test]
+    fn test_threshold_filter() {
+        let mut tracker = LoadTracker::new(Duration::from_secs(60));
+        tracker.record(usage(3.8), Instant::now());
+        let filter = LoadThreshold::new(
+            &LoadThresholdArgs {
+                thresholds: BTreeMap::from_iter(vec![("cpu".to_string(), 0.9)]),
+            },
+            &tracker.snapshot(),
+        );
+
+        assert!(!filter.filter(&pod("p", &[]), &node("a", &[("cpu", "4")])));
+        assert!(filter.filter(&pod("p", &[]), &node("a", &[("cpu", "8")])));
+        assert!(filter.filter(&pod("p", &[]), &node("b", &[("cpu", "4")])));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_from_stand_in() {
+        let url = stand_in(|_, _| {
+            json!({
+                "kind": "NodeMetricsList",
+                "apiVersion": "metrics.k8s.io/v1beta1",
+                "items": [{
+                    "metadata": { "name": "a" },
+                    "timestamp": "2023-07-01T00:00:00Z",
+                    "window": "20s",
+                    "usage": { "cpu": "1500m", "memory": "2Gi" }
+                }]
+            })
+        });
+
+        let usage = MetricsSource::Url(format!("{url}{NODE_METRICS_PATH}"))
+            .fetch()
+            .await
+            .unwrap();
+
+        assert_eq!(usage["a"]["cpu"], 1.5);
+        assert_eq!(usage["a"]["memory"], 2.0 * 1024.0 * 1024.0 * 1024.0);
+    }
+}
@@ -1,8 +1,26 @@
 mod algorithms;
+mod bandwidth;
+mod cache;
+pub(crate) mod capacity;
+mod events;
+pub(crate) mod extenders;
 mod filters;
+mod gang;
+mod load;
+mod queue;
+mod report;
+mod resources;
+mod scores;
+mod selectors;
+mod shadow;
+#[cfg(test)]
+mod testing;
+pub(crate) mod volumes;
+pub(crate) mod wasm;
 
 use std::{
     collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard},
     time::{Duration, Instant},
 };
 
@@ -11,12 +29,27 @@ use futures::{StreamExt, TryStreamExt};
 use k8s_openapi::api::core::v1::{Node, Pod};
 use kube::{
     api::ListParams,
-    core::ObjectList,
-    runtime::{reflector, watcher, WatchStreamExt},
+    core::{ListMeta, ObjectList},
+    runtime::{reflector, watcher},
     Api, Client,
 };
 
-use crate::Cli;
+use crate::{
+    config::{Profile, SchedulerConfig},
+    reconciler::{bind_pods, BindError},
+    scheduler::{
+        cache::AssumeCache,
+        capacity::{apply_overcommit, HeldCapacity, ReservationCache},
+        events::{AssignedPodTracker, ClusterEvent, NodeTracker},
+        gang::{place_batch, PodGroupPermits},
+        load::{LoadCache, NodeLoad},
+        queue::{pod_key, SchedulingQueue},
+        report::report_dry_run,
+        shadow::{run_shadow, ShadowMetrics},
+        volumes::{assign_volumes, ClaimBinding, VolumeCache, VolumeState},
+    },
+    Algorithm, Cli, DryRun,
+};
 
 pub(crate) struct SchedulingParameters {
     pub client: Client,
@@ -32,12 +65,35 @@ pub(crate) struct WorldState {
     pub(crate) unscheduled_pods: ObjectList<Pod>,
     // State of the node to pod task scheduling
     pub(crate) state: BTreeMap<String, ObjectList<Pod>>,
+    // Claims, volumes, storage classes and CSI nodes the volume filters consult
+    pub(crate) volumes: VolumeState,
+    // Actual usage of the nodes the LoadAware plugins consult
+    pub(crate) load: NodeLoad,
+    // Headroom and reservations the filters hold back from pods
+    pub(crate) capacity: HeldCapacity,
+}
+
+// Cluster state kept up to date in the background and snapshotted by each scheduler run
+#[derive(Clone)]
+pub(crate) struct Caches {
+    pub(crate) volumes: VolumeCache,
+    pub(crate) load: LoadCache,
+    pub(crate) reservations: ReservationCache,
 }
 
+#[derive(Debug, Clone)]
 pub(crate) enum Reason {
     NoFeasibleNode,
     NodeName,
     NodePods,
+    // The pod group of the pod did not get enough members placed within its waiting time
+    PodGroupTimeout,
+    // The algorithm failed on the batch of the pod
+    AlgorithmFailed,
+    // A non-ignorable extender failed to filter the nodes for the pod
+    ExtenderFailed,
+    // The volume the pod was placed with went to another pod placed by the same run
+    VolumeConflict,
 }
 
 // Will be used by the reconciler to change pod node bindings and perform preemption
@@ -48,10 +104,33 @@ pub(crate) struct TargetState {
     pub(crate) state: BTreeMap<String, Vec<Pod>>,
 }
 
+impl TargetState {
+    // Pods placed by the scheduler run that are not bound to their node yet
+    pub(crate) fn placements(&self) -> impl Iterator<Item = (&str, &Pod)> {
+        self.state.iter().flat_map(|(node_name, node_pods)| {
+            node_pods
+                .iter()
+                .filter(|pod| {
+                    pod.spec
+                        .as_ref()
+                        .and_then(|spec| spec.node_name.as_ref())
+                        .is_none()
+                })
+                .map(move |pod| (node_name.as_str(), pod))
+        })
+    }
+}
+
 pub(crate) async fn run_scheduler(cli: Cli) -> Result<()> {
     // Infer the runtime environment and try to create a Kubernetes Client
     let client = Client::try_default().await?;
 
+    let config = match &cli.config {
+        Some(path) => SchedulerConfig::load(path)?,
+        None => SchedulerConfig::default(),
+    };
+    let profile = config.profile(&cli.scheduler_name);
+
     let pods: Api<Pod> = Api::all(client.clone());
 
     // List params to only obtain pods that are unscheduled/not bound to a node and
@@ -60,7 +139,59 @@ pub(crate) async fn run_scheduler(cli: Cli) -> Result<()> {
         .fields(format!("spec.schedulerName={},spec.nodeName=", cli.scheduler_name).as_str());
 
     let (_, pod_writer) = reflector::store();
-    let pod_reflector = reflector(pod_writer, watcher(pods.clone(), unscheduled_lp.clone()));
+    let pod_reflector = reflector(pod_writer, watcher(pods.clone(), unscheduled_lp));
+
+    // Watches of the nodes and the pods bound to them, whose changes could make pods
+    // schedulable that failed to schedule before
+    let mut node_watcher = watcher(Api::<Node>::all(client.clone()), ListParams::default()).boxed();
+    let mut assigned_pod_watcher = watcher(
+        pods.clone(),
+        ListParams::default().fields("spec.nodeName!="),
+    )
+    .boxed();
+    let mut node_tracker = NodeTracker::default();
+    let mut assigned_pod_tracker = AssignedPodTracker::default();
+
+    // Pods waiting to be scheduled, fed by the reflector and drained by the scheduler runs
+    let queue = Arc::new(Mutex::new(SchedulingQueue::new(profile.queue.clone())));
+
+    // Placements of previous runs whose binding was not observed yet
+    let cache = Arc::new(Mutex::new(AssumeCache::new(Duration::from_secs(
+        profile.cache.assumed_pod_ttl_seconds,
+    ))));
+
+    // Volume objects, usage of nodes and reservations of the cluster, snapshotted by each
+    // scheduler run
+    let caches = Caches {
+        volumes: VolumeCache::start(&client),
+        load: LoadCache::start(&client, &profile),
+        reservations: ReservationCache::start(&client, &profile),
+    };
+
+    // Pod groups waiting for members, shared by the consecutive scheduler runs
+    let permits = Arc::new(Mutex::new(PodGroupPermits::default()));
+
+    // Compare the decisions of another scheduler with the ones of the algorithm
+    let registry = prometheus::Registry::new();
+    if let Some(shadowed_scheduler_name) = cli.shadow_scheduler_name.clone() {
+        let metrics = ShadowMetrics::new(&registry)?;
+        tokio::spawn(run_shadow(
+            client.clone(),
+            cli.algorithm.clone(),
+            profile.clone(),
+            shadowed_scheduler_name,
+            caches.clone(),
+            cli.shadow_report.clone(),
+            metrics,
+        ));
+    }
+    if let Some(address) = cli.metrics_address {
+        tokio::spawn(async move {
+            if let Err(err) = crate::metrics::serve(address, registry).await {
+                log::error!("Metrics server failed: {err:#}");
+            }
+        });
+    }
 
     let mut handle: tokio::task::JoinHandle<Result<()>> = tokio::spawn(async { Ok(()) });
 
@@ -68,12 +199,63 @@ pub(crate) async fn run_scheduler(cli: Cli) -> Result<()> {
 
     let mut last_run = Instant::now();
 
-    let mut pod_reflector = pod_reflector.applied_objects().boxed();
-    while (pod_reflector.try_next().await?).is_some() {
+    // Interval in which pods whose backoff expired are moved back to the active queue
+    let mut flush_interval = tokio::time::interval(Duration::from_secs(1));
+
+    let mut pod_reflector = pod_reflector.boxed();
+    loop {
+        tokio::select! {
+            event = pod_reflector.try_next() => {
+                let Some(event) = event? else { break };
+                let Some(event) = lock(&cache)?.filter_unscheduled(event) else { continue };
+                let mut queue = lock(&queue)?;
+                queue.handle_pod_event(event, Instant::now());
+                if !queue.flush(Instant::now()) {
+                    continue;
+                }
+            }
+            event = node_watcher.try_next() => {
+                let Some(event) = event? else { break };
+                // Only trigger a run when the change made pods active
+                if !requeue(&queue, node_tracker.handle(event))? {
+                    continue;
+                }
+            }
+            event = assigned_pod_watcher.try_next() => {
+                let Some(event) = event? else { break };
+                lock(&cache)?.observe_bound(&event);
+                if !requeue(&queue, assigned_pod_tracker.handle(event))? {
+                    continue;
+                }
+            }
+            _ = flush_interval.tick() => {
+                // Placements whose binding was not observed in time and reserved members of
+                // pod groups whose permit expired are scheduled again
+                let expired = lock(&cache)?.expire(Instant::now());
+                let mut queue = lock(&queue)?;
+                for pod in expired {
+                    queue.add(pod, Instant::now());
+                }
+
+                // Only trigger a run for pods leaving the backoff or unschedulable queue
+                // once the previous run finished
+                if !handle.is_finished() || !queue.flush(Instant::now()) {
+                    continue;
+                }
+            }
+        }
+
         let client = client.clone();
         let pods = pods.clone();
         let scheduler_name = cli.scheduler_name.clone();
-        let unscheduled_lp = unscheduled_lp.clone();
+        let algorithm = cli.algorithm.clone();
+        let dry_run = cli.dry_run;
+        let dry_run_output = cli.dry_run_output.clone();
+        let profile = profile.clone();
+        let queue = queue.clone();
+        let cache = cache.clone();
+        let permits = permits.clone();
+        let caches = caches.clone();
 
         // A timeout, after which a scheduler run is triggered anyways
         if last_run.elapsed() < Duration::from_secs(cli.debounce_duration) {
@@ -92,13 +274,13 @@ pub(crate) async fn run_scheduler(cli: Cli) -> Result<()> {
             // loop
             tokio::time::sleep(Duration::from_secs(cli.debounce_duration)).await;
 
-            let nodes: ObjectList<Node> = Api::all(client.clone())
+            let mut nodes: ObjectList<Node> = Api::all(client.clone())
                 // TODO: might potentially add some filtering here in the future
                 .list(&ListParams::default())
                 .await?;
 
             // Get a mapping of nodes and their pods
-            let state: BTreeMap<String, ObjectList<Pod>> = {
+            let mut state: BTreeMap<String, ObjectList<Pod>> = {
                 let mut state = BTreeMap::new();
                 for node in &nodes.items {
                     let Some(node_name) = &node.metadata.name else { continue };
@@ -112,25 +294,49 @@ pub(crate) async fn run_scheduler(cli: Cli) -> Result<()> {
                 state
             };
 
-            let schedule_state = WorldState {
-                nodes,
-                state,
-                unscheduled_pods: pods.list(&unscheduled_lp).await?,
-            };
+            // Take the batch only after the last await point in front of the algorithm, so
+            // aborting a debounced run never loses the pods it took from the queue
+            let batch = lock(&queue)?.pop_batch(Instant::now());
+
+            // Account for the pods placed by previous runs that are not bound yet
+            lock(&cache)?.add_to_state(&mut state);
 
-            if schedule_state.unscheduled_pods.items.is_empty() {
+            if batch.is_empty() {
                 return Err(color_eyre::eyre::eyre!(
                     "No unscheduled pods found after debouncing"
                 ));
             }
 
-            let target_state = match match cli.algorithm {
-                crate::Algorithm::BinPacking => {
-                    algorithms::bin_packing::schedule(schedule_state).await
-                }
-            } {
-                Ok(target_state) => target_state,
+            // Filters and scores see the allocatable resources scaled by the overcommit
+            // ratios
+            apply_overcommit(&mut nodes.items, &profile.overcommit);
+
+            // Kept to choose the volumes of the placed pods once the algorithm is done
+            let node_list = nodes.items.clone();
+            let volume_state = caches.volumes.snapshot();
+
+            let schedule_state = WorldState {
+                nodes,
+                state,
+                unscheduled_pods: ObjectList {
+                    metadata: ListMeta::default(),
+                    items: batch.clone(),
+                },
+                volumes: volume_state.clone(),
+                load: caches.load.snapshot(),
+                capacity: HeldCapacity::new(&profile.headroom, caches.reservations.snapshot()),
+            };
+
+            // Reserve the members of pod groups that cannot be placed as a whole yet
+            let placed = place_batch(&algorithm, schedule_state, &profile, &permits).await;
+            let (target_state, reserved) = match placed {
+                Ok(placed) => placed,
                 Err(err) => {
+                    let unscheduled_pods: Vec<(Pod, Reason)> = batch
+                        .iter()
+                        .map(|pod| (pod.clone(), Reason::AlgorithmFailed))
+                        .collect();
+                    lock(&queue)?.complete(&batch, &unscheduled_pods, Instant::now());
                     return Err(color_eyre::eyre::eyre!(
                         "Failed to obtain target_state: {:#?}",
                         err
@@ -138,6 +344,50 @@ pub(crate) async fn run_scheduler(cli: Cli) -> Result<()> {
                 }
             };
 
+            // Choose the volumes for the unbound claims of the placed pods
+            let (target_state, mut volume_bindings) =
+                assign_volumes(target_state, &node_list, &volume_state);
+
+            // Pods left unscheduled wait in the queue for their next attempt
+            lock(&queue)?.complete(&batch, &target_state.unscheduled_pods, Instant::now());
+
+            if dry_run.is_some() {
+                report_dry_run(&target_state, dry_run_output.as_deref())?;
+            }
+            if dry_run == Some(DryRun::Client) {
+                return Ok(());
+            }
+
+            // Placed pods count as bound until their binding is observed, reserved members
+            // of pod groups until their permit expires. Dry runs bind nothing, so their
+            // placements must not hold capacity in the following runs.
+            if dry_run.is_none() {
+                let mut cache = lock(&cache)?;
+                cache.assume(&target_state, Instant::now());
+                for member in &reserved {
+                    cache.reserve(&member.node_name, &member.pod, member.expires_at);
+                }
+            }
+
+            // Bind outside of the debounced run, so a following run cannot abort bindings
+            // in flight
+            let placements: Vec<(Pod, String, Vec<ClaimBinding>)> = target_state
+                .placements()
+                .map(|(node_name, pod)| {
+                    let bindings = volume_bindings.remove(&pod_key(pod)).unwrap_or_default();
+                    (pod.clone(), node_name.to_owned(), bindings)
+                })
+                .collect();
+            tokio::spawn(bind(
+                client,
+                scheduler_name,
+                placements,
+                profile.clone(),
+                dry_run == Some(DryRun::Server),
+                queue,
+                cache,
+            ));
+
             // TODO: Implement a reconciler
 
             Ok(())
@@ -146,3 +396,90 @@ pub(crate) async fn run_scheduler(cli: Cli) -> Result<()> {
 
     Ok(())
 }
+
+pub(crate) async fn schedule(
+    algorithm: &Algorithm,
+    schedule_state: WorldState,
+    profile: &Profile,
+) -> Result<TargetState> {
+    match algorithm {
+        Algorithm::BinPacking => algorithms::bin_packing::schedule(schedule_state, profile).await,
+        Algorithm::LeastAllocated => {
+            algorithms::least_allocated::schedule(schedule_state, profile).await
+        }
+        Algorithm::Optimal => algorithms::optimal::schedule(schedule_state, profile).await,
+        Algorithm::Annealing => algorithms::annealing::schedule(schedule_state, profile).await,
+    }
+}
+
+// Run the algorithm on the blocking thread pool, as the filter and score plugins it calls
+// are synchronous and WebAssembly plugins may use up their deadline on every node
+pub(crate) async fn schedule_blocking(
+    algorithm: &Algorithm,
+    schedule_state: WorldState,
+    profile: &Profile,
+) -> Result<TargetState> {
+    let algorithm = algorithm.clone();
+    let profile = profile.clone();
+    let runtime = tokio::runtime::Handle::current();
+
+    tokio::task::spawn_blocking(move || {
+        runtime.block_on(schedule(&algorithm, schedule_state, &profile))
+    })
+    .await?
+}
+
+// Bind the placed pods and undo the assumptions of the pods that could not be bound
+async fn bind(
+    client: Client,
+    scheduler_name: String,
+    placements: Vec<(Pod, String, Vec<ClaimBinding>)>,
+    profile: Profile,
+    dry_run: bool,
+    queue: Arc<Mutex<SchedulingQueue>>,
+    cache: Arc<Mutex<AssumeCache>>,
+) -> Result<()> {
+    let binder = extenders::binder(&profile);
+    let results = bind_pods(
+        client,
+        scheduler_name,
+        placements,
+        profile.binding,
+        binder.as_ref(),
+        dry_run,
+    )
+    .await;
+
+    for (pod, result) in results {
+        let Err(err) = result else { continue };
+        log::warn!("{err}");
+
+        lock(&cache)?.forget(&pod);
+        match err {
+            // The pod is bound elsewhere or gone, there is nothing left to schedule
+            BindError::Conflict { .. } | BindError::NotFound { .. } => {}
+            _ => lock(&queue)?.backoff(pod, Instant::now()),
+        }
+    }
+
+    Ok(())
+}
+
+// Pass cluster events on to the scheduling queue and report whether they made pods active
+fn requeue(queue: &Mutex<SchedulingQueue>, events: Vec<ClusterEvent>) -> Result<bool> {
+    let mut queue = lock(queue)?;
+    let now = Instant::now();
+
+    let mut activated = false;
+    for event in &events {
+        activated |= queue.on_cluster_event(event, now);
+    }
+
+    Ok(activated)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>> {
+    mutex
+        .lock()
+        .map_err(|err| color_eyre::eyre::eyre!("Failed to lock scheduler state: {err}"))
+}
@@ -0,0 +1,484 @@
+// Scheduling queue holding the pods waiting to be scheduled, modelled after the upstream
+// scheduling queue:
+// - active: pods taken by the next scheduler run
+// - backoff: pods that failed to schedule and wait for their backoff to expire
+// - unschedulable: pods that failed to schedule and wait for a cluster event that could
+//   make them schedulable, or for the maximum time a pod stays unschedulable
+//
+// Which cluster events could make a pod schedulable is decided by queueing hints, based on
+// the reason the pod failed to schedule.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, Instant},
+};
+
+use k8s_openapi::api::core::v1::Pod;
+use kube::runtime::watcher;
+
+use crate::{
+    config::QueueArgs,
+    scheduler::{events::ClusterEvent, gang::pod_group, Reason},
+};
+
+pub(crate) struct SchedulingQueue {
+    args: QueueArgs,
+    active: BTreeMap<String, Pod>,
+    // Pods with the time their backoff expires
+    backoff: BTreeMap<String, (Pod, Instant)>,
+    // Pods with the time and the reason their last scheduling attempt failed
+    unschedulable: BTreeMap<String, (Pod, Instant, Reason)>,
+    // Number of failed scheduling attempts of each pod
+    attempts: BTreeMap<String, u32>,
+    // Pods taken by the scheduler run in progress, until its outcome is recorded, with the
+    // latest update of each pod received meanwhile
+    in_flight: BTreeMap<String, Option<Pod>>,
+}
+
+impl SchedulingQueue {
+    pub(crate) fn new(args: QueueArgs) -> Self {
+        Self {
+            args,
+            active: BTreeMap::new(),
+            backoff: BTreeMap::new(),
+            unschedulable: BTreeMap::new(),
+            attempts: BTreeMap::new(),
+            in_flight: BTreeMap::new(),
+        }
+    }
+
+    // Keep the queue in sync with the watch of the unscheduled pods
+    pub(crate) fn handle_pod_event(&mut self, event: watcher::Event<Pod>, now: Instant) {
+        match event {
+            watcher::Event::Applied(pod) => self.add(pod, now),
+            watcher::Event::Deleted(pod) => self.delete(&pod),
+            watcher::Event::Restarted(pods) => {
+                let keys: BTreeSet<String> = pods.iter().map(pod_key).collect();
+                self.active.retain(|key, _| keys.contains(key));
+                self.backoff.retain(|key, _| keys.contains(key));
+                self.unschedulable.retain(|key, _| keys.contains(key));
+                self.attempts.retain(|key, _| keys.contains(key));
+                self.in_flight.retain(|key, _| keys.contains(key));
+
+                for pod in pods {
+                    self.add(pod, now);
+                }
+            }
+        }
+    }
+
+    // Add a new pod to the active queue or update a queued one. An update of an
+    // unschedulable pod might make it schedulable, so it moves on as after a cluster event.
+    // Updates of pods in the scheduler run in progress are kept until its outcome is
+    // recorded, so the pods are not taken by another run meanwhile. A new member of a pod
+    // group brings the rejected members of its group back, as the group can only be
+    // admitted when its members are scheduled together.
+    pub(crate) fn add(&mut self, pod: Pod, now: Instant) {
+        let key = pod_key(&pod);
+
+        if let Some(update) = self.in_flight.get_mut(&key) {
+            *update = Some(pod);
+        } else if let Some(queued) = self.active.get_mut(&key) {
+            *queued = pod;
+        } else if let Some((queued, _)) = self.backoff.get_mut(&key) {
+            *queued = pod;
+        } else if let Some((_, failed_at, _)) = self.unschedulable.remove(&key) {
+            self.requeue(key, pod, failed_at, now);
+        } else {
+            let group = pod_group(&pod).map(|(group, _)| group);
+            self.active.insert(key, pod);
+
+            if let Some(group) = group {
+                self.on_cluster_event(&ClusterEvent::PodGroupMemberAdded(group), now);
+            }
+        }
+    }
+
+    pub(crate) fn delete(&mut self, pod: &Pod) {
+        let key = pod_key(pod);
+
+        self.active.remove(&key);
+        self.backoff.remove(&key);
+        self.unschedulable.remove(&key);
+        self.attempts.remove(&key);
+        self.in_flight.remove(&key);
+    }
+
+    // Move the pods whose backoff expired and the pods that stayed unschedulable for too
+    // long to the active queue and report whether there are active pods
+    pub(crate) fn flush(&mut self, now: Instant) -> bool {
+        let expired: Vec<String> = self
+            .backoff
+            .iter()
+            .filter(|(_, (_, ready_at))| *ready_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            if let Some((pod, _)) = self.backoff.remove(&key) {
+                self.active.insert(key, pod);
+            }
+        }
+
+        let max_unschedulable =
+            Duration::from_secs(self.args.pod_max_in_unschedulable_pods_seconds);
+        let stale: Vec<String> = self
+            .unschedulable
+            .iter()
+            .filter(|(_, (_, failed_at, _))| now.duration_since(*failed_at) >= max_unschedulable)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in stale {
+            if let Some((pod, _, _)) = self.unschedulable.remove(&key) {
+                self.active.insert(key, pod);
+            }
+        }
+
+        !self.active.is_empty()
+    }
+
+    // Take all active pods for a scheduler run
+    pub(crate) fn pop_batch(&mut self, now: Instant) -> Vec<Pod> {
+        self.flush(now);
+
+        let batch = std::mem::take(&mut self.active);
+        self.in_flight
+            .extend(batch.keys().map(|key| (key.clone(), None)));
+
+        batch.into_values().collect()
+    }
+
+    // Record the outcome of a scheduler run: the pods left unscheduled wait in the
+    // unschedulable queue, or move on as after a cluster event if they were updated during
+    // the run. The others are done, and so are the pods deleted during the run.
+    pub(crate) fn complete(
+        &mut self,
+        batch: &[Pod],
+        unscheduled_pods: &[(Pod, Reason)],
+        now: Instant,
+    ) {
+        let unscheduled: BTreeMap<String, &Reason> = unscheduled_pods
+            .iter()
+            .map(|(pod, reason)| (pod_key(pod), reason))
+            .collect();
+
+        for pod in batch {
+            let key = pod_key(pod);
+            let Some(update) = self.in_flight.remove(&key) else { continue };
+
+            if let Some(reason) = unscheduled.get(&key) {
+                *self.attempts.entry(key.clone()).or_default() += 1;
+                match update {
+                    Some(update) => {
+                        self.requeue(key, update, now, now);
+                    }
+                    None => {
+                        self.unschedulable
+                            .insert(key, (pod.clone(), now, (*reason).clone()));
+                    }
+                }
+            } else {
+                self.attempts.remove(&key);
+            }
+        }
+    }
+
+    // Retry a pod whose placement could not be carried out once its backoff expires
+    pub(crate) fn backoff(&mut self, pod: Pod, now: Instant) {
+        let key = pod_key(&pod);
+        *self.attempts.entry(key.clone()).or_default() += 1;
+
+        let ready_at = now + self.backoff_duration(&key);
+        self.backoff.insert(key, (pod, ready_at));
+    }
+
+    // Move the unschedulable pods the event could make schedulable to the backoff queue,
+    // or straight to the active queue if their backoff expired already, and report whether
+    // any pods became active
+    pub(crate) fn on_cluster_event(&mut self, event: &ClusterEvent, now: Instant) -> bool {
+        let keys: Vec<String> = self
+            .unschedulable
+            .iter()
+            .filter(|(_, (pod, _, reason))| is_schedulable_after(event, pod, reason))
+            .map(|(key, _)| key.clone())
+            .collect();
+        if keys.is_empty() {
+            return false;
+        }
+        log::debug!("{event:?}, moving {} unschedulable pods", keys.len());
+
+        let mut activated = false;
+        for key in keys {
+            let Some((pod, failed_at, _)) = self.unschedulable.remove(&key) else { continue };
+            activated |= self.requeue(key, pod, failed_at, now);
+        }
+
+        activated
+    }
+
+    // Move a pod to the active queue if its backoff expired, to the backoff queue otherwise,
+    // and report whether it became active
+    fn requeue(&mut self, key: String, pod: Pod, failed_at: Instant, now: Instant) -> bool {
+        let ready_at = failed_at + self.backoff_duration(&key);
+
+        if ready_at <= now {
+            self.active.insert(key, pod);
+            true
+        } else {
+            self.backoff.insert(key, (pod, ready_at));
+            false
+        }
+    }
+
+    // Backoff doubling with each failed attempt, starting at the initial backoff and
+    // capped at the maximum backoff
+    fn backoff_duration(&self, key: &str) -> Duration {
+        let attempts = self.attempts.get(key).copied().unwrap_or_default();
+        if attempts == 0 {
+            return Duration::ZERO;
+        }
+
+        let initial = Duration::from_secs(self.args.pod_initial_backoff_seconds);
+        let max = Duration::from_secs(self.args.pod_max_backoff_seconds);
+
+        initial
+            .checked_mul(2u32.saturating_pow(attempts - 1))
+            .unwrap_or(max)
+            .min(max)
+    }
+}
+
+// Queueing hint: whether the event could make a pod schedulable that failed to schedule
+// for the given reason
+fn is_schedulable_after(event: &ClusterEvent, pod: &Pod, reason: &Reason) -> bool {
+    match (reason, event) {
+        // Rejected members of a pod group are worth another attempt as soon as the group grows
+        (Reason::PodGroupTimeout, ClusterEvent::PodGroupMemberAdded(group)) => {
+            matches!(pod_group(pod), Some((pod_group, _)) if &pod_group == group)
+        }
+        // Only pods constrained to nodes by labels care about label changes
+        (_, ClusterEvent::NodeLabelsChanged) => matches!(
+            &pod.spec,
+            Some(spec) if spec.node_selector.is_some() || spec.affinity.is_some()
+        ),
+        (_, ClusterEvent::PodGroupMemberAdded(_)) => false,
+        // Any other change adds capacity or nodes the pod may fit onto
+        (_, _) => true,
+    }
+}
+
+pub(crate) fn pod_key(pod: &Pod) -> String {
+    format!(
+        "{}/{}",
+        pod.metadata.namespace.as_deref().unwrap_or_default(),
+        pod.metadata.name.as_deref().unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scheduler::{gang::POD_GROUP_NAME_LABEL, resources::pod_requests, testing::pod};
+
+    use super::*;
+
+    fn queue() -> SchedulingQueue {
+        SchedulingQueue::new(QueueArgs::default())
+    }
+
+    fn unscheduled(pods: &[Pod]) -> Vec<(Pod, Reason)> {
+        pods.iter()
+            .map(|pod| (pod.clone(), Reason::NoFeasibleNode))
+            .collect()
+    }
+
+    fn names(pods: &[Pod]) -> Vec<String> {
+        pods.iter()
+            .filter_map(|pod| pod.metadata.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_unschedulable_pods_wait_for_cluster_event() {
+        let mut queue = queue();
+        let now = Instant::now();
+
+        queue.add(pod("p", &[]), now);
+        let batch = queue.pop_batch(now);
+        assert_eq!(names(&batch), vec!["p"]);
+
+        queue.complete(&batch, &unscheduled(&batch), now);
+        let later = now + Duration::from_secs(60);
+        assert!(!queue.flush(later));
+
+        queue.on_cluster_event(&ClusterEvent::NodeAdded, later);
+        assert_eq!(names(&queue.pop_batch(later)), vec!["p"]);
+    }
+
+    #[test]
+    fn test_backoff_grows_exponentially() {
+        let mut queue = queue();
+        let now = Instant::now();
+
+        queue.add(pod("p", &[]), now);
+        for (attempt, backoff) in [1, 2, 4, 8, 10, 10].into_iter().enumerate() {
+            let batch = queue.pop_batch(now);
+            assert_eq!(batch.len(), 1, "attempt {attempt}");
+
+            queue.complete(&batch, &unscheduled(&batch), now);
+            queue.on_cluster_event(&ClusterEvent::AssignedPodRemoved, now);
+
+            let backoff = Duration::from_secs(backoff);
+            assert!(!queue.flush(now + backoff - Duration::from_millis(1)));
+            assert!(queue.flush(now + backoff));
+            queue.flush(now);
+        }
+    }
+
+    #[test]
+    fn test_pods_deleted_during_run_are_not_requeued() {
+        let mut queue = queue();
+        let now = Instant::now();
+
+        queue.add(pod("p", &[]), now);
+        let batch = queue.pop_batch(now);
+        queue.delete(&pod("p", &[]));
+        queue.complete(&batch, &unscheduled(&batch), now);
+
+        queue.on_cluster_event(&ClusterEvent::NodeAdded, now + Duration::from_secs(60));
+        assert!(queue
+            .pop_batch(now + Duration::from_secs(60 * 60))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_updates_during_run_wait_for_its_outcome() {
+        let mut queue = queue();
+        let now = Instant::now();
+
+        queue.add(pod("placed", &[]), now);
+        queue.add(pod("unplaced", &[]), now);
+        let batch = queue.pop_batch(now);
+
+        // Updates and relists during the run do not hand its pods to another run
+        queue.add(pod("placed", &[("cpu", "1")]), now);
+        queue.handle_pod_event(
+            watcher::Event::Restarted(vec![pod("placed", &[]), pod("unplaced", &[("cpu", "1")])]),
+            now,
+        );
+        assert!(queue.pop_batch(now).is_empty());
+
+        // The placed pod is done, the unplaced one is retried with its update
+        queue.complete(&batch, &unscheduled(&batch[1..]), now);
+        let retried = queue.pop_batch(now + Duration::from_secs(1));
+        assert_eq!(names(&retried), ["unplaced"]);
+        assert!(pod_requests(&retried[0]).contains_key("cpu"));
+    }
+
+    #[test]
+    fn test_scheduled_pods_leave_the_queue() {
+        let mut queue = queue();
+        let now = Instant::now();
+
+        queue.add(pod("placed", &[]), now);
+        queue.add(pod("left", &[]), now);
+        let batch = queue.pop_batch(now);
+        let left: Vec<Pod> = batch
+            .iter()
+            .filter(|pod| pod.metadata.name.as_deref() == Some("left"))
+            .cloned()
+            .collect();
+        queue.complete(&batch, &unscheduled(&left), now);
+
+        queue.on_cluster_event(&ClusterEvent::NodeAdded, now + Duration::from_secs(60));
+        assert_eq!(
+            names(&queue.pop_batch(now + Duration::from_secs(60))),
+            vec!["left"]
+        );
+    }
+
+    #[test]
+    fn test_failed_binding_is_retried_after_backoff() {
+        let mut queue = queue();
+        let now = Instant::now();
+
+        queue.add(pod("p", &[]), now);
+        let batch = queue.pop_batch(now);
+        queue.complete(&batch, &[], now);
+
+        queue.backoff(pod("p", &[]), now);
+        assert!(!queue.flush(now));
+        assert!(queue.flush(now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_unschedulable_pods_are_flushed_after_max_duration() {
+        let mut queue = queue();
+        let now = Instant::now();
+
+        queue.add(pod("p", &[]), now);
+        let batch = queue.pop_batch(now);
+        queue.complete(&batch, &unscheduled(&batch), now);
+
+        assert!(!queue.flush(now + Duration::from_secs(299)));
+        assert!(queue.flush(now + Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn test_restart_drops_pods_missing_from_the_list() {
+        let mut queue = queue();
+        let now = Instant::now();
+
+        queue.add(pod("gone", &[]), now);
+        queue.handle_pod_event(watcher::Event::Restarted(vec![pod("kept", &[])]), now);
+
+        assert_eq!(names(&queue.pop_batch(now)), vec!["kept"]);
+    }
+
+    #[test]
+    fn test_label_changes_only_requeue_constrained_pods() {
+        let mut queue = queue();
+        let now = Instant::now();
+
+        let mut constrained = pod("constrained", &[]);
+        if let Some(spec) = constrained.spec.as_mut() {
+            spec.node_selector = Some(BTreeMap::from_iter(vec![(
+                "disktype".to_string(),
+                "ssd".to_string(),
+            )]));
+        }
+        queue.add(constrained, now);
+        queue.add(pod("unconstrained", &[]), now);
+        let batch = queue.pop_batch(now);
+        queue.complete(&batch, &unscheduled(&batch), now);
+
+        let later = now + Duration::from_secs(60);
+        assert!(queue.on_cluster_event(&ClusterEvent::NodeLabelsChanged, later));
+        assert_eq!(names(&queue.pop_batch(later)), vec!["constrained"]);
+    }
+
+    #[test]
+    fn test_new_group_member_requeues_rejected_members() {
+        let mut queue = queue();
+        let now = Instant::now();
+        let member = |name: &str| {
+            let mut pod = pod(name, &[]);
+            pod.metadata.labels = Some(BTreeMap::from_iter(vec![(
+                POD_GROUP_NAME_LABEL.to_string(),
+                "job".to_string(),
+            )]));
+            pod
+        };
+
+        queue.add(member("worker-1"), now);
+        queue.add(pod("other", &[]), now);
+        let batch = queue.pop_batch(now);
+        let rejected: Vec<(Pod, Reason)> = batch
+            .iter()
+            .map(|pod| (pod.clone(), Reason::PodGroupTimeout))
+            .collect();
+        queue.complete(&batch, &rejected, now);
+
+        let later = now + Duration::from_secs(60);
+        queue.add(member("worker-2"), later);
+        assert_eq!(names(&queue.pop_batch(later)), vec!["worker-1", "worker-2"]);
+    }
+}
@@ -0,0 +1,108 @@
+// Reports of scheduling decisions, logged and optionally appended to a file as JSON lines
+
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use color_eyre::Result;
+use serde::Serialize;
+
+use crate::scheduler::{queue::pod_key, TargetState};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DryRunRecord {
+    pod: String,
+    // Node the pod would be bound to
+    node: Option<String>,
+    // Why the pod would stay unscheduled
+    reason: Option<String>,
+}
+
+// Report the bindings a dry run would have made and the pods it would have left unscheduled.
+// There are no preemptions to report, as the algorithms never evict placed pods.
+pub(crate) fn report_dry_run(target: &TargetState, output: Option<&Path>) -> Result<()> {
+    let mut records: Vec<DryRunRecord> = target
+        .placements()
+        .map(|(node_name, pod)| DryRunRecord {
+            pod: pod_key(pod),
+            node: Some(node_name.to_owned()),
+            reason: None,
+        })
+        .collect();
+    records.extend(
+        target
+            .unscheduled_pods
+            .iter()
+            .map(|(pod, reason)| DryRunRecord {
+                pod: pod_key(pod),
+                node: None,
+                reason: Some(format!("{reason:?}")),
+            }),
+    );
+
+    for record in &records {
+        match (&record.node, &record.reason) {
+            (Some(node), _) => log::info!("[dry-run] Would bind pod {} to node {node}", record.pod),
+            (None, reason) => log::info!(
+                "[dry-run] Would leave pod {} unscheduled: {}",
+                record.pod,
+                reason.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+
+    if let Some(output) = output {
+        append_jsonl(output, &records)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn append_jsonl<T: Serialize>(path: &Path, records: &[T]) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    for record in records {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        file.write_all(&line)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::scheduler::{testing::pod, Reason};
+
+    use super::*;
+
+    #[test]
+    fn test_report_dry_run_appends_json_lines() {
+        let path = std::env::temp_dir().join(format!(
+            "kube-scheduler-rs-dry-run-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let target = TargetState {
+            unscheduled_pods: vec![(pod("left", &[]), Reason::NoFeasibleNode)],
+            state: BTreeMap::from_iter(vec![("a".to_string(), vec![pod("placed", &[])])]),
+        };
+        report_dry_run(&target, Some(&path)).unwrap();
+        report_dry_run(&target, Some(&path)).unwrap();
+
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["pod"], "default/placed");
+        assert_eq!(lines[0]["node"], "a");
+        assert_eq!(lines[1]["pod"], "default/left");
+        assert_eq!(lines[1]["reason"], "NoFeasibleNode");
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::{Node, Pod};
+use kube_quantity::{ParseQuantityError, ParsedQuantity};
+
+// Resources that are always taken into account when scoring, even if the pod does not
+// request them explicitly
+pub(crate) const DEFAULT_RESOURCES: [&str; 2] = ["cpu", "memory"];
+
+// Parse the allocatable quantities of a node into a map of resource names to quantities
+pub(crate) fn node_allocatable(node: &Node) -> Option<BTreeMap<String, ParsedQuantity>> {
+    let Some(status) = &node.status else { return None };
+    let Some(allocatable) = &status.allocatable else { return None };
+
+    Some(
+        allocatable
+            .iter()
+            .filter_map(|(k, v)| {
+                let Ok(v): Result<ParsedQuantity, ParseQuantityError> = v.try_into() else {
+                    return None;
+                };
+
+                Some((k.clone(), v))
+            })
+            .collect(),
+    )
+}
+
+// Combine all container requests of a pod into a map of resource names to quantities
+pub(crate) fn pod_requests(pod: &Pod) -> BTreeMap<String, ParsedQuantity> {
+    let mut requests: BTreeMap<String, ParsedQuantity> = BTreeMap::new();
+
+    let Some(pod_spec) = &pod.spec else { return requests };
+
+    for container in &pod_spec.containers {
+        let Some(resources) = &container.resources else { continue };
+        let Some(container_requests) = &resources.requests else { continue };
+
+        for (k, v) in container_requests {
+            let Ok(v): Result<ParsedQuantity, ParseQuantityError> = v.try_into() else { continue };
+
+            add_quantity(&mut requests, k, v);
+        }
+    }
+
+    requests
+}
+
+// Sum up the requests of all given pods, e.g. all pods currently bound to a node
+pub(crate) fn pods_requests<'a>(
+    pods: impl IntoIterator<Item = &'a Pod>,
+) -> BTreeMap<String, ParsedQuantity> {
+    let mut requests: BTreeMap<String, ParsedQuantity> = BTreeMap::new();
+
+    for pod in pods {
+        for (k, v) in pod_requests(pod) {
+            add_quantity(&mut requests, &k, v);
+        }
+    }
+
+    requests
+}
+
+// Requests on a node once the pod with the given requests is placed next to its current pods
+pub(crate) fn requested_after_placement(
+    node_pods: &[Pod],
+    requests: &BTreeMap<String, ParsedQuantity>,
+) -> BTreeMap<String, ParsedQuantity> {
+    let mut requested = pods_requests(node_pods);
+    for (k, v) in requests {
+        add_quantity(&mut requested, k, v.clone());
+    }
+
+    requested
+}
+
+// Default resources followed by any other resource the pod requests
+pub(crate) fn scoring_resources(requests: &BTreeMap<String, ParsedQuantity>) -> Vec<&str> {
+    let mut resources: Vec<&str> = DEFAULT_RESOURCES.to_vec();
+    for resource in requests.keys() {
+        if !resources.contains(&resource.as_str()) {
+            resources.push(resource);
+        }
+    }
+
+    resources
+}
+
+// Fraction of the allocatable capacity of each of the given resources that is requested
+// once the pod is placed next to the current pods of the node. Values above 1 mean that the
+// pod does not fit, resources the node does not advertise are left out.
+pub(crate) fn utilization_after_placement(
+    node: &Node,
+    node_pods: &[Pod],
+    requests: &BTreeMap<String, ParsedQuantity>,
+    resources: &[&str],
+) -> BTreeMap<String, f64> {
+    let Some(allocatable) = node_allocatable(node) else { return BTreeMap::new() };
+    let requested = requested_after_placement(node_pods, requests);
+
+    resources
+        .iter()
+        .filter_map(|&resource| {
+            let allocatable_quantity = allocatable.get(resource)?;
+            let requested_quantity = requested.get(resource).cloned().unwrap_or_default();
+            let remaining = remaining_fraction(allocatable_quantity, &requested_quantity)?;
+
+            Some((resource.to_owned(), 1.0 - remaining))
+        })
+        .collect()
+}
+
+// Average utilization of the default and requested resources once the pod is placed onto
+// the node. Returns None if the pod would not fit onto the node next to its current pods.
+pub(crate) fn average_utilization_after_placement(
+    node: &Node,
+    node_pods: &[Pod],
+    requests: &BTreeMap<String, ParsedQuantity>,
+) -> Option<f64> {
+    let utilization =
+        utilization_after_placement(node, node_pods, requests, &scoring_resources(requests));
+
+    if utilization.values().any(|&fraction| fraction > 1.0) {
+        return None;
+    }
+    if utilization.is_empty() {
+        return Some(0.0);
+    }
+
+    Some(utilization.values().sum::<f64>() / utilization.len() as f64)
+}
+
+// Add a quantity to the given resource of a map of quantities.
+// Starting from the parsed quantity itself rather than ParsedQuantity::default() keeps its
+// format, as adding decimal quantities to a binary zero would skew the result.
+pub(crate) fn add_quantity(
+    quantities: &mut BTreeMap<String, ParsedQuantity>,
+    resource_name: &str,
+    quantity: ParsedQuantity,
+) {
+    match quantities.get_mut(resource_name) {
+        Some(existing) => *existing += quantity,
+        None => {
+            quantities.insert(resource_name.to_owned(), quantity);
+        }
+    }
+}
+
+// Fraction of the allocatable quantity that is left after subtracting the requested one.
+// Returns None if the resource cannot be compared, e.g. because nothing is allocatable.
+pub(crate) fn remaining_fraction(
+    allocatable: &ParsedQuantity,
+    requested: &ParsedQuantity,
+) -> Option<f64> {
+    let allocatable = allocatable.to_bytes_f64()?;
+    let requested = requested.to_bytes_f64()?;
+
+    if allocatable <= 0.0 {
+        return None;
+    }
+
+    Some((allocatable - requested) / allocatable)
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::{
+        api::core::v1::{Container, ResourceRequirements},
+        apimachinery::pkg::api::resource::Quantity,
+    };
+
+    use crate::scheduler::testing::{node, pod, quantities};
+
+    use super::*;
+
+    #[test]
+    fn test_pod_requests_sums_containers() {
+        let mut pod = pod("pod", &[("cpu", "500m"), ("memory", "1Gi")]);
+        if let Some(spec) = pod.spec.as_mut() {
+            spec.containers.push(Container {
+                name: "sidecar".to_string(),
+                resources: Some(ResourceRequirements {
+                    requests: Some(quantities(&[("cpu", "250m")])),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            });
+        }
+
+        let requests = pod_requests(&pod);
+
+        assert_eq!(requests["cpu"].to_bytes_f64(), Some(0.75));
+        assert_eq!(
+            requests["memory"].to_bytes_f64(),
+            Some(1024.0 * 1024.0 * 1024.0)
+        );
+    }
+
+    #[test]
+    fn test_pods_requests_sums_pods() {
+        let pods = vec![pod("a", &[("cpu", "1")]), pod("b", &[("cpu", "2")])];
+
+        assert_eq!(pods_requests(&pods)["cpu"].to_bytes_f64(), Some(3.0));
+    }
+
+    #[test]
+    fn test_scoring_resources_deduplicates_defaults() {
+        let requests = pod_requests(&pod("pod", &[("cpu", "1"), ("nvidia.com/gpu", "1")]));
+
+        assert_eq!(
+            scoring_resources(&requests),
+            vec!["cpu", "memory", "nvidia.com/gpu"]
+        );
+    }
+
+    #[test]
+    fn test_average_utilization_after_placement() {
+        let node = node("node", &[("cpu", "4"), ("memory", "4Gi")]);
+        let requests = pod_requests(&pod("new", &[("cpu", "1"), ("memory", "1Gi")]));
+        let existing = [pod("existing", &[("cpu", "1"), ("memory", "3Gi")])];
+
+        assert_eq!(
+            average_utilization_after_placement(&node, &existing, &requests),
+            Some(0.75)
+        );
+    }
+
+    #[test]
+    fn test_average_utilization_after_placement_does_not_fit() {
+        let node = node("node", &[("cpu", "4"), ("memory", "4Gi")]);
+        let requests = pod_requests(&pod("new", &[("memory", "2Gi")]));
+        let existing = [pod("existing", &[("memory", "3Gi")])];
+
+        assert_eq!(
+            average_utilization_after_placement(&node, &existing, &requests),
+            None
+        );
+    }
+
+    #[test]
+    fn test_remaining_fraction() {
+        let allocatable: ParsedQuantity = Quantity("4".to_string()).try_into().unwrap();
+        let requested: ParsedQuantity = Quantity("1".to_string()).try_into().unwrap();
+
+        assert_eq!(remaining_fraction(&allocatable, &requested), Some(0.75));
+    }
+
+    #[test]
+    fn test_remaining_fraction_nothing_allocatable() {
+        let allocatable = ParsedQuantity::default();
+        let requested: ParsedQuantity = Quantity("1".to_string()).try_into().unwrap();
+
+        assert_eq!(remaining_fraction(&allocatable, &requested), None);
+    }
+}
@@ -0,0 +1,110 @@
+use k8s_openapi::api::core::v1::{Node, Pod};
+
+use crate::{
+    config::BalancedAllocationArgs,
+    scheduler::{
+        resources::{pod_requests, utilization_after_placement},
+        scores::{ScoreContext, ScorePlugin, MAX_NODE_SCORE},
+    },
+};
+
+// Prefer nodes whose resources are utilized evenly once the pod is placed, i.e. where the
+// standard deviation of the utilization fractions is low, so that bin packing does not
+// leave nodes CPU-full but memory-empty
+pub(crate) struct BalancedAllocation {
+    resources: Vec<String>,
+}
+
+impl BalancedAllocation {
+    pub(crate) fn new(args: &BalancedAllocationArgs) -> Self {
+        Self {
+            resources: args
+                .resources
+                .iter()
+                .map(|resource| resource.name.clone())
+                .collect(),
+        }
+    }
+}
+
+impl ScorePlugin for BalancedAllocation {
+    fn score(&self, ctx: &ScoreContext, pod: &Pod, node: &Node) -> i64 {
+        let resources: Vec<&str> = self.resources.iter().map(String::as_str).collect();
+        let fractions: Vec<f64> =
+            utilization_after_placement(node, ctx.node_pods(node), &pod_requests(pod), &resources)
+                .into_values()
+                .map(|fraction| fraction.min(1.0))
+                .collect();
+
+        // A single resource is always balanced
+        if fractions.len() < 2 {
+            return MAX_NODE_SCORE;
+        }
+
+        let mean = fractions.iter().sum::<f64>() / fractions.len() as f64;
+        let variance = fractions
+            .iter()
+            .map(|fraction| (fraction - mean).powi(2))
+            .sum::<f64>()
+            / fractions.len() as f64;
+
+        ((1.0 - variance.sqrt()) * MAX_NODE_SCORE as f64).round() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::scheduler::testing::{node, pod};
+
+    use super::*;
+
+    #[test]
+    fn test_score_prefers_balanced_node() {
+        let plugin = BalancedAllocation::new(&BalancedAllocationArgs::default());
+        let allocatable = [("cpu", "4"), ("memory", "4Gi")];
+
+        let nodes = [
+            node("cpu-heavy", &allocatable),
+            node("balanced", &allocatable),
+        ];
+        let state = BTreeMap::from_iter(vec![
+            (
+                "cpu-heavy".to_string(),
+                vec![pod("existing", &[("cpu", "3")])],
+            ),
+            (
+                "balanced".to_string(),
+                vec![pod("existing", &[("memory", "2Gi")])],
+            ),
+        ]);
+        let ctx = ScoreContext {
+            nodes: &[],
+            state: &state,
+        };
+        let incoming = pod("incoming", &[("cpu", "1"), ("memory", "0")]);
+
+        // cpu 100%, memory 0%
+        assert_eq!(plugin.score(&ctx, &incoming, &nodes[0]), 50);
+        // cpu 25%, memory 50%
+        assert_eq!(plugin.score(&ctx, &incoming, &nodes[1]), 88);
+    }
+
+    #[test]
+    fn test_score_single_resource_is_balanced() {
+        let plugin = BalancedAllocation::new(&BalancedAllocationArgs::default());
+
+        let nodes = [node("a", &[("cpu", "4")])];
+        let state = BTreeMap::from_iter(vec![("a".to_string(), vec![])]);
+        let ctx = ScoreContext {
+            nodes: &[],
+            state: &state,
+        };
+
+        assert_eq!(
+            plugin.score(&ctx, &pod("incoming", &[("cpu", "1")]), &nodes[0]),
+            MAX_NODE_SCORE
+        );
+    }
+}
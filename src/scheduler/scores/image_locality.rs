@@ -0,0 +1,161 @@
+use k8s_openapi::api::core::v1::{Node, Pod};
+
+use crate::scheduler::scores::{ScoreContext, ScorePlugin, MAX_NODE_SCORE};
+
+// Bounds of the summed image sizes, in bytes, mapped onto the score range like upstream:
+// small images are cheap to pull anyway and the upper bound grows with the containers
+const MIN_THRESHOLD: i64 = 23 * 1024 * 1024;
+const MAX_CONTAINER_THRESHOLD: i64 = 1000 * 1024 * 1024;
+
+// Prefer nodes already holding the container images of the pod, scoring each image present
+// with its size scaled by the fraction of nodes holding it, so that an image on only a few
+// nodes does not herd pods onto them
+pub(crate) struct ImageLocality;
+
+// Name of an image with the implicit latest tag made explicit, so that e.g. nginx and
+// nginx:latest compare equal
+fn normalized_image_name(name: &str) -> String {
+    let last_segment = name.rsplit('/').next().unwrap_or(name);
+    if last_segment.contains(':') || last_segment.contains('@') {
+        name.to_owned()
+    } else {
+        format!("{name}:latest")
+    }
+}
+
+// Size of the image if the node holds it
+fn image_size(node: &Node, image: &str) -> Option<i64> {
+    node.status
+        .as_ref()
+        .and_then(|status| status.images.as_ref())
+        .into_iter()
+        .flatten()
+        .find(|container_image| {
+            container_image
+                .names
+                .iter()
+                .flatten()
+                .any(|name| normalized_image_name(name) == image)
+        })
+        .map(|container_image| container_image.size_bytes.unwrap_or_default())
+}
+
+impl ScorePlugin for ImageLocality {
+    fn score(&self, ctx: &ScoreContext, pod: &Pod, node: &Node) -> i64 {
+        let Some(spec) = &pod.spec else { return 0 };
+        let containers = spec
+            .init_containers
+            .iter()
+            .flatten()
+            .chain(spec.containers.iter());
+
+        let total_nodes = ctx.nodes.len().max(1) as f64;
+        let mut container_count = 0;
+        let mut sum = 0;
+        for container in containers {
+            container_count += 1;
+
+            let Some(image) = container.image.as_deref() else { continue };
+            let image = normalized_image_name(image);
+            let Some(size) = image_size(node, &image) else { continue };
+
+            let holders = ctx
+                .nodes
+                .iter()
+                .filter(|other| image_size(other, &image).is_some())
+                .count()
+                .max(1) as f64;
+            sum += (size as f64 * (holders / total_nodes).min(1.0)) as i64;
+        }
+
+        let max_threshold = MAX_CONTAINER_THRESHOLD * container_count.max(1);
+        let sum = sum.clamp(MIN_THRESHOLD, max_threshold);
+
+        MAX_NODE_SCORE * (sum - MIN_THRESHOLD) / (max_threshold - MIN_THRESHOLD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::api::core::v1::ContainerImage;
+
+    use crate::scheduler::testing::{node, pod};
+
+    use super::*;
+
+    const MIB: i64 = 1024 * 1024;
+
+    fn with_images(name: &str, images: &[(&str, i64)]) -> Node {
+        let mut node = node(name, &[]);
+        if let Some(status) = node.status.as_mut() {
+            status.images = Some(
+                images
+                    .iter()
+                    .map(|(name, size)| ContainerImage {
+                        names: Some(vec![name.to_string()]),
+                        size_bytes: Some(*size),
+                    })
+                    .collect(),
+            );
+        }
+
+        node
+    }
+
+    fn with_image(mut pod: Pod, image: &str) -> Pod {
+        if let Some(spec) = pod.spec.as_mut() {
+            spec.containers[0].image = Some(image.to_owned());
+        }
+
+        pod
+    }
+
+    #[test]
+    fn test_normalized_image_name() {
+        assert_eq!(normalized_image_name("nginx"), "nginx:latest");
+        assert_eq!(normalized_image_name("nginx:1.25"), "nginx:1.25");
+        assert_eq!(
+            normalized_image_name("registry:5000/team/app"),
+            "registry:5000/team/app:latest"
+        );
+        assert_eq!(normalized_image_name("app@sha256:abc"), "app@sha256:abc");
+    }
+
+    #[test]
+    fn test_score_scales_with_size_and_spread() {
+        let nodes = [
+            with_images("large", &[("ml:latest", 1023 * MIB)]),
+            with_images("spread", &[("ml:latest", 1023 * MIB)]),
+            with_images("small", &[("ml", 10 * MIB)]),
+            with_images("empty", &[]),
+        ];
+        let state = BTreeMap::new();
+        let ctx = ScoreContext {
+            nodes: &nodes,
+            state: &state,
+        };
+        let incoming = with_image(pod("incoming", &[]), "ml");
+
+        let scores: Vec<i64> = nodes
+            .iter()
+            .map(|node| ImageLocality.score(&ctx, &incoming, node))
+            .collect();
+        // Held by 3 of 4 nodes, so 1023 MiB counts as 767 MiB, while 10 MiB is below the
+        // minimum threshold
+        assert_eq!(scores, [76, 76, 0, 0]);
+
+        // The same image on a single node of four counts for a quarter of its size
+        let ctx = ScoreContext {
+            nodes: &[
+                nodes[0].clone(),
+                with_images("b", &[]),
+                with_images("c", &[]),
+                with_images("d", &[]),
+            ],
+            state: &state,
+        };
+        assert_eq!(ImageLocality.score(&ctx, &incoming, &nodes[0]), 23);
+    }
+}
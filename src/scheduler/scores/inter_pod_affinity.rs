@@ -0,0 +1,309 @@
+use k8s_openapi::api::core::v1::{Node, Pod, PodAffinityTerm};
+
+use crate::{
+    config::InterPodAffinityArgs,
+    scheduler::{
+        scores::{ScoreContext, ScorePlugin, MAX_NODE_SCORE},
+        selectors::labels_match_selector,
+    },
+};
+
+// Prefer nodes in topology domains that satisfy the preferred pod affinity and avoid those
+// that violate the preferred pod anti-affinity, of the incoming pod towards the pods
+// already placed as well as of the placed pods towards the incoming pod. Required affinity
+// terms of placed pods matching the incoming pod count with the hard pod affinity weight,
+// like upstream.
+pub(crate) struct InterPodAffinity {
+    hard_pod_affinity_weight: i64,
+}
+
+impl InterPodAffinity {
+    pub(crate) fn new(args: &InterPodAffinityArgs) -> Self {
+        Self {
+            hard_pod_affinity_weight: args.hard_pod_affinity_weight,
+        }
+    }
+}
+
+// Affinity terms of a pod with the weight they contribute when matched, negative for
+// anti-affinity
+fn weighted_terms(pod: &Pod, hard_pod_affinity_weight: i64) -> Vec<(i64, &PodAffinityTerm)> {
+    let Some(affinity) = pod.spec.as_ref().and_then(|spec| spec.affinity.as_ref()) else {
+        return vec![];
+    };
+
+    let mut terms = vec![];
+
+    if let Some(pod_affinity) = &affinity.pod_affinity {
+        if hard_pod_affinity_weight != 0 {
+            terms.extend(
+                pod_affinity
+                    .required_during_scheduling_ignored_during_execution
+                    .iter()
+                    .flatten()
+                    .map(|term| (hard_pod_affinity_weight, term)),
+            );
+        }
+        terms.extend(
+            pod_affinity
+                .preferred_during_scheduling_ignored_during_execution
+                .iter()
+                .flatten()
+                .map(|term| (i64::from(term.weight), &term.pod_affinity_term)),
+        );
+    }
+    if let Some(pod_anti_affinity) = &affinity.pod_anti_affinity {
+        terms.extend(
+            pod_anti_affinity
+                .preferred_during_scheduling_ignored_during_execution
+                .iter()
+                .flatten()
+                .map(|term| (-i64::from(term.weight), &term.pod_affinity_term)),
+        );
+    }
+
+    terms
+}
+
+// Whether the term of the owner selects the target pod. Namespace selectors would need the
+// labels of namespaces, so only an empty one, selecting all namespaces, is honored.
+fn term_selects(term: &PodAffinityTerm, owner: &Pod, target: &Pod) -> bool {
+    let namespace = target.metadata.namespace.as_deref().unwrap_or("default");
+    let namespaces = term.namespaces.as_deref().unwrap_or_default();
+    let all_namespaces = matches!(
+        &term.namespace_selector,
+        Some(selector) if selector.match_labels.is_none() && selector.match_expressions.is_none()
+    );
+
+    let in_namespace = if namespaces.is_empty() && term.namespace_selector.is_none() {
+        owner.metadata.namespace.as_deref().unwrap_or("default") == namespace
+    } else {
+        all_namespaces || namespaces.iter().any(|candidate| candidate == namespace)
+    };
+
+    let empty = Default::default();
+    let labels = target.metadata.labels.as_ref().unwrap_or(&empty);
+
+    in_namespace
+        && matches!(&term.label_selector, Some(selector) if labels_match_selector(labels, selector))
+}
+
+// Whether both nodes carry the topology label with the same value
+fn same_domain(node: &Node, other: &Node, topology_key: &str) -> bool {
+    let value = |node: &Node| {
+        node.metadata
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.get(topology_key))
+            .cloned()
+    };
+
+    matches!((value(node), value(other)), (Some(a), Some(b)) if a == b)
+}
+
+impl ScorePlugin for InterPodAffinity {
+    fn score(&self, ctx: &ScoreContext, pod: &Pod, node: &Node) -> i64 {
+        let incoming_terms: Vec<(i64, &PodAffinityTerm)> = weighted_terms(pod, 0);
+
+        let mut score = 0;
+        for other in ctx.nodes {
+            for existing in ctx.node_pods(other) {
+                // Terms of the incoming pod towards the pods already placed
+                for (weight, term) in &incoming_terms {
+                    if same_domain(node, other, &term.topology_key)
+                        && term_selects(term, pod, existing)
+                    {
+                        score += weight;
+                    }
+                }
+
+                // Terms of the pods already placed towards the incoming pod
+                for (weight, term) in weighted_terms(existing, self.hard_pod_affinity_weight) {
+                    if same_domain(node, other, &term.topology_key)
+                        && term_selects(term, existing, pod)
+                    {
+                        score += weight;
+                    }
+                }
+            }
+        }
+
+        score
+    }
+
+    // Scale the scores between the lowest and the highest one, as they may be negative
+    fn normalize(&self, scores: &mut [i64]) {
+        let min = scores.iter().copied().min().unwrap_or_default();
+        let max = scores.iter().copied().max().unwrap_or_default();
+
+        for score in scores.iter_mut() {
+            *score = if max > min {
+                (*score - min) * MAX_NODE_SCORE / (max - min)
+            } else {
+                0
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::{
+        api::core::v1::{Affinity, PodAffinity, PodAntiAffinity, WeightedPodAffinityTerm},
+        apimachinery::pkg::apis::meta::v1::LabelSelector,
+    };
+
+    use crate::scheduler::testing::{node, pod};
+
+    use super::*;
+
+    const ZONE: &str = "topology.kubernetes.io/zone";
+
+    fn zoned(name: &str, zone: &str) -> Node {
+        let mut node = node(name, &[]);
+        node.metadata.labels = Some(BTreeMap::from_iter(vec![(
+            ZONE.to_string(),
+            zone.to_string(),
+        )]));
+
+        node
+    }
+
+    fn app(name: &str, app: &str) -> Pod {
+        let mut pod = pod(name, &[]);
+        pod.metadata.labels = Some(BTreeMap::from_iter(vec![(
+            "app".to_string(),
+            app.to_string(),
+        )]));
+
+        pod
+    }
+
+    fn term(weight: i32, app: &str) -> WeightedPodAffinityTerm {
+        WeightedPodAffinityTerm {
+            weight,
+            pod_affinity_term: PodAffinityTerm {
+                label_selector: Some(LabelSelector {
+                    match_labels: Some(BTreeMap::from_iter(vec![(
+                        "app".to_string(),
+                        app.to_string(),
+                    )])),
+                    match_expressions: None,
+                }),
+                topology_key: ZONE.to_owned(),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn with_affinity(mut pod: Pod, affinity: Affinity) -> Pod {
+        if let Some(spec) = pod.spec.as_mut() {
+            spec.affinity = Some(affinity);
+        }
+
+        pod
+    }
+
+    fn scores(ctx: &ScoreContext, pod: &Pod) -> Vec<i64> {
+        let plugin = InterPodAffinity::new(&InterPodAffinityArgs::default());
+        let mut scores: Vec<i64> = ctx
+            .nodes
+            .iter()
+            .map(|node| plugin.score(ctx, pod, node))
+            .collect();
+        plugin.normalize(&mut scores);
+
+        scores
+    }
+
+    #[test]
+    fn test_preferred_affinity_of_incoming_pod() {
+        let nodes = [zoned("a", "eu-1"), zoned("b", "eu-1"), zoned("c", "eu-2")];
+        let state = BTreeMap::from_iter(vec![
+            ("a".to_string(), vec![app("consumer", "consumer")]),
+            ("b".to_string(), vec![]),
+            ("c".to_string(), vec![]),
+        ]);
+        let ctx = ScoreContext {
+            nodes: &nodes,
+            state: &state,
+        };
+
+        let cache = with_affinity(
+            app("cache", "cache"),
+            Affinity {
+                pod_affinity: Some(PodAffinity {
+                    preferred_during_scheduling_ignored_during_execution: Some(vec![term(
+                        10, "consumer",
+                    )]),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+
+        // Both nodes of the zone of the consumer are preferred
+        assert_eq!(scores(&ctx, &cache), [100, 100, 0]);
+    }
+
+    #[test]
+    fn test_preferred_anti_affinity_spreads_replicas() {
+        let nodes = [zoned("a", "eu-1"), zoned("b", "eu-2")];
+        let replica = |name| {
+            with_affinity(
+                app(name, "web"),
+                Affinity {
+                    pod_anti_affinity: Some(PodAntiAffinity {
+                        preferred_during_scheduling_ignored_during_execution: Some(vec![term(
+                            5, "web",
+                        )]),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            )
+        };
+        let state = BTreeMap::from_iter(vec![
+            ("a".to_string(), vec![replica("web-1")]),
+            ("b".to_string(), vec![]),
+        ]);
+        let ctx = ScoreContext {
+            nodes: &nodes,
+            state: &state,
+        };
+
+        // The anti-affinity of the incoming replica as well as the one of the placed
+        // replica push the incoming replica into the other zone
+        assert_eq!(scores(&ctx, &replica("web-2")), [0, 100]);
+    }
+
+    #[test]
+    fn test_affinity_of_existing_pods() {
+        let nodes = [zoned("a", "eu-1"), zoned("b", "eu-2")];
+        let consumer = with_affinity(
+            app("consumer", "consumer"),
+            Affinity {
+                pod_affinity: Some(PodAffinity {
+                    required_during_scheduling_ignored_during_execution: Some(vec![
+                        term(1, "cache").pod_affinity_term,
+                    ]),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+        let state = BTreeMap::from_iter(vec![
+            ("a".to_string(), vec![]),
+            ("b".to_string(), vec![consumer]),
+        ]);
+        let ctx = ScoreContext {
+            nodes: &nodes,
+            state: &state,
+        };
+
+        assert_eq!(scores(&ctx, &app("cache", "cache")), [0, 100]);
+        assert_eq!(scores(&ctx, &app("other", "other")), [0, 0]);
+    }
+}
@@ -0,0 +1,101 @@
+use k8s_openapi::api::core::v1::{Node, Pod};
+
+use crate::{
+    config::LoadAwareArgs,
+    scheduler::{
+        load::NodeLoad,
+        scores::{ScoreContext, ScorePlugin, MAX_NODE_SCORE},
+    },
+};
+
+// Prefer nodes whose actual usage, averaged over the load window, leaves the most room,
+// going by the most used resource. Nodes without metrics score lowest so that known idle
+// nodes win over unknown ones.
+pub(crate) struct LoadAware {
+    resources: Vec<String>,
+    load: NodeLoad,
+}
+
+impl LoadAware {
+    pub(crate) fn new(args: &LoadAwareArgs, load: &NodeLoad) -> Self {
+        Self {
+            resources: args.resources.clone(),
+            load: load.clone(),
+        }
+    }
+}
+
+impl ScorePlugin for LoadAware {
+    fn score(&self, _ctx: &ScoreContext, _pod: &Pod, node: &Node) -> i64 {
+        let Some(utilization) = self
+            .resources
+            .iter()
+            .map(|resource| self.load.utilization(node, resource))
+            .collect::<Option<Vec<f64>>>()
+        else {
+            return 0;
+        };
+
+        let highest = utilization.into_iter().reduce(f64::max).unwrap_or_default();
+
+        ((1.0 - highest).clamp(0.0, 1.0) * MAX_NODE_SCORE as f64).round() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        time::{Duration, Instant},
+    };
+
+    use crate::scheduler::{
+        load::LoadTracker,
+        testing::{node, pod},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_score_prefers_idle_nodes() {
+        let mut tracker = LoadTracker::new(Duration::from_secs(60));
+        tracker.record(
+            BTreeMap::from_iter(vec![
+                (
+                    "busy".to_string(),
+                    BTreeMap::from_iter(vec![
+                        ("cpu".to_string(), 3.0),
+                        ("memory".to_string(), 1024.0 * 1024.0 * 1024.0),
+                    ]),
+                ),
+                (
+                    "idle".to_string(),
+                    BTreeMap::from_iter(vec![
+                        ("cpu".to_string(), 0.4),
+                        ("memory".to_string(), 2.0 * 1024.0 * 1024.0 * 1024.0),
+                    ]),
+                ),
+            ]),
+            Instant::now(),
+        );
+        let plugin = LoadAware::new(&LoadAwareArgs::default(), &tracker.snapshot());
+
+        let nodes = [
+            node("busy", &[("cpu", "4"), ("memory", "8Gi")]),
+            node("idle", &[("cpu", "4"), ("memory", "8Gi")]),
+            node("unknown", &[("cpu", "4"), ("memory", "8Gi")]),
+        ];
+        let state = BTreeMap::new();
+        let ctx = ScoreContext {
+            nodes: &nodes,
+            state: &state,
+        };
+
+        let scores: Vec<i64> = nodes
+            .iter()
+            .map(|node| plugin.score(&ctx, &pod("p", &[]), node))
+            .collect();
+        // The memory of the idle node is used more than its CPU
+        assert_eq!(scores, [25, 75, 0]);
+    }
+}
@@ -0,0 +1,134 @@
+pub(crate) mod balanced_allocation;
+pub(crate) mod image_locality;
+pub(crate) mod inter_pod_affinity;
+pub(crate) mod load_aware;
+pub(crate) mod network_bandwidth;
+pub(crate) mod node_affinity;
+pub(crate) mod requested_to_capacity_ratio;
+pub(crate) mod taint_toleration;
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::{Node, Pod};
+
+use crate::{
+    config::{Profile, ScorePlugin as ScorePluginConfig},
+    scheduler::{load::NodeLoad, wasm::WasmPlugin},
+};
+
+// Upper bound of the score a plugin may assign to a node
+pub(crate) const MAX_NODE_SCORE: i64 = 100;
+
+// Cluster state a score plugin may take into account next to the pod and node it scores
+pub(crate) struct ScoreContext<'a> {
+    // All nodes of the cluster, e.g. to resolve the topology domains of other nodes
+    pub(crate) nodes: &'a [Node],
+    // Pods bound, or already assigned during this run, to each node
+    pub(crate) state: &'a BTreeMap<String, Vec<Pod>>,
+}
+
+impl<'a> ScoreContext<'a> {
+    pub(crate) fn node_pods(&self, node: &Node) -> &'a [Pod] {
+        node.metadata
+            .name
+            .as_ref()
+            .and_then(|node_name| self.state.get(node_name))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+pub(crate) trait ScorePlugin: Send + Sync {
+    // Score a feasible node for the pod, between 0 and MAX_NODE_SCORE unless the plugin
+    // normalizes its scores afterwards
+    fn score(&self, ctx: &ScoreContext, pod: &Pod, node: &Node) -> i64;
+
+    // Normalize the scores of all feasible nodes into 0..=MAX_NODE_SCORE
+    fn normalize(&self, _scores: &mut [i64]) {}
+}
+
+pub(crate) struct WeightedScorePlugin {
+    pub(crate) plugin: Box<dyn ScorePlugin>,
+    pub(crate) weight: i64,
+}
+
+// Instantiate the score plugins configured in a profile
+pub(crate) fn plugins(profile: &Profile, load: &NodeLoad) -> Vec<WeightedScorePlugin> {
+    profile
+        .score_plugins
+        .iter()
+        .filter_map(|config| {
+            let plugin: Box<dyn ScorePlugin> = match &config.plugin {
+                ScorePluginConfig::RequestedToCapacityRatio { args } => Box::new(
+                    requested_to_capacity_ratio::RequestedToCapacityRatio::new(args),
+                ),
+                ScorePluginConfig::BalancedAllocation { args } => {
+                    Box::new(balanced_allocation::BalancedAllocation::new(args))
+                }
+                ScorePluginConfig::NodeAffinity => Box::new(node_affinity::NodeAffinity),
+                ScorePluginConfig::InterPodAffinity { args } => {
+                    Box::new(inter_pod_affinity::InterPodAffinity::new(args))
+                }
+                ScorePluginConfig::TaintToleration => Box::new(taint_toleration::TaintToleration),
+                ScorePluginConfig::ImageLocality => Box::new(image_locality::ImageLocality),
+                ScorePluginConfig::NetworkBandwidth => {
+                    Box::new(network_bandwidth::NetworkBandwidth)
+                }
+                ScorePluginConfig::LoadAware { args } => {
+                    Box::new(load_aware::LoadAware::new(args, load))
+                }
+                ScorePluginConfig::Wasm { args } => match WasmPlugin::load(args) {
+                    Ok(plugin) => Box::new(plugin),
+                    Err(err) => {
+                        log::error!("Skipping score plugin: {err}");
+                        return None;
+                    }
+                },
+            };
+
+            Some(WeightedScorePlugin {
+                plugin,
+                weight: config.weight,
+            })
+        })
+        .collect()
+}
+
+// Scale the scores into 0..=MAX_NODE_SCORE relative to the highest score, optionally
+// reversed so the lowest raw score gets the maximum, like the upstream default normalization
+pub(crate) fn normalize(scores: &mut [i64], reverse: bool) {
+    let max = scores.iter().copied().max().unwrap_or_default();
+
+    for score in scores.iter_mut() {
+        let scaled = if max > 0 { *score * MAX_NODE_SCORE / max } else { 0 };
+        *score = match (reverse, max > 0) {
+            (false, _) => scaled,
+            (true, true) => MAX_NODE_SCORE - scaled,
+            (true, false) => MAX_NODE_SCORE,
+        };
+    }
+}
+
+// Sum of the weighted and normalized scores of all plugins for each of the given nodes
+pub(crate) fn score_nodes(
+    plugins: &[WeightedScorePlugin],
+    ctx: &ScoreContext,
+    pod: &Pod,
+    nodes: &[&Node],
+) -> Vec<i64> {
+    let mut totals = vec![0; nodes.len()];
+
+    for WeightedScorePlugin { plugin, weight } in plugins {
+        let mut scores: Vec<i64> = nodes
+            .iter()
+            .map(|node| plugin.score(ctx, pod, node))
+            .collect();
+        plugin.normalize(&mut scores);
+
+        for (total, score) in totals.iter_mut().zip(scores) {
+            *total += score * weight;
+        }
+    }
+
+    totals
+}
@@ -0,0 +1,78 @@
+use k8s_openapi::api::core::v1::{Node, Pod};
+
+use crate::scheduler::{
+    bandwidth::free_after_placement,
+    scores::{ScoreContext, ScorePlugin, MAX_NODE_SCORE},
+};
+
+// Prefer nodes with the most network bandwidth left once the pod is placed, going by the
+// direction with the least left. Nodes not advertising the bandwidth the pod requests score
+// lowest, while pods not requesting bandwidth score all nodes alike.
+pub(crate) struct NetworkBandwidth;
+
+impl ScorePlugin for NetworkBandwidth {
+    fn score(&self, ctx: &ScoreContext, pod: &Pod, node: &Node) -> i64 {
+        let Some(free) = free_after_placement(node, ctx.node_pods(node), pod)
+            .into_iter()
+            .collect::<Option<Vec<f64>>>()
+        else {
+            return 0;
+        };
+
+        let least = free.into_iter().reduce(f64::min).unwrap_or_default();
+
+        (least.clamp(0.0, 1.0) * MAX_NODE_SCORE as f64).round() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::scheduler::testing::{annotated, node, pod};
+
+    use super::*;
+
+    #[test]
+    fn test_score_prefers_spare_bandwidth() {
+        let bandwidth = [
+            ("network.scheduling/ingress-bandwidth", "1G"),
+            ("network.scheduling/egress-bandwidth", "1G"),
+        ];
+        let nodes = [
+            annotated(node("busy", &[]), &bandwidth),
+            annotated(node("idle", &[]), &bandwidth),
+            node("unknown", &[]),
+        ];
+        let state = BTreeMap::from_iter(vec![(
+            "busy".to_string(),
+            vec![annotated(
+                pod("existing", &[]),
+                &[("kubernetes.io/egress-bandwidth", "600M")],
+            )],
+        )]);
+        let ctx = ScoreContext {
+            nodes: &nodes,
+            state: &state,
+        };
+
+        let incoming = annotated(
+            pod("incoming", &[]),
+            &[
+                ("kubernetes.io/ingress-bandwidth", "100M"),
+                ("kubernetes.io/egress-bandwidth", "200M"),
+            ],
+        );
+        let scores: Vec<i64> = nodes
+            .iter()
+            .map(|node| NetworkBandwidth.score(&ctx, &incoming, node))
+            .collect();
+        assert_eq!(scores, [20, 80, 0]);
+
+        let other = pod("other", &[]);
+        assert_eq!(
+            NetworkBandwidth.score(&ctx, &other, &nodes[1]),
+            NetworkBandwidth.score(&ctx, &other, &nodes[2])
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use k8s_openapi::api::core::v1::{Node, Pod};
+
+use crate::scheduler::{
+    scores::{normalize, ScoreContext, ScorePlugin},
+    selectors::node_matches_term,
+};
+
+// Prefer nodes matching the preferred node affinity terms of the pod, scoring each node
+// with the sum of the weights of the terms it matches
+pub(crate) struct NodeAffinity;
+
+impl ScorePlugin for NodeAffinity {
+    fn score(&self, _ctx: &ScoreContext, pod: &Pod, node: &Node) -> i64 {
+        let Some(preferred) = pod
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.affinity.as_ref())
+            .and_then(|affinity| affinity.node_affinity.as_ref())
+            .and_then(|node_affinity| {
+                node_affinity
+                    .preferred_during_scheduling_ignored_during_execution
+                    .as_ref()
+            })
+        else {
+            return 0;
+        };
+
+        preferred
+            .iter()
+            .filter(|term| node_matches_term(node, &term.preference))
+            .map(|term| i64::from(term.weight))
+            .sum()
+    }
+
+    fn normalize(&self, scores: &mut [i64]) {
+        normalize(scores, false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::api::core::v1::{
+        Affinity, NodeAffinity as NodeAffinitySpec, NodeSelectorRequirement, NodeSelectorTerm,
+        PreferredSchedulingTerm,
+    };
+
+    use crate::scheduler::{
+        scores::MAX_NODE_SCORE,
+        testing::{node, pod},
+    };
+
+    use super::*;
+
+    fn preferred(weight: i32, key: &str, value: &str) -> PreferredSchedulingTerm {
+        PreferredSchedulingTerm {
+            weight,
+            preference: NodeSelectorTerm {
+                match_expressions: Some(vec![NodeSelectorRequirement {
+                    key: key.to_owned(),
+                    operator: "In".to_owned(),
+                    values: Some(vec![value.to_owned()]),
+                }]),
+                match_fields: None,
+            },
+        }
+    }
+
+    fn labeled(name: &str, labels: &[(&str, &str)]) -> Node {
+        let mut node = node(name, &[]);
+        node.metadata.labels = Some(
+            labels
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        );
+
+        node
+    }
+
+    #[test]
+    fn test_score_sums_matching_weights() {
+        let mut incoming = pod("incoming", &[]);
+        if let Some(spec) = incoming.spec.as_mut() {
+            spec.affinity = Some(Affinity {
+                node_affinity: Some(NodeAffinitySpec {
+                    preferred_during_scheduling_ignored_during_execution: Some(vec![
+                        preferred(80, "zone", "eu-1"),
+                        preferred(20, "disk", "ssd"),
+                    ]),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            });
+        }
+
+        let nodes = [
+            labeled("both", &[("zone", "eu-1"), ("disk", "ssd")]),
+            labeled("zone", &[("zone", "eu-1")]),
+            labeled("disk", &[("disk", "ssd")]),
+            labeled("none", &[]),
+        ];
+        let state = BTreeMap::new();
+        let ctx = ScoreContext {
+            nodes: &[],
+            state: &state,
+        };
+
+        let mut scores: Vec<i64> = nodes
+            .iter()
+            .map(|node| NodeAffinity.score(&ctx, &incoming, node))
+            .collect();
+        assert_eq!(scores, [100, 80, 20, 0]);
+
+        // Scaled so the best matching node gets the maximum score
+        scores.truncate(2);
+        scores[0] = 50;
+        scores[1] = 40;
+        NodeAffinity.normalize(&mut scores);
+        assert_eq!(scores, [MAX_NODE_SCORE, 80]);
+    }
+
+    #[test]
+    fn test_score_without_preferences() {
+        let state = BTreeMap::new();
+        let ctx = ScoreContext {
+            nodes: &[],
+            state: &state,
+        };
+
+        assert_eq!(
+            NodeAffinity.score(&ctx, &pod("incoming", &[]), &labeled("a", &[])),
+            0
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use k8s_openapi::api::core::v1::{Node, Pod};
+
+use crate::{
+    config::{RequestedToCapacityRatioArgs, ResourceSpec},
+    scheduler::{
+        resources::{pod_requests, utilization_after_placement},
+        scores::{ScoreContext, ScorePlugin, MAX_NODE_SCORE},
+    },
+};
+
+// Highest score a point of the user defined utilization shape may have
+const MAX_SHAPE_SCORE: i64 = 10;
+
+// Score nodes by mapping the utilization of each resource after placement onto a user
+// defined piecewise linear function, as done by the upstream RequestedToCapacityRatio
+// scoring strategy
+pub(crate) struct RequestedToCapacityRatio {
+    // (utilization in percent, score between 0 and MAX_NODE_SCORE) sorted by utilization
+    shape: Vec<(f64, f64)>,
+    resources: Vec<ResourceSpec>,
+}
+
+impl RequestedToCapacityRatio {
+    pub(crate) fn new(args: &RequestedToCapacityRatioArgs) -> Self {
+        Self {
+            shape: args
+                .shape
+                .iter()
+                .map(|point| {
+                    (
+                        point.utilization as f64,
+                        (point.score * MAX_NODE_SCORE / MAX_SHAPE_SCORE) as f64,
+                    )
+                })
+                .collect(),
+            resources: args.resources.clone(),
+        }
+    }
+
+    // Evaluate the piecewise linear shape at the given utilization
+    fn shape_score(&self, utilization: f64) -> f64 {
+        let Some(&(first_utilization, first_score)) = self.shape.first() else { return 0.0 };
+        if utilization <= first_utilization {
+            return first_score;
+        }
+
+        for points in self.shape.windows(2) {
+            let (x0, y0) = points[0];
+            let (x1, y1) = points[1];
+
+            if utilization <= x1 {
+                return y0 + (y1 - y0) * (utilization - x0) / (x1 - x0);
+            }
+        }
+
+        self.shape
+            .last()
+            .map(|&(_, score)| score)
+            .unwrap_or_default()
+    }
+}
+
+impl ScorePlugin for RequestedToCapacityRatio {
+    fn score(&self, ctx: &ScoreContext, pod: &Pod, node: &Node) -> i64 {
+        let resources: Vec<&str> = self
+            .resources
+            .iter()
+            .map(|resource| resource.name.as_str())
+            .collect();
+        let utilization =
+            utilization_after_placement(node, ctx.node_pods(node), &pod_requests(pod), &resources);
+
+        let mut node_score = 0.0;
+        let mut weight_sum = 0;
+        for ResourceSpec { name, weight } in &self.resources {
+            let Some(fraction) = utilization.get(name) else { continue };
+
+            // Requesting more than is allocatable counts as full utilization
+            let utilization = (fraction * 100.0).clamp(0.0, 100.0);
+
+            node_score += self.shape_score(utilization) * *weight as f64;
+            weight_sum += weight;
+        }
+
+        if weight_sum == 0 {
+            return 0;
+        }
+
+        (node_score / weight_sum as f64).round() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::{
+        config::UtilizationShapePoint,
+        scheduler::testing::{node, pod},
+    };
+
+    use super::*;
+
+    fn plugin(shape: &[(i64, i64)], resources: &[(&str, i64)]) -> RequestedToCapacityRatio {
+        RequestedToCapacityRatio::new(&RequestedToCapacityRatioArgs {
+            shape: shape
+                .iter()
+                .map(|&(utilization, score)| UtilizationShapePoint { utilization, score })
+                .collect(),
+            resources: resources
+                .iter()
+                .map(|&(name, weight)| ResourceSpec {
+                    name: name.to_owned(),
+                    weight,
+                })
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn test_shape_interpolation() {
+        let plugin = plugin(&[(20, 0), (60, 10), (100, 2)], &[]);
+
+        assert_eq!(plugin.shape_score(0.0), 0.0);
+        assert_eq!(plugin.shape_score(40.0), 50.0);
+        assert_eq!(plugin.shape_score(60.0), 100.0);
+        assert_eq!(plugin.shape_score(80.0), 60.0);
+        assert_eq!(plugin.shape_score(100.0), 20.0);
+    }
+
+    #[test]
+    fn test_score_bin_packing_shape_prefers_fuller_node() {
+        let plugin = plugin(&[(0, 0), (100, 10)], &[("cpu", 1), ("memory", 1)]);
+        let allocatable = [("cpu", "4"), ("memory", "4Gi")];
+
+        let nodes = [node("empty", &allocatable), node("busy", &allocatable)];
+        let state = BTreeMap::from_iter(vec![
+            ("empty".to_string(), vec![]),
+            (
+                "busy".to_string(),
+                vec![pod("existing", &[("cpu", "2"), ("memory", "2Gi")])],
+            ),
+        ]);
+        let ctx = ScoreContext {
+            nodes: &[],
+            state: &state,
+        };
+        let incoming = pod("incoming", &[("cpu", "1"), ("memory", "1Gi")]);
+
+        assert_eq!(plugin.score(&ctx, &incoming, &nodes[0]), 25);
+        assert_eq!(plugin.score(&ctx, &incoming, &nodes[1]), 75);
+    }
+
+    #[test]
+    fn test_score_resource_weights() {
+        let plugin = plugin(&[(0, 0), (100, 10)], &[("cpu", 3), ("memory", 1)]);
+
+        let nodes = [node("a", &[("cpu", "4"), ("memory", "4Gi")])];
+        let state = BTreeMap::from_iter(vec![("a".to_string(), vec![])]);
+        let ctx = ScoreContext {
+            nodes: &[],
+            state: &state,
+        };
+
+        // cpu is fully utilized, memory is not used at all
+        let incoming = pod("incoming", &[("cpu", "4")]);
+
+        assert_eq!(plugin.score(&ctx, &incoming, &nodes[0]), 75);
+    }
+}
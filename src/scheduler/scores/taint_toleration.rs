@@ -0,0 +1,121 @@
+use k8s_openapi::api::core::v1::{Node, Pod};
+
+use crate::scheduler::{
+    scores::{normalize, ScoreContext, ScorePlugin},
+    selectors::tolerates,
+};
+
+// Avoid nodes with PreferNoSchedule taints the pod does not tolerate, the soft counterpart
+// of the taint toleration filter. A node is scored with the number of intolerable taints,
+// which the normalization reverses so the node with the fewest gets the highest score.
+pub(crate) struct TaintToleration;
+
+impl ScorePlugin for TaintToleration {
+    fn score(&self, _ctx: &ScoreContext, pod: &Pod, node: &Node) -> i64 {
+        let Some(taints) = node.spec.as_ref().and_then(|spec| spec.taints.as_ref()) else {
+            return 0;
+        };
+
+        // Only tolerations applying to PreferNoSchedule taints count
+        let tolerations: Vec<_> = pod
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.tolerations.as_ref())
+            .into_iter()
+            .flatten()
+            .filter(|toleration| {
+                matches!(
+                    toleration.effect.as_deref(),
+                    None | Some("") | Some("PreferNoSchedule")
+                )
+            })
+            .collect();
+
+        taints
+            .iter()
+            .filter(|taint| taint.effect == "PreferNoSchedule")
+            .filter(|taint| {
+                !tolerations
+                    .iter()
+                    .any(|toleration| tolerates(toleration, taint))
+            })
+            .count() as i64
+    }
+
+    fn normalize(&self, scores: &mut [i64]) {
+        normalize(scores, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use k8s_openapi::api::core::v1::{Taint, Toleration};
+
+    use crate::scheduler::{
+        scores::MAX_NODE_SCORE,
+        testing::{node, pod},
+    };
+
+    use super::*;
+
+    fn tainted(name: &str, taints: &[(&str, &str)]) -> Node {
+        let mut node = node(name, &[]);
+        if let Some(spec) = node.spec.as_mut() {
+            spec.taints = Some(
+                taints
+                    .iter()
+                    .map(|(key, effect)| Taint {
+                        key: key.to_string(),
+                        effect: effect.to_string(),
+                        ..Default::default()
+                    })
+                    .collect(),
+            );
+        }
+
+        node
+    }
+
+    #[test]
+    fn test_score_prefers_fewer_intolerable_taints() {
+        let nodes = [
+            tainted("clean", &[]),
+            tainted("one", &[("spot", "PreferNoSchedule")]),
+            tainted(
+                "two",
+                &[("spot", "PreferNoSchedule"), ("legacy", "PreferNoSchedule")],
+            ),
+            tainted("hard", &[("gpu", "NoSchedule")]),
+        ];
+        let state = BTreeMap::new();
+        let ctx = ScoreContext {
+            nodes: &nodes,
+            state: &state,
+        };
+
+        let mut incoming = pod("incoming", &[]);
+        let mut scores: Vec<i64> = nodes
+            .iter()
+            .map(|node| TaintToleration.score(&ctx, &incoming, node))
+            .collect();
+        assert_eq!(scores, [0, 1, 2, 0]);
+        TaintToleration.normalize(&mut scores);
+        assert_eq!(scores, [MAX_NODE_SCORE, 50, 0, MAX_NODE_SCORE]);
+
+        // Tolerating the spot taint leaves only the legacy taint to avoid
+        if let Some(spec) = incoming.spec.as_mut() {
+            spec.tolerations = Some(vec![Toleration {
+                key: Some("spot".to_owned()),
+                operator: Some("Exists".to_owned()),
+                ..Default::default()
+            }]);
+        }
+        let scores: Vec<i64> = nodes
+            .iter()
+            .map(|node| TaintToleration.score(&ctx, &incoming, node))
+            .collect();
+        assert_eq!(scores, [0, 0, 1, 0]);
+    }
+}
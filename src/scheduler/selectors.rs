@@ -0,0 +1,238 @@
+// Matching of nodes against node selector terms, of labels against label selectors and of
+// taints against tolerations, following the upstream semantics: the requirements of a term
+// or selector are ANDed, an empty node selector term matches no node while an empty label
+// selector matches all labels
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::{
+    api::core::v1::{Node, NodeSelectorRequirement, NodeSelectorTerm, Taint, Toleration},
+    apimachinery::pkg::apis::meta::v1::LabelSelector,
+};
+
+pub(crate) fn node_matches_term(node: &Node, term: &NodeSelectorTerm) -> bool {
+    let expressions = term.match_expressions.as_deref().unwrap_or_default();
+    let fields = term.match_fields.as_deref().unwrap_or_default();
+    if expressions.is_empty() && fields.is_empty() {
+        return false;
+    }
+
+    let empty = BTreeMap::new();
+    let labels = node.metadata.labels.as_ref().unwrap_or(&empty);
+
+    expressions
+        .iter()
+        .all(|requirement| requirement_matches(requirement, labels.get(&requirement.key)))
+        && fields.iter().all(|requirement| {
+            // metadata.name is the only field nodes can be selected by
+            requirement.key == "metadata.name"
+                && requirement_matches(requirement, node.metadata.name.as_ref())
+        })
+}
+
+pub(crate) fn labels_match_selector(
+    labels: &BTreeMap<String, String>,
+    selector: &LabelSelector,
+) -> bool {
+    let mut match_labels = selector.match_labels.iter().flatten();
+    let mut match_expressions = selector.match_expressions.iter().flatten();
+
+    match_labels.all(|(key, value)| labels.get(key) == Some(value))
+        && match_expressions.all(|requirement| {
+            let values = requirement.values.as_deref().unwrap_or_default();
+
+            match (requirement.operator.as_str(), labels.get(&requirement.key)) {
+                ("In", Some(value)) => values.contains(value),
+                ("NotIn", value) => !matches!(value, Some(value) if values.contains(value)),
+                ("Exists", value) => value.is_some(),
+                ("DoesNotExist", value) => value.is_none(),
+                _ => false,
+            }
+        })
+}
+
+// Whether the toleration tolerates the taint. An empty effect tolerates all effects and an
+// empty key with the Exists operator tolerates all taints.
+pub(crate) fn tolerates(toleration: &Toleration, taint: &Taint) -> bool {
+    let effect = toleration.effect.as_deref().unwrap_or_default();
+    if !effect.is_empty() && effect != taint.effect {
+        return false;
+    }
+
+    let key = toleration.key.as_deref().unwrap_or_default();
+    if !key.is_empty() && key != taint.key {
+        return false;
+    }
+
+    match toleration.operator.as_deref().unwrap_or("Equal") {
+        "Exists" => true,
+        "Equal" => {
+            !key.is_empty()
+                && toleration.value.as_deref().unwrap_or_default()
+                    == taint.value.as_deref().unwrap_or_default()
+        }
+        _ => false,
+    }
+}
+
+fn requirement_matches(requirement: &NodeSelectorRequirement, value: Option<&String>) -> bool {
+    let values = requirement.values.as_deref().unwrap_or_default();
+
+    match (requirement.operator.as_str(), value) {
+        ("In", Some(value)) => values.contains(value),
+        ("NotIn", Some(value)) => !values.contains(value),
+        ("NotIn", None) => true,
+        ("Exists", value) => value.is_some(),
+        ("DoesNotExist", value) => value.is_none(),
+        ("Gt" | "Lt", Some(value)) => {
+            let (Ok(value), [bound]) = (value.parse::<i64>(), values) else { return false };
+            let Ok(bound) = bound.parse::<i64>() else { return false };
+
+            if requirement.operator == "Gt" {
+                value > bound
+            } else {
+                value < bound
+            }
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelectorRequirement;
+
+    use crate::scheduler::testing::node;
+
+    use super::*;
+
+    fn requirement(key: &str, operator: &str, values: &[&str]) -> NodeSelectorRequirement {
+        NodeSelectorRequirement {
+            key: key.to_owned(),
+            operator: operator.to_owned(),
+            values: Some(values.iter().map(|value| value.to_string()).collect()),
+        }
+    }
+
+    fn term(expressions: Vec<NodeSelectorRequirement>) -> NodeSelectorTerm {
+        NodeSelectorTerm {
+            match_expressions: Some(expressions),
+            match_fields: None,
+        }
+    }
+
+    #[test]
+    fn test_operators() {
+        let mut labeled = node("a", &[]);
+        labeled.metadata.labels = Some(BTreeMap::from_iter(vec![
+            ("zone".to_string(), "eu-1".to_string()),
+            ("cores".to_string(), "8".to_string()),
+        ]));
+
+        for (requirement, expected) in [
+            (requirement("zone", "In", &["eu-1", "eu-2"]), true),
+            (requirement("zone", "NotIn", &["eu-1"]), false),
+            (requirement("gpu", "NotIn", &["a100"]), true),
+            (requirement("zone", "Exists", &[]), true),
+            (requirement("gpu", "DoesNotExist", &[]), true),
+            (requirement("cores", "Gt", &["4"]), true),
+            (requirement("cores", "Lt", &["4"]), false),
+            (requirement("zone", "Gt", &["4"]), false),
+        ] {
+            assert_eq!(
+                node_matches_term(&labeled, &term(vec![requirement.clone()])),
+                expected,
+                "{requirement:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_label_selector() {
+        let labels = BTreeMap::from_iter(vec![("app".to_string(), "cache".to_string())]);
+        let selector = |match_labels: &[(&str, &str)], operator: &str| LabelSelector {
+            match_labels: Some(
+                match_labels
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            ),
+            match_expressions: Some(vec![LabelSelectorRequirement {
+                key: "tier".to_owned(),
+                operator: operator.to_owned(),
+                values: Some(vec!["frontend".to_owned()]),
+            }]),
+        };
+
+        assert!(labels_match_selector(&labels, &LabelSelector::default()));
+        assert!(labels_match_selector(
+            &labels,
+            &selector(&[("app", "cache")], "NotIn")
+        ));
+        assert!(!labels_match_selector(
+            &labels,
+            &selector(&[("app", "web")], "NotIn")
+        ));
+        assert!(!labels_match_selector(
+            &labels,
+            &selector(&[("app", "cache")], "In")
+        ));
+    }
+
+    #[test]
+    fn test_tolerations() {
+        let taint = Taint {
+            key: "dedicated".to_owned(),
+            value: Some("gpu".to_owned()),
+            effect: "PreferNoSchedule".to_owned(),
+            time_added: None,
+        };
+        let toleration = |key: &str, operator: &str, value: &str, effect: &str| Toleration {
+            key: Some(key.to_owned()),
+            operator: Some(operator.to_owned()),
+            value: Some(value.to_owned()),
+            effect: Some(effect.to_owned()),
+            toleration_seconds: None,
+        };
+
+        assert!(tolerates(
+            &toleration("dedicated", "Equal", "gpu", ""),
+            &taint
+        ));
+        assert!(tolerates(
+            &toleration("dedicated", "Exists", "", "PreferNoSchedule"),
+            &taint
+        ));
+        assert!(tolerates(&toleration("", "Exists", "", ""), &taint));
+        assert!(!tolerates(
+            &toleration("dedicated", "Equal", "cpu", ""),
+            &taint
+        ));
+        assert!(!tolerates(
+            &toleration("dedicated", "Equal", "gpu", "NoSchedule"),
+            &taint
+        ));
+        assert!(!tolerates(&toleration("", "Equal", "gpu", ""), &taint));
+    }
+
+    #[test]
+    fn test_term_semantics() {
+        let node = node("a", &[]);
+
+        assert!(!node_matches_term(&node, &term(vec![])));
+        assert!(node_matches_term(
+            &node,
+            &NodeSelectorTerm {
+                match_expressions: None,
+                match_fields: Some(vec![requirement("metadata.name", "In", &["a"])]),
+            }
+        ));
+        assert!(!node_matches_term(
+            &node,
+            &term(vec![
+                requirement("zone", "DoesNotExist", &[]),
+                requirement("zone", "Exists", &[]),
+            ])
+        ));
+    }
+}
@@ -0,0 +1,502 @@
+// Shadow mode: evaluate the algorithm against another scheduler on real traffic. Whenever a
+// pod of the other scheduler gets bound, the algorithm places the pod on the cluster as it
+// was at that moment and the decision is compared with the node the other scheduler chose.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    time::Duration,
+};
+
+use color_eyre::Result;
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::{Node, Pod};
+use kube::{
+    api::ListParams,
+    core::{ListMeta, ObjectList},
+    runtime::{reflector::Store, watcher},
+    Api, Client,
+};
+use prometheus::{Gauge, Histogram, HistogramOpts, IntCounter, IntCounterVec, Opts, Registry};
+use serde::Serialize;
+
+use crate::{
+    config::Profile,
+    scheduler::{
+        algorithms::{bin_packing, least_allocated, plugin_scores},
+        capacity::{apply_overcommit, HeldCapacity},
+        extenders,
+        filters::{self, feasible_nodes, FilterContext},
+        queue::pod_key,
+        report::append_jsonl,
+        resources::pod_requests,
+        schedule,
+        scores::{self, ScoreContext},
+        volumes::{reflect, reflect_matching},
+        Caches, WorldState,
+    },
+    Algorithm,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ShadowDecision {
+    pub(crate) pod: String,
+    // Node the other scheduler bound the pod to
+    pub(crate) actual_node: String,
+    // Node the algorithm would have chosen
+    pub(crate) chosen_node: Option<String>,
+    pub(crate) agreed: bool,
+    // Scores of both nodes as the algorithm rates them, missing for infeasible nodes
+    pub(crate) actual_score: Option<f64>,
+    pub(crate) chosen_score: Option<f64>,
+    // How much better the algorithm rates its own choice
+    pub(crate) score_delta: Option<f64>,
+}
+
+pub(crate) struct ShadowMetrics {
+    decisions: IntCounterVec,
+    agreement_ratio: Gauge,
+    score_delta: Histogram,
+    errors: IntCounter,
+}
+
+impl ShadowMetrics {
+    pub(crate) fn new(registry: &Registry) -> Result<Self> {
+        let decisions = IntCounterVec::new(
+            Opts::new(
+                "shadow_decisions_total",
+                "Pods bound by the shadowed scheduler, by agreement of the algorithm",
+            ),
+            &["agreed"],
+        )?;
+        let agreement_ratio = Gauge::new(
+            "shadow_agreement_ratio",
+            "Fraction of pods the algorithm placed onto the same node as the shadowed scheduler",
+        )?;
+        let score_delta = Histogram::with_opts(
+            HistogramOpts::new(
+                "shadow_score_delta",
+                "Score of the node chosen by the algorithm minus the score of the node chosen by the shadowed scheduler",
+            )
+            .buckets(vec![0.0, 1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 200.0]),
+        )?;
+
+        let errors = IntCounter::new(
+            "shadow_errors_total",
+            "Failures of the shadow mode, such as bindings that could not be evaluated",
+        )?;
+
+        registry.register(Box::new(decisions.clone()))?;
+        registry.register(Box::new(agreement_ratio.clone()))?;
+        registry.register(Box::new(score_delta.clone()))?;
+        registry.register(Box::new(errors.clone()))?;
+
+        Ok(Self {
+            decisions,
+            agreement_ratio,
+            score_delta,
+            errors,
+        })
+    }
+
+    pub(crate) fn record(&self, decision: &ShadowDecision) {
+        self.decisions
+            .with_label_values(&[if decision.agreed { "true" } else { "false" }])
+            .inc();
+
+        let agreed = self.decisions.with_label_values(&["true"]).get();
+        let disagreed = self.decisions.with_label_values(&["false"]).get();
+        self.agreement_ratio
+            .set(agreed as f64 / (agreed + disagreed) as f64);
+
+        if let Some(score_delta) = decision.score_delta {
+            self.score_delta.observe(score_delta);
+        }
+    }
+}
+
+// Watch the pods of the shadowed scheduler and evaluate each binding. Failures are counted
+// and skip the binding, so the shadow mode keeps running next to the scheduler.
+pub(crate) async fn run_shadow(
+    client: Client,
+    algorithm: Algorithm,
+    profile: Profile,
+    shadowed_scheduler_name: String,
+    caches: Caches,
+    report: Option<PathBuf>,
+    metrics: ShadowMetrics,
+) {
+    let pods: Api<Pod> = Api::all(client.clone());
+    let lp = ListParams::default()
+        .fields(format!("spec.schedulerName={shadowed_scheduler_name}").as_str());
+
+    // Cluster the bindings are evaluated against
+    let nodes = reflect(Api::<Node>::all(client.clone()));
+    let bound_pods = reflect_matching(
+        pods.clone(),
+        ListParams::default().fields("spec.nodeName!="),
+    );
+
+    log::info!("Shadowing scheduler {shadowed_scheduler_name}");
+
+    // Pods whose binding was seen already
+    let mut bound: BTreeSet<String> = BTreeSet::new();
+
+    let mut shadow_watcher = watcher(pods, lp).boxed();
+    while let Some(event) = shadow_watcher.next().await {
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => {
+                log::warn!("Watch of shadowed pods failed: {err}");
+                metrics.errors.inc();
+                // The watcher restarts on the next poll, give the API server a moment
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        match event {
+            watcher::Event::Applied(pod) => {
+                let Some(actual_node) = node_name(&pod) else { continue };
+                if !bound.insert(pod_key(&pod)) {
+                    continue;
+                }
+
+                let world = world_at_binding(&nodes, &bound_pods, &caches, &profile, &pod);
+                let decision = match evaluate_blocking(&algorithm, &profile, world, actual_node)
+                    .await
+                {
+                    Ok(decision) => decision,
+                    Err(err) => {
+                        log::warn!("Failed to evaluate shadowed pod {}: {err:#}", pod_key(&pod));
+                        metrics.errors.inc();
+                        continue;
+                    }
+                };
+                log::info!(
+                    "Shadowed pod {} bound to {}, algorithm chose {:?}",
+                    decision.pod,
+                    decision.actual_node,
+                    decision.chosen_node
+                );
+
+                metrics.record(&decision);
+                if let Some(report) = &report {
+                    if let Err(err) = append_jsonl(report, &[&decision]) {
+                        log::warn!("Failed to write shadow report: {err:#}");
+                        metrics.errors.inc();
+                    }
+                }
+            }
+            watcher::Event::Deleted(pod) => {
+                bound.remove(&pod_key(&pod));
+            }
+            // Pods bound before the watch (re)started cannot be evaluated at the moment of
+            // their binding anymore
+            watcher::Event::Restarted(pods) => {
+                bound = pods
+                    .iter()
+                    .filter(|pod| node_name(pod).is_some())
+                    .map(pod_key)
+                    .collect();
+            }
+        }
+    }
+}
+
+fn node_name(pod: &Pod) -> Option<String> {
+    pod.spec.as_ref()?.node_name.clone()
+}
+
+// Cluster next to the pod as reflected at its binding, with the pod itself waiting to be
+// scheduled
+fn world_at_binding(
+    nodes: &Store<Node>,
+    bound_pods: &Store<Pod>,
+    caches: &Caches,
+    profile: &Profile,
+    pod: &Pod,
+) -> WorldState {
+    let mut nodes: Vec<Node> = nodes.state().iter().map(|node| (**node).clone()).collect();
+    apply_overcommit(&mut nodes, &profile.overcommit);
+
+    let key = pod_key(pod);
+    let mut state: BTreeMap<String, ObjectList<Pod>> = nodes
+        .iter()
+        .filter_map(|node| node.metadata.name.clone())
+        .map(|node_name| {
+            (
+                node_name,
+                ObjectList {
+                    metadata: ListMeta::default(),
+                    items: vec![],
+                },
+            )
+        })
+        .collect();
+    for bound_pod in bound_pods.state() {
+        if pod_key(&bound_pod) == key {
+            continue;
+        }
+        let Some(node_pods) = node_name(&bound_pod).and_then(|node_name| state.get_mut(&node_name))
+        else {
+            continue;
+        };
+        node_pods.items.push((*bound_pod).clone());
+    }
+
+    let mut pod = pod.clone();
+    if let Some(spec) = pod.spec.as_mut() {
+        spec.node_name = None;
+    }
+
+    WorldState {
+        nodes: ObjectList {
+            metadata: ListMeta::default(),
+            items: nodes,
+        },
+        unscheduled_pods: ObjectList {
+            metadata: ListMeta::default(),
+            items: vec![pod],
+        },
+        state,
+        volumes: caches.volumes.snapshot(),
+        load: caches.load.snapshot(),
+        capacity: HeldCapacity::new(&profile.headroom, caches.reservations.snapshot()),
+    }
+}
+
+// Evaluate a binding on the blocking thread pool, as the filter and score plugins involved
+// are synchronous
+async fn evaluate_blocking(
+    algorithm: &Algorithm,
+    profile: &Profile,
+    world: WorldState,
+    actual_node: String,
+) -> Result<ShadowDecision> {
+    let algorithm = algorithm.clone();
+    let profile = profile.clone();
+    let runtime = tokio::runtime::Handle::current();
+
+    tokio::task::spawn_blocking(move || {
+        runtime.block_on(evaluate(&algorithm, &profile, world, &actual_node))
+    })
+    .await?
+}
+
+// Place the single unscheduled pod of the world with the algorithm and compare the result
+// with the node the shadowed scheduler chose
+pub(crate) async fn evaluate(
+    algorithm: &Algorithm,
+    profile: &Profile,
+    world: WorldState,
+    actual_node: &str,
+) -> Result<ShadowDecision> {
+    let Some(pod) = world.unscheduled_pods.items.first().cloned() else {
+        color_eyre::eyre::bail!("Shadow evaluation requires a pod to schedule");
+    };
+    let scores = node_scores(algorithm, profile, &world, &pod).await?;
+
+    let target = schedule(algorithm, world, profile).await?;
+    let key = pod_key(&pod);
+    let chosen_node = target
+        .placements()
+        .find(|(_, placed)| pod_key(placed) == key)
+        .map(|(node_name, _)| node_name.to_owned());
+
+    let actual_score = scores.get(actual_node).copied();
+    let chosen_score = chosen_node
+        .as_ref()
+        .and_then(|node_name| scores.get(node_name))
+        .copied();
+
+    Ok(ShadowDecision {
+        pod: key,
+        actual_node: actual_node.to_owned(),
+        agreed: chosen_node.as_deref() == Some(actual_node),
+        chosen_node,
+        actual_score,
+        chosen_score,
+        score_delta: chosen_score
+            .zip(actual_score)
+            .map(|(chosen, actual)| chosen - actual),
+    })
+}
+
+// Rate the nodes for the pod through the filters, extenders and scores the algorithm places
+// pods with. Optimal and Annealing refine the placement of bin packing, so their nodes are
+// rated the way bin packing rates them.
+async fn node_scores(
+    algorithm: &Algorithm,
+    profile: &Profile,
+    world: &WorldState,
+    pod: &Pod,
+) -> Result<BTreeMap<String, f64>> {
+    let state: BTreeMap<String, Vec<Pod>> = world
+        .state
+        .iter()
+        .map(|(node_name, node_pods)| (node_name.clone(), node_pods.items.clone()))
+        .collect();
+    let requests = pod_requests(pod);
+
+    let filter_plugins = filters::plugins(profile, &world.load);
+    let extenders = extenders::extenders(profile);
+
+    let ctx = FilterContext {
+        state: &state,
+        volumes: &world.volumes,
+        capacity: &world.capacity,
+    };
+    let feasible_nodes = feasible_nodes(&world.nodes.items, pod, &ctx, &filter_plugins);
+    let feasible_nodes = extenders::filter(&extenders, pod, feasible_nodes).await?;
+
+    let score = match algorithm {
+        Algorithm::LeastAllocated => least_allocated::score,
+        Algorithm::BinPacking | Algorithm::Optimal | Algorithm::Annealing => bin_packing::score,
+    };
+    let node_scores: Vec<(&Node, f64)> = feasible_nodes
+        .into_iter()
+        .filter_map(|node| {
+            let node_pods = state.get(node.metadata.name.as_ref()?)?;
+
+            Some((node, score(node, node_pods, &requests)?))
+        })
+        .collect();
+
+    let candidates: Vec<&Node> = node_scores.iter().map(|(node, _)| *node).collect();
+    let plugin_scores = plugin_scores(
+        &scores::plugins(profile, &world.load),
+        &extenders,
+        &ScoreContext {
+            nodes: &world.nodes.items,
+            state: &state,
+        },
+        pod,
+        &candidates,
+    )
+    .await;
+
+    Ok(node_scores
+        .into_iter()
+        .zip(plugin_scores)
+        .filter_map(|((node, score), plugin_score)| {
+            Some((node.metadata.name.clone()?, score + plugin_score as f64))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        config::ExtenderConfig,
+        scheduler::testing::{node, pod, stand_in, world},
+    };
+
+    use super::*;
+
+    fn half_full_world() -> WorldState {
+        let allocatable = [("cpu", "4"), ("memory", "4Gi")];
+
+        let mut world = world(
+            vec![node("a", &allocatable), node("b", &allocatable)],
+            vec![pod("p", &[("cpu", "1")])],
+        );
+        if let Some(pods) = world.state.get_mut("a") {
+            pods.items.push(pod("existing", &[("cpu", "2")]));
+        }
+
+        world
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_agreement() {
+        let decision = evaluate(
+            &Algorithm::BinPacking,
+            &Profile::default(),
+            half_full_world(),
+            "a",
+        )
+        .await
+        .unwrap();
+
+        assert!(decision.agreed);
+        assert_eq!(decision.score_delta, Some(0.0));
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_disagreement() {
+        let decision = evaluate(
+            &Algorithm::BinPacking,
+            &Profile::default(),
+            half_full_world(),
+            "b",
+        )
+        .await
+        .unwrap();
+
+        assert!(!decision.agreed);
+        assert_eq!(decision.chosen_node.as_deref(), Some("a"));
+        assert!(decision.score_delta.unwrap() > 0.0);
+
+        let decision = evaluate(
+            &Algorithm::LeastAllocated,
+            &Profile::default(),
+            half_full_world(),
+            "b",
+        )
+        .await
+        .unwrap();
+        assert!(decision.agreed);
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_honors_extenders() {
+        // Extender rejecting node "a", which bin packing would prefer otherwise
+        let url_prefix = stand_in(|_, _| json!({ "NodeNames": ["b"] }));
+
+        let profile = Profile {
+            extenders: vec![ExtenderConfig {
+                url_prefix,
+                filter_verb: Some("filter".to_owned()),
+                prioritize_verb: None,
+                bind_verb: None,
+                weight: 1,
+                timeout_ms: 1000,
+                ignorable: false,
+            }],
+            ..Default::default()
+        };
+
+        let decision = evaluate(&Algorithm::BinPacking, &profile, half_full_world(), "a")
+            .await
+            .unwrap();
+
+        assert_eq!(decision.chosen_node.as_deref(), Some("b"));
+        assert_eq!(decision.actual_score, None);
+        assert!(decision.chosen_score.is_some());
+    }
+
+    #[test]
+    fn test_metrics_track_agreement_ratio() {
+        let metrics = ShadowMetrics::new(&Registry::new()).unwrap();
+        let decision = |agreed| ShadowDecision {
+            pod: "default/p".to_string(),
+            actual_node: "a".to_string(),
+            chosen_node: Some("a".to_string()),
+            agreed,
+            actual_score: None,
+            chosen_score: None,
+            score_delta: None,
+        };
+
+        metrics.record(&decision(true));
+        metrics.record(&decision(true));
+        metrics.record(&decision(false));
+        metrics.record(&decision(true));
+
+        assert_eq!(metrics.agreement_ratio.get(), 0.75);
+    }
+}
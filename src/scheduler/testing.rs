@@ -0,0 +1,255 @@
+// Builders for the Kubernetes objects used throughout the scheduler tests
+
+use std::{collections::BTreeMap, convert::Infallible, net::SocketAddr, time::Duration};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+
+use k8s_openapi::{
+    api::{
+        core::v1::{
+            CSIPersistentVolumeSource, Container, Node, NodeSelector, NodeSelectorRequirement,
+            NodeSelectorTerm, NodeSpec, NodeStatus, PersistentVolume, PersistentVolumeClaim,
+            PersistentVolumeClaimSpec, PersistentVolumeClaimVolumeSource, PersistentVolumeSpec,
+            Pod, PodSpec, ResourceRequirements, Volume, VolumeNodeAffinity,
+        },
+        storage::v1::{CSINode, CSINodeDriver, CSINodeSpec, VolumeNodeResources},
+    },
+    apimachinery::pkg::{api::resource::Quantity, apis::meta::v1::ObjectMeta},
+};
+use kube::{
+    core::{ListMeta, ObjectList},
+    Resource,
+};
+use serde_json::Value;
+
+use crate::scheduler::{
+    capacity::{HeldCapacity, Reservation, ReservationSpec, RESERVATION_WORKLOAD_LABEL},
+    load::NodeLoad,
+    volumes::VolumeState,
+    WorldState,
+};
+
+pub(crate) fn node(name: &str, allocatable: &[(&str, &str)]) -> Node {
+    Node {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            ..Default::default()
+        },
+        spec: Some(NodeSpec::default()),
+        status: Some(NodeStatus {
+            allocatable: Some(quantities(allocatable)),
+            ..Default::default()
+        }),
+    }
+}
+
+pub(crate) fn pod(name: &str, requests: &[(&str, &str)]) -> Pod {
+    Pod {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            namespace: Some("default".to_string()),
+            ..Default::default()
+        },
+        spec: Some(PodSpec {
+            containers: vec![Container {
+                name: "main".to_string(),
+                resources: Some(ResourceRequirements {
+                    requests: Some(quantities(requests)),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+pub(crate) fn annotated<T: Resource>(mut object: T, annotations: &[(&str, &str)]) -> T {
+    object.meta_mut().annotations = Some(
+        annotations
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+    );
+
+    object
+}
+
+// Reservation holding the resources on the node for the workload in the namespace
+pub(crate) fn reservation(
+    namespace: &str,
+    workload: &str,
+    node_name: &str,
+    resources: &[(&str, &str)],
+) -> Reservation {
+    let mut reservation = Reservation::new(
+        workload,
+        ReservationSpec {
+            node_name: node_name.to_string(),
+            workload: workload.to_string(),
+            resources: quantities(resources),
+        },
+    );
+    reservation.metadata.namespace = Some(namespace.to_string());
+
+    reservation
+}
+
+pub(crate) fn in_workload(mut pod: Pod, namespace: &str, workload: &str) -> Pod {
+    pod.metadata.namespace = Some(namespace.to_string());
+    pod.metadata.labels = Some(BTreeMap::from_iter(vec![(
+        RESERVATION_WORKLOAD_LABEL.to_string(),
+        workload.to_string(),
+    )]));
+
+    pod
+}
+
+pub(crate) fn quantities(values: &[(&str, &str)]) -> BTreeMap<String, Quantity> {
+    values
+        .iter()
+        .map(|(k, v)| (k.to_string(), Quantity(v.to_string())))
+        .collect()
+}
+
+pub(crate) fn list<T: Clone>(items: Vec<T>) -> ObjectList<T> {
+    ObjectList {
+        metadata: ListMeta::default(),
+        items,
+    }
+}
+
+// World state with the given nodes, no pods bound to them yet and the given pods waiting
+// to be scheduled
+pub(crate) fn world(nodes: Vec<Node>, unscheduled_pods: Vec<Pod>) -> WorldState {
+    let state = nodes
+        .iter()
+        .filter_map(|node| node.metadata.name.clone())
+        .map(|name| (name, list(vec![])))
+        .collect();
+
+    WorldState {
+        nodes: list(nodes),
+        unscheduled_pods: list(unscheduled_pods),
+        state,
+        volumes: VolumeState::default(),
+        load: NodeLoad::default(),
+        capacity: HeldCapacity::default(),
+    }
+}
+
+// Driver attaching the volumes built by volume
+pub(crate) const CSI_DRIVER: &str = "ebs.csi.aws.com";
+
+pub(crate) fn with_claims(mut pod: Pod, claims: &[&str]) -> Pod {
+    if let Some(spec) = pod.spec.as_mut() {
+        spec.volumes = Some(
+            claims
+                .iter()
+                .map(|claim| Volume {
+                    name: claim.to_string(),
+                    persistent_volume_claim: Some(PersistentVolumeClaimVolumeSource {
+                        claim_name: claim.to_string(),
+                        read_only: None,
+                    }),
+                    ..Default::default()
+                })
+                .collect(),
+        );
+    }
+
+    pod
+}
+
+pub(crate) fn claim(name: &str, volume_name: &str, access_mode: &str) -> PersistentVolumeClaim {
+    PersistentVolumeClaim {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some("default".to_owned()),
+            ..Default::default()
+        },
+        spec: Some(PersistentVolumeClaimSpec {
+            volume_name: Some(volume_name.to_owned()),
+            access_modes: Some(vec![access_mode.to_owned()]),
+            ..Default::default()
+        }),
+        status: None,
+    }
+}
+
+pub(crate) fn volume(name: &str, zone: Option<&str>) -> PersistentVolume {
+    PersistentVolume {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            ..Default::default()
+        },
+        spec: Some(PersistentVolumeSpec {
+            csi: Some(CSIPersistentVolumeSource {
+                driver: CSI_DRIVER.to_owned(),
+                volume_handle: name.to_owned(),
+                ..Default::default()
+            }),
+            node_affinity: zone.map(|zone| VolumeNodeAffinity {
+                required: Some(NodeSelector {
+                    node_selector_terms: vec![NodeSelectorTerm {
+                        match_expressions: Some(vec![NodeSelectorRequirement {
+                            key: "zone".to_owned(),
+                            operator: "In".to_owned(),
+                            values: Some(vec![zone.to_owned()]),
+                        }]),
+                        match_fields: None,
+                    }],
+                }),
+            }),
+            ..Default::default()
+        }),
+        status: None,
+    }
+}
+
+pub(crate) fn csi_node(name: &str, limit: i32) -> CSINode {
+    CSINode {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            ..Default::default()
+        },
+        spec: CSINodeSpec {
+            drivers: vec![CSINodeDriver {
+                name: CSI_DRIVER.to_owned(),
+                node_id: name.to_owned(),
+                allocatable: Some(VolumeNodeResources { count: Some(limit) }),
+                topology_keys: None,
+            }],
+        },
+    }
+}
+
+// Stand-in HTTP server answering each request with the response of the handler to its path
+// and JSON body, returning the URL it listens on
+pub(crate) fn stand_in(handler: fn(&str, Value) -> Value) -> String {
+    stand_in_after(Duration::ZERO, handler)
+}
+
+// Stand-in HTTP server like stand_in, answering each request only after the given delay
+pub(crate) fn stand_in_after(delay: Duration, handler: fn(&str, Value) -> Value) -> String {
+    let make_service = make_service_fn(move |_| async move {
+        Ok::<_, Infallible>(service_fn(move |request: Request<Body>| async move {
+            let path = request.uri().path().to_owned();
+            let body = hyper::body::to_bytes(request.into_body()).await?;
+            let args = serde_json::from_slice(&body).unwrap_or_default();
+            tokio::time::sleep(delay).await;
+
+            Ok::<_, hyper::Error>(Response::new(Body::from(handler(&path, args).to_string())))
+        }))
+    });
+
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+
+    url
+}
@@ -0,0 +1,874 @@
+// Volumes of pods: a cache of the claims, volumes, storage classes and CSI nodes of the
+// cluster, kept up to date by reflectors and snapshotted for each scheduler run, and the
+// filters placing pods only where their volumes can be attached
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+    hash::Hash,
+    time::Duration,
+};
+
+use futures::StreamExt;
+use k8s_openapi::{
+    api::{
+        core::v1::{Node, PersistentVolume, PersistentVolumeClaim, Pod},
+        storage::v1::{CSINode, StorageClass},
+    },
+    apimachinery::pkg::api::resource::Quantity,
+};
+use kube::{
+    api::ListParams,
+    runtime::{reflector, reflector::Store, watcher},
+    Api, Client, Resource,
+};
+use kube_quantity::ParsedQuantity;
+use serde::de::DeserializeOwned;
+
+use crate::scheduler::{
+    filters::FilterContext, queue::pod_key, selectors::node_matches_term, Reason, TargetState,
+};
+
+const READ_WRITE_ONCE_POD: &str = "ReadWriteOncePod";
+const WAIT_FOR_FIRST_CONSUMER: &str = "WaitForFirstConsumer";
+// Provisioner of storage classes whose volumes are created by hand instead of provisioned
+const NO_PROVISIONER: &str = "kubernetes.io/no-provisioner";
+
+// Reflected volume objects of the cluster
+#[derive(Clone)]
+pub(crate) struct VolumeCache {
+    claims: Store<PersistentVolumeClaim>,
+    volumes: Store<PersistentVolume>,
+    storage_classes: Store<StorageClass>,
+    csi_nodes: Store<CSINode>,
+}
+
+impl VolumeCache {
+    // Start reflecting the volume objects of the cluster
+    pub(crate) fn start(client: &Client) -> Self {
+        Self {
+            claims: reflect(Api::all(client.clone())),
+            volumes: reflect(Api::all(client.clone())),
+            storage_classes: reflect(Api::all(client.clone())),
+            csi_nodes: reflect(Api::all(client.clone())),
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> VolumeState {
+        fn items<K>(store: &Store<K>) -> Vec<K>
+        where
+            K: Resource + Clone,
+            K::DynamicType: Eq + Hash + Clone,
+        {
+            store
+                .state()
+                .iter()
+                .map(|item| item.as_ref().clone())
+                .collect()
+        }
+
+        VolumeState::new(
+            items(&self.claims),
+            items(&self.volumes),
+            items(&self.storage_classes),
+            items(&self.csi_nodes),
+        )
+    }
+}
+
+// Run a reflector of all objects of the kind in the background and return its store
+pub(crate) fn reflect<K>(api: Api<K>) -> Store<K>
+where
+    K: Resource + Clone + DeserializeOwned + Debug + Send + Sync + 'static,
+    K::DynamicType: Default + Eq + Hash + Clone,
+{
+    reflect_matching(api, ListParams::default())
+}
+
+// Run a reflector of the objects of the kind matching the list params in the background and
+// return its store
+pub(crate) fn reflect_matching<K>(api: Api<K>, lp: ListParams) -> Store<K>
+where
+    K: Resource + Clone + DeserializeOwned + Debug + Send + Sync + 'static,
+    K::DynamicType: Default + Eq + Hash + Clone,
+{
+    let (reader, writer) = reflector::store();
+    let mut stream = reflector(writer, watcher(api, lp)).boxed();
+
+    tokio::spawn(async move {
+        while let Some(event) = stream.next().await {
+            if let Err(err) = event {
+                log::warn!("Watch of {} failed: {err}", K::kind(&Default::default()));
+                // The watcher restarts on the next poll, give the API server a moment
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    });
+
+    reader
+}
+
+// Volume objects of the cluster at the start of a scheduler run
+#[derive(Clone, Default)]
+pub(crate) struct VolumeState {
+    // Claims by namespace and name
+    claims: BTreeMap<String, PersistentVolumeClaim>,
+    volumes: BTreeMap<String, PersistentVolume>,
+    storage_classes: BTreeMap<String, StorageClass>,
+    csi_nodes: BTreeMap<String, CSINode>,
+}
+
+impl VolumeState {
+    pub(crate) fn new(
+        claims: Vec<PersistentVolumeClaim>,
+        volumes: Vec<PersistentVolume>,
+        storage_classes: Vec<StorageClass>,
+        csi_nodes: Vec<CSINode>,
+    ) -> Self {
+        fn by_name<K: Resource>(items: Vec<K>) -> BTreeMap<String, K> {
+            items
+                .into_iter()
+                .filter_map(|item| Some((item.meta().name.clone()?, item)))
+                .collect()
+        }
+
+        Self {
+            claims: claims
+                .into_iter()
+                .filter_map(|claim| {
+                    let namespace = claim.metadata.namespace.as_deref().unwrap_or("default");
+                    let key = format!("{namespace}/{}", claim.metadata.name.as_ref()?);
+
+                    Some((key, claim))
+                })
+                .collect(),
+            volumes: by_name(volumes),
+            storage_classes: by_name(storage_classes),
+            csi_nodes: by_name(csi_nodes),
+        }
+    }
+
+    pub(crate) fn claim(&self, key: &str) -> Option<&PersistentVolumeClaim> {
+        self.claims.get(key)
+    }
+
+    // Volume the claim is bound to
+    pub(crate) fn bound_volume(&self, claim: &PersistentVolumeClaim) -> Option<&PersistentVolume> {
+        let volume_name = claim.spec.as_ref()?.volume_name.as_deref()?;
+        self.volumes.get(volume_name)
+    }
+
+    pub(crate) fn storage_class(&self, claim: &PersistentVolumeClaim) -> Option<&StorageClass> {
+        let name = claim.spec.as_ref()?.storage_class_name.as_deref()?;
+        self.storage_classes.get(name)
+    }
+
+    // CSI driver attaching the volume of the claim along with a name identifying the volume,
+    // or none if the volume is not attached by a CSI driver
+    fn csi_volume(&self, key: &str) -> Option<(String, String)> {
+        let claim = self.claims.get(key)?;
+
+        match self.bound_volume(claim) {
+            Some(volume) => {
+                let driver = volume.spec.as_ref()?.csi.as_ref()?.driver.clone();
+                Some((driver, volume.metadata.name.clone()?))
+            }
+            // Claims to be provisioned are attached by the provisioner of their class
+            None => Some((
+                self.storage_class(claim)?.provisioner.clone(),
+                key.to_owned(),
+            )),
+        }
+    }
+
+    // CSI volumes the claims of the pod attach to its node, by driver
+    pub(crate) fn csi_volumes(&self, pod: &Pod) -> BTreeMap<String, BTreeSet<String>> {
+        let mut volumes: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for (driver, volume) in pod_claims(pod)
+            .iter()
+            .filter_map(|key| self.csi_volume(key))
+        {
+            volumes.entry(driver).or_default().insert(volume);
+        }
+
+        volumes
+    }
+
+    // Number of volumes each CSI driver reporting a limit in the CSINode of the node can
+    // attach to it
+    pub(crate) fn csi_volume_limits(&self, node_name: &str) -> BTreeMap<String, usize> {
+        let Some(csi_node) = self.csi_nodes.get(node_name) else { return BTreeMap::new() };
+
+        csi_node
+            .spec
+            .drivers
+            .iter()
+            .filter_map(|driver| {
+                let limit = driver.allocatable.as_ref()?.count?;
+                Some((driver.name.clone(), limit.max(0) as usize))
+            })
+            .collect()
+    }
+
+    // Claims of the pod with the ReadWriteOncePod access mode
+    pub(crate) fn exclusive_claims(&self, pod: &Pod) -> Vec<String> {
+        pod_claims(pod)
+            .into_iter()
+            .filter(|key| {
+                matches!(
+                    self.claim(key).and_then(|claim| claim.spec.as_ref()),
+                    Some(spec) if spec.access_modes.iter().flatten().any(|mode| mode == READ_WRITE_ONCE_POD)
+                )
+            })
+            .collect()
+    }
+}
+
+// Namespaced names of the claims of the volumes of a pod, including the claims created for
+// its generic ephemeral volumes
+pub(crate) fn pod_claims(pod: &Pod) -> Vec<String> {
+    let namespace = pod.metadata.namespace.as_deref().unwrap_or("default");
+    let pod_name = pod.metadata.name.as_deref().unwrap_or_default();
+
+    pod.spec
+        .as_ref()
+        .and_then(|spec| spec.volumes.as_ref())
+        .into_iter()
+        .flatten()
+        .filter_map(|volume| {
+            if let Some(claim) = &volume.persistent_volume_claim {
+                Some(format!("{namespace}/{}", claim.claim_name))
+            } else {
+                volume
+                    .ephemeral
+                    .as_ref()
+                    .map(|_| format!("{namespace}/{pod_name}-{}", volume.name))
+            }
+        })
+        .collect()
+}
+
+// Claims must exist and volumes already bound can only be used on the nodes matching their
+// node affinity, e.g. within the zone of the disk. Unbound claims are left to the binding
+// of volumes.
+pub(crate) fn is_pod_volume_topology_fulfilled(
+    node: &Node,
+    pod: &Pod,
+    volumes: &VolumeState,
+) -> bool {
+    pod_claims(pod).iter().all(|key| {
+        let Some(claim) = volumes.claim(key) else { return false };
+        if claim
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.volume_name.as_ref())
+            .is_none()
+        {
+            return true;
+        }
+        let Some(volume) = volumes.bound_volume(claim) else { return false };
+
+        match volume
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.node_affinity.as_ref())
+            .and_then(|affinity| affinity.required.as_ref())
+        {
+            Some(selector) => selector
+                .node_selector_terms
+                .iter()
+                .any(|term| node_matches_term(node, term)),
+            None => true,
+        }
+    })
+}
+
+// How an unbound claim of a pod gets its volume once the pod is placed on a node
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ClaimBinding {
+    // Bind the existing volume to the claim
+    Bind {
+        namespace: String,
+        claim: String,
+        uid: Option<String>,
+        volume: String,
+    },
+    // Provision a volume for the claim in the topology of the node
+    Provision {
+        namespace: String,
+        claim: String,
+    },
+}
+
+// Bindings for the unbound claims of a pod on the node, or none if a claim can neither be
+// bound to an available volume nor provisioned there. Claims of storage classes binding
+// immediately are left to the volume controller, so pods wait until they are bound, and
+// the reserved volumes are skipped as other pods were placed with them already.
+pub(crate) fn volume_bindings(
+    node: &Node,
+    pod: &Pod,
+    volumes: &VolumeState,
+    reserved: &BTreeSet<String>,
+) -> Option<Vec<ClaimBinding>> {
+    let mut taken = reserved.clone();
+    let mut bindings = vec![];
+
+    for key in pod_claims(pod) {
+        // Missing claims are rejected by the volume topology filter
+        let Some(claim) = volumes.claim(&key) else { continue };
+        let Some(spec) = &claim.spec else { continue };
+        if spec.volume_name.is_some() {
+            continue;
+        }
+
+        let class = volumes.storage_class(claim)?;
+        if class.volume_binding_mode.as_deref() != Some(WAIT_FOR_FIRST_CONSUMER) {
+            return None;
+        }
+
+        let namespace = claim.metadata.namespace.clone().unwrap_or_default();
+        let name = claim.metadata.name.clone().unwrap_or_default();
+
+        // Prefer the smallest available volume, like upstream
+        let volume = volumes
+            .volumes
+            .values()
+            .filter(|volume| matches!(&volume.metadata.name, Some(name) if !taken.contains(name)))
+            .filter(|volume| volume_matches_claim(volume, claim, node))
+            .min_by(|a, b| storage(capacity(a)).total_cmp(&storage(capacity(b))))
+            .and_then(|volume| volume.metadata.name.clone());
+
+        match volume {
+            Some(volume) => {
+                taken.insert(volume.clone());
+                bindings.push(ClaimBinding::Bind {
+                    namespace,
+                    claim: name,
+                    uid: claim.metadata.uid.clone(),
+                    volume,
+                });
+            }
+            None if is_provisioning_allowed(class, node) => {
+                bindings.push(ClaimBinding::Provision {
+                    namespace,
+                    claim: name,
+                });
+            }
+            None => return None,
+        }
+    }
+
+    Some(bindings)
+}
+
+// Choose the volumes for the unbound claims of the pods newly placed by a scheduler run, one
+// pod after another so that no volume is bound to two claims. Pods left without a volume
+// return to the unscheduled pods.
+pub(crate) fn assign_volumes(
+    target: TargetState,
+    nodes: &[Node],
+    volumes: &VolumeState,
+) -> (TargetState, BTreeMap<String, Vec<ClaimBinding>>) {
+    let mut reserved = BTreeSet::new();
+    let mut assigned = BTreeMap::new();
+    let mut conflicting = BTreeSet::new();
+
+    for (node_name, pod) in target.placements() {
+        let node = nodes
+            .iter()
+            .find(|node| node.metadata.name.as_deref() == Some(node_name));
+        let bindings = node.and_then(|node| volume_bindings(node, pod, volumes, &reserved));
+
+        match bindings {
+            Some(bindings) if bindings.is_empty() => {}
+            Some(bindings) => {
+                for binding in &bindings {
+                    if let ClaimBinding::Bind { volume, .. } = binding {
+                        reserved.insert(volume.clone());
+                    }
+                }
+                assigned.insert(pod_key(pod), bindings);
+            }
+            None => {
+                conflicting.insert(pod_key(pod));
+            }
+        }
+    }
+
+    let TargetState {
+        mut unscheduled_pods,
+        mut state,
+    } = target;
+    for node_pods in state.values_mut() {
+        let (left, kept): (Vec<Pod>, Vec<Pod>) = std::mem::take(node_pods)
+            .into_iter()
+            .partition(|pod| conflicting.contains(&pod_key(pod)));
+        unscheduled_pods.extend(left.into_iter().map(|pod| (pod, Reason::VolumeConflict)));
+        *node_pods = kept;
+    }
+
+    (
+        TargetState {
+            unscheduled_pods,
+            state,
+        },
+        assigned,
+    )
+}
+
+// Unbound claims must be satisfiable on the node by an available volume or by provisioning
+pub(crate) fn is_pod_volume_binding_fulfilled(
+    node: &Node,
+    pod: &Pod,
+    volumes: &VolumeState,
+) -> bool {
+    volume_bindings(node, pod, volumes, &BTreeSet::new()).is_some()
+}
+
+fn capacity(volume: &PersistentVolume) -> Option<&BTreeMap<String, Quantity>> {
+    volume.spec.as_ref()?.capacity.as_ref()
+}
+
+// Storage quantity in bytes, zero if missing
+fn storage(quantities: Option<&BTreeMap<String, Quantity>>) -> f64 {
+    quantities
+        .and_then(|quantities| quantities.get("storage"))
+        .and_then(|quantity| ParsedQuantity::try_from(quantity).ok())
+        .and_then(|quantity| quantity.to_bytes_f64())
+        .unwrap_or_default()
+}
+
+// Whether the available volume satisfies the claim and can be attached to the node
+fn volume_matches_claim(
+    volume: &PersistentVolume,
+    claim: &PersistentVolumeClaim,
+    node: &Node,
+) -> bool {
+    let (Some(volume_spec), Some(claim_spec)) = (&volume.spec, &claim.spec) else {
+        return false;
+    };
+
+    // Volumes reserved for another claim or no longer available are out
+    if let Some(claim_ref) = &volume_spec.claim_ref {
+        if claim_ref.name != claim.metadata.name || claim_ref.namespace != claim.metadata.namespace
+        {
+            return false;
+        }
+    }
+    if !matches!(
+        volume
+            .status
+            .as_ref()
+            .and_then(|status| status.phase.as_deref()),
+        None | Some("Available")
+    ) {
+        return false;
+    }
+
+    let class = claim_spec.storage_class_name.as_deref().unwrap_or_default();
+    if volume_spec
+        .storage_class_name
+        .as_deref()
+        .unwrap_or_default()
+        != class
+    {
+        return false;
+    }
+
+    let volume_mode = |mode: Option<&String>| mode.map_or("Filesystem", String::as_str).to_owned();
+    if volume_mode(volume_spec.volume_mode.as_ref()) != volume_mode(claim_spec.volume_mode.as_ref())
+    {
+        return false;
+    }
+
+    let access_modes = volume_spec.access_modes.as_deref().unwrap_or_default();
+    if !claim_spec
+        .access_modes
+        .iter()
+        .flatten()
+        .all(|mode| access_modes.contains(mode))
+    {
+        return false;
+    }
+
+    let requested = storage(
+        claim_spec
+            .resources
+            .as_ref()
+            .and_then(|resources| resources.requests.as_ref()),
+    );
+    if storage(volume_spec.capacity.as_ref()) < requested {
+        return false;
+    }
+
+    match volume_spec
+        .node_affinity
+        .as_ref()
+        .and_then(|affinity| affinity.required.as_ref())
+    {
+        Some(selector) => selector
+            .node_selector_terms
+            .iter()
+            .any(|term| node_matches_term(node, term)),
+        None => true,
+    }
+}
+
+// Whether the storage class may provision volumes in the topology of the node
+fn is_provisioning_allowed(class: &StorageClass, node: &Node) -> bool {
+    if class.provisioner == NO_PROVISIONER {
+        return false;
+    }
+
+    let terms = class.allowed_topologies.as_deref().unwrap_or_default();
+    if terms.is_empty() {
+        return true;
+    }
+
+    let empty = BTreeMap::new();
+    let labels = node.metadata.labels.as_ref().unwrap_or(&empty);
+
+    terms.iter().any(|term| {
+        term.match_label_expressions
+            .iter()
+            .flatten()
+            .all(|requirement| {
+                matches!(labels.get(&requirement.key), Some(value) if requirement.values.contains(value))
+            })
+    })
+}
+
+// The number of volumes each CSI driver attaches to the node must stay within the limit
+// the driver reports in the CSINode of the node
+pub(crate) fn is_pod_csi_volume_limit_fulfilled(
+    node: &Node,
+    pod: &Pod,
+    ctx: &FilterContext,
+) -> bool {
+    let volumes = ctx.volumes;
+
+    let new = volumes.csi_volumes(pod);
+    if new.is_empty() {
+        return true;
+    }
+
+    let Some(node_name) = node.metadata.name.as_ref() else { return false };
+
+    let mut attached: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for node_pod in ctx.state.get(node_name).into_iter().flatten() {
+        for (driver, node_pod_volumes) in volumes.csi_volumes(node_pod) {
+            attached.entry(driver).or_default().extend(node_pod_volumes);
+        }
+    }
+
+    volumes
+        .csi_volume_limits(node_name)
+        .iter()
+        .all(|(driver, limit)| {
+            let Some(new) = new.get(driver) else { return true };
+
+            let attached = attached.get(driver);
+            let count = attached.map(BTreeSet::len).unwrap_or_default()
+                + new
+                    .iter()
+                    .filter(
+                        |volume| !matches!(attached, Some(attached) if attached.contains(*volume)),
+                    )
+                    .count();
+
+            count <= *limit
+        })
+}
+
+// A ReadWriteOncePod claim can only be used by a single pod in the whole cluster
+pub(crate) fn is_pod_volume_access_fulfilled(pod: &Pod, ctx: &FilterContext) -> bool {
+    let exclusive = ctx.volumes.exclusive_claims(pod);
+    if exclusive.is_empty() {
+        return true;
+    }
+
+    let key = pod_key(pod);
+    !ctx.state
+        .values()
+        .flatten()
+        .filter(|other| pod_key(other) != key)
+        .any(|other| {
+            pod_claims(other)
+                .iter()
+                .any(|claim| exclusive.contains(claim))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::{
+        api::core::v1::{
+            ResourceRequirements, TopologySelectorLabelRequirement, TopologySelectorTerm,
+        },
+        apimachinery::pkg::apis::meta::v1::ObjectMeta,
+    };
+
+    use crate::scheduler::testing::{claim, csi_node, node, pod, volume, with_claims, CSI_DRIVER};
+
+    use crate::scheduler::capacity::HeldCapacity;
+
+    use super::*;
+
+    fn zoned(name: &str, zone: &str) -> Node {
+        let mut node = node(name, &[]);
+        node.metadata.labels = Some(BTreeMap::from_iter(vec![(
+            "zone".to_string(),
+            zone.to_string(),
+        )]));
+
+        node
+    }
+
+    #[test]
+    fn test_bound_volume_node_affinity() {
+        let volumes = VolumeState::new(
+            vec![
+                claim("data", "pv-data", "ReadWriteOnce"),
+                claim("anywhere", "pv-anywhere", "ReadWriteOnce"),
+            ],
+            vec![volume("pv-data", Some("eu-1")), volume("pv-anywhere", None)],
+            vec![],
+            vec![],
+        );
+
+        let incoming = with_claims(pod("incoming", &[]), &["data", "anywhere"]);
+        assert!(is_pod_volume_topology_fulfilled(
+            &zoned("a", "eu-1"),
+            &incoming,
+            &volumes
+        ));
+        assert!(!is_pod_volume_topology_fulfilled(
+            &zoned("b", "eu-2"),
+            &incoming,
+            &volumes
+        ));
+
+        // Claims missing from the cache cannot be attached anywhere
+        let missing = with_claims(pod("missing", &[]), &["unknown"]);
+        assert!(!is_pod_volume_topology_fulfilled(
+            &zoned("a", "eu-1"),
+            &missing,
+            &volumes
+        ));
+    }
+
+    #[test]
+    fn test_csi_volume_limit() {
+        let volumes = VolumeState::new(
+            vec![
+                claim("one", "pv-one", "ReadWriteOnce"),
+                claim("two", "pv-two", "ReadWriteOnce"),
+            ],
+            vec![volume("pv-one", None), volume("pv-two", None)],
+            vec![],
+            vec![csi_node("a", 1)],
+        );
+        let state = BTreeMap::from_iter(vec![(
+            "a".to_string(),
+            vec![with_claims(pod("existing", &[]), &["one"])],
+        )]);
+        let ctx = FilterContext {
+            state: &state,
+            volumes: &volumes,
+            capacity: &HeldCapacity::default(),
+        };
+
+        // The volume attached already does not count twice, another one exceeds the limit
+        let node = node("a", &[]);
+        assert!(is_pod_csi_volume_limit_fulfilled(
+            &node,
+            &with_claims(pod("p", &[]), &["one"]),
+            &ctx
+        ));
+        assert!(!is_pod_csi_volume_limit_fulfilled(
+            &node,
+            &with_claims(pod("p", &[]), &["two"]),
+            &ctx
+        ));
+    }
+
+    #[test]
+    fn test_read_write_once_pod_conflict() {
+        let volumes = VolumeState::new(
+            vec![claim("exclusive", "pv-exclusive", READ_WRITE_ONCE_POD)],
+            vec![volume("pv-exclusive", None)],
+            vec![],
+            vec![],
+        );
+        let mut state = BTreeMap::from_iter(vec![("a".to_string(), vec![])]);
+        let incoming = with_claims(pod("incoming", &[]), &["exclusive"]);
+
+        let ctx = FilterContext {
+            state: &state,
+            volumes: &volumes,
+            capacity: &HeldCapacity::default(),
+        };
+        assert!(is_pod_volume_access_fulfilled(&incoming, &ctx));
+
+        state.insert(
+            "b".to_string(),
+            vec![with_claims(pod("owner", &[]), &["exclusive"])],
+        );
+        let ctx = FilterContext {
+            state: &state,
+            volumes: &volumes,
+            capacity: &HeldCapacity::default(),
+        };
+        assert!(!is_pod_volume_access_fulfilled(&incoming, &ctx));
+    }
+
+    fn unbound_claim(name: &str, class: &str, size: &str) -> PersistentVolumeClaim {
+        let mut claim = claim(name, "", "ReadWriteOnce");
+        if let Some(spec) = claim.spec.as_mut() {
+            spec.volume_name = None;
+            spec.storage_class_name = Some(class.to_owned());
+            spec.resources = Some(ResourceRequirements {
+                requests: Some(BTreeMap::from_iter(vec![(
+                    "storage".to_string(),
+                    Quantity(size.to_string()),
+                )])),
+                ..Default::default()
+            });
+        }
+
+        claim
+    }
+
+    fn local_volume(name: &str, zone: &str, size: &str) -> PersistentVolume {
+        let mut volume = volume(name, Some(zone));
+        if let Some(spec) = volume.spec.as_mut() {
+            spec.csi = None;
+            spec.storage_class_name = Some("local".to_owned());
+            spec.access_modes = Some(vec!["ReadWriteOnce".to_owned()]);
+            spec.capacity = Some(BTreeMap::from_iter(vec![(
+                "storage".to_string(),
+                Quantity(size.to_string()),
+            )]));
+        }
+
+        volume
+    }
+
+    fn storage_class(name: &str, provisioner: &str, mode: &str, zones: &[&str]) -> StorageClass {
+        StorageClass {
+            metadata: ObjectMeta {
+                name: Some(name.to_owned()),
+                ..Default::default()
+            },
+            provisioner: provisioner.to_owned(),
+            volume_binding_mode: Some(mode.to_owned()),
+            allowed_topologies: Some(vec![TopologySelectorTerm {
+                match_label_expressions: Some(vec![TopologySelectorLabelRequirement {
+                    key: "zone".to_owned(),
+                    values: zones.iter().map(|zone| zone.to_string()).collect(),
+                }]),
+            }]),
+            ..Default::default()
+        }
+    }
+
+    fn classes() -> Vec<StorageClass> {
+        vec![
+            storage_class("local", NO_PROVISIONER, WAIT_FOR_FIRST_CONSUMER, &[]),
+            storage_class("ssd", CSI_DRIVER, WAIT_FOR_FIRST_CONSUMER, &["eu-2"]),
+            storage_class("standard", CSI_DRIVER, "Immediate", &[]),
+        ]
+    }
+
+    #[test]
+    fn test_wait_for_first_consumer_bindings() {
+        let volumes = VolumeState::new(
+            vec![
+                unbound_claim("local", "local", "5Gi"),
+                unbound_claim("ssd", "ssd", "5Gi"),
+                unbound_claim("standard", "standard", "5Gi"),
+            ],
+            vec![
+                local_volume("pv-small", "eu-1", "1Gi"),
+                local_volume("pv-large", "eu-1", "100Gi"),
+                local_volume("pv-fitting", "eu-1", "10Gi"),
+            ],
+            classes(),
+            vec![],
+        );
+        let none = BTreeSet::new();
+
+        // The smallest local volume fitting the claim is chosen on its node only
+        let local = with_claims(pod("local", &[]), &["local"]);
+        assert_eq!(
+            volume_bindings(&zoned("a", "eu-1"), &local, &volumes, &none),
+            Some(vec![ClaimBinding::Bind {
+                namespace: "default".to_owned(),
+                claim: "local".to_owned(),
+                uid: None,
+                volume: "pv-fitting".to_owned(),
+            }])
+        );
+        assert_eq!(
+            volume_bindings(&zoned("b", "eu-2"), &local, &volumes, &none),
+            None
+        );
+
+        // Provisioning is limited to the allowed topologies of the class
+        let ssd = with_claims(pod("ssd", &[]), &["ssd"]);
+        assert_eq!(
+            volume_bindings(&zoned("b", "eu-2"), &ssd, &volumes, &none),
+            Some(vec![ClaimBinding::Provision {
+                namespace: "default".to_owned(),
+                claim: "ssd".to_owned(),
+            }])
+        );
+        assert!(!is_pod_volume_binding_fulfilled(
+            &zoned("a", "eu-1"),
+            &ssd,
+            &volumes
+        ));
+
+        // Claims binding immediately wait for the volume controller
+        let standard = with_claims(pod("standard", &[]), &["standard"]);
+        assert!(!is_pod_volume_binding_fulfilled(
+            &zoned("a", "eu-1"),
+            &standard,
+            &volumes
+        ));
+    }
+
+    #[test]
+    fn test_assign_volumes_once() {
+        let volumes = VolumeState::new(
+            vec![
+                unbound_claim("first", "local", "1Gi"),
+                unbound_claim("second", "local", "1Gi"),
+            ],
+            vec![local_volume("pv", "eu-1", "1Gi")],
+            classes(),
+            vec![],
+        );
+        let nodes = [zoned("a", "eu-1")];
+        let target = TargetState {
+            unscheduled_pods: vec![],
+            state: BTreeMap::from_iter(vec![(
+                "a".to_string(),
+                vec![
+                    with_claims(pod("first", &[]), &["first"]),
+                    with_claims(pod("second", &[]), &["second"]),
+                ],
+            )]),
+        };
+
+        let (target, bindings) = assign_volumes(target, &nodes, &volumes);
+
+        assert_eq!(bindings.len(), 1);
+        assert!(bindings.contains_key("default/first"));
+        assert_eq!(target.state["a"].len(), 1);
+        assert!(matches!(
+            target.unscheduled_pods.as_slice(),
+            [(pod, Reason::VolumeConflict)] if pod.metadata.name.as_deref() == Some("second")
+        ));
+    }
+}
@@ -0,0 +1,370 @@
+// WebAssembly plugins: filters and scores implemented by modules loaded at runtime, so
+// custom placement logic does not require rebuilding the scheduler. A module exports
+// - `memory` and `alloc(len: i32) -> i32`, returning a buffer the input is written to,
+// - `filter(ptr: i32, len: i32) -> i32`, non-zero if the node is feasible for the pod, or
+// - `score(ptr: i32, len: i32) -> i64`, the score of the node between 0 and 100.
+// The input is the JSON object {"pod": ..., "node": ..., "nodePods": [...]}, where scores
+// additionally get the pods on the node.
+// Every call runs in a fresh instance with limited fuel and a deadline, so a plugin that
+// loops or hogs the CPU fails the call rather than stalling the scheduling loop. Scheduling
+// runs execute on the blocking thread pool, so the calls never hold up the async runtime.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock, Weak},
+    time::Duration,
+};
+
+use color_eyre::Result;
+use k8s_openapi::api::core::v1::{Node, Pod};
+use serde::Serialize;
+use wasmtime::{Config, Engine, ExternType, Instance, Module, Store, WasmResults};
+
+use crate::{
+    config::WasmPluginArgs,
+    scheduler::{
+        filters::FilterPlugin,
+        scores::{ScoreContext, ScorePlugin, MAX_NODE_SCORE},
+    },
+};
+
+pub(crate) const FILTER_EXPORT: &str = "filter";
+pub(crate) const SCORE_EXPORT: &str = "score";
+
+// Interval in which the epoch of the engine advances, the granularity of call deadlines
+const EPOCH_TICK: Duration = Duration::from_millis(5);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PluginInput<'a> {
+    pod: &'a Pod,
+    node: &'a Node,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    node_pods: Option<&'a [Pod]>,
+}
+
+// Keeps the epoch of the engine advancing while held, plugins hold it for as long as they
+// may be called
+struct EpochTicker;
+
+pub(crate) struct WasmPlugin {
+    args: WasmPluginArgs,
+    module: Module,
+    _ticker: Arc<EpochTicker>,
+}
+
+impl WasmPlugin {
+    pub(crate) fn load(args: &WasmPluginArgs) -> Result<Self> {
+        let module = load_module(&args.path)?;
+
+        Ok(Self {
+            args: args.clone(),
+            _ticker: start_ticker(module.engine())?,
+            module,
+        })
+    }
+
+    // Run an export of the module on the input in a fresh instance within the limits of the
+    // plugin
+    fn call<R: WasmResults>(&self, export: &str, input: &PluginInput) -> wasmtime::Result<R> {
+        let mut store = Store::new(self.module.engine(), ());
+        store.add_fuel(self.args.fuel)?;
+        store.set_epoch_deadline(self.args.timeout_ms / EPOCH_TICK.as_millis() as u64 + 1);
+
+        let instance = Instance::new(&mut store, &self.module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("module does not export its memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let function = instance.get_typed_func::<(i32, i32), R>(&mut store, export)?;
+
+        let input = serde_json::to_vec(input)?;
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, usize::try_from(ptr)?, &input)?;
+
+        function.call(&mut store, (ptr, len))
+    }
+}
+
+impl FilterPlugin for WasmPlugin {
+    // A failing call rejects the node, as the plugin could not vouch for it
+    fn filter(&self, pod: &Pod, node: &Node) -> bool {
+        let input = PluginInput {
+            pod,
+            node,
+            node_pods: None,
+        };
+
+        match self.call::<i32>(FILTER_EXPORT, &input) {
+            Ok(feasible) => feasible != 0,
+            Err(err) => {
+                log::warn!(
+                    "WebAssembly filter {} failed: {err:#}",
+                    self.args.path.display()
+                );
+                false
+            }
+        }
+    }
+}
+
+impl ScorePlugin for WasmPlugin {
+    fn score(&self, ctx: &ScoreContext, pod: &Pod, node: &Node) -> i64 {
+        let input = PluginInput {
+            pod,
+            node,
+            node_pods: Some(ctx.node_pods(node)),
+        };
+
+        match self.call::<i64>(SCORE_EXPORT, &input) {
+            Ok(score) => score.clamp(0, MAX_NODE_SCORE),
+            Err(err) => {
+                log::warn!(
+                    "WebAssembly score {} failed: {err:#}",
+                    self.args.path.display()
+                );
+                0
+            }
+        }
+    }
+}
+
+// Stand-in for a filter plugin that failed to load and may not be ignored, so pods are not
+// placed without the plugin vouching for the node
+pub(crate) struct RejectAll;
+
+impl FilterPlugin for RejectAll {
+    fn filter(&self, _pod: &Pod, _node: &Node) -> bool {
+        false
+    }
+}
+
+// Check that the module compiles and exports the interface of the given plugin kind
+pub(crate) fn check_module(path: &Path, export: &str) -> Result<()> {
+    let module = load_module(path)?;
+
+    for (name, is_expected) in [
+        (
+            "memory",
+            matches!(module.get_export("memory"), Some(ExternType::Memory(_))),
+        ),
+        (
+            "alloc",
+            matches!(module.get_export("alloc"), Some(ExternType::Func(_))),
+        ),
+        (
+            export,
+            matches!(module.get_export(export), Some(ExternType::Func(_))),
+        ),
+    ] {
+        if !is_expected {
+            color_eyre::eyre::bail!(
+                "WebAssembly plugin {} does not export {name}",
+                path.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Shared engine metering fuel and interrupting calls past their deadline
+fn engine() -> wasmtime::Result<&'static Engine> {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+
+    if let Some(engine) = ENGINE.get() {
+        return Ok(engine);
+    }
+
+    let mut config = Config::new();
+    config.consume_fuel(true).epoch_interruption(true);
+    let engine = Engine::new(&config)?;
+
+    Ok(ENGINE.get_or_init(|| engine))
+}
+
+// Advance the epoch of the engine in a background thread, which stops once no plugin holds
+// the returned ticker anymore
+fn start_ticker(engine: &Engine) -> Result<Arc<EpochTicker>> {
+    static TICKER: OnceLock<Mutex<Weak<EpochTicker>>> = OnceLock::new();
+
+    let mut current = TICKER
+        .get_or_init(Default::default)
+        .lock()
+        .map_err(|err| color_eyre::eyre::eyre!("Failed to lock WebAssembly ticker: {err}"))?;
+    if let Some(ticker) = current.upgrade() {
+        return Ok(ticker);
+    }
+
+    let ticker = Arc::new(EpochTicker);
+    *current = Arc::downgrade(&ticker);
+
+    let held = Arc::downgrade(&ticker);
+    let engine = engine.clone();
+    std::thread::spawn(move || {
+        while held.strong_count() > 0 {
+            std::thread::sleep(EPOCH_TICK);
+            engine.increment_epoch();
+        }
+    });
+
+    Ok(ticker)
+}
+
+// Compile a module once and reuse it for every following plugin instantiation
+fn load_module(path: &Path) -> Result<Module> {
+    static MODULES: OnceLock<Mutex<BTreeMap<PathBuf, Module>>> = OnceLock::new();
+
+    let mut modules = MODULES
+        .get_or_init(Default::default)
+        .lock()
+        .map_err(|err| color_eyre::eyre::eyre!("Failed to lock WebAssembly modules: {err}"))?;
+    if let Some(module) = modules.get(path) {
+        return Ok(module.clone());
+    }
+
+    let module = engine()
+        .and_then(|engine| Module::from_file(engine, path))
+        .map_err(|err| {
+            color_eyre::eyre::eyre!(
+                "Failed to load WebAssembly plugin {}: {err:#}",
+                path.display()
+            )
+        })?;
+    modules.insert(path.to_owned(), module.clone());
+
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::{
+        config::{FilterPlugin as FilterPluginConfig, Profile},
+        scheduler::{
+            filters,
+            load::NodeLoad,
+            testing::{node, pod},
+        },
+    };
+
+    use super::*;
+
+    // Feasible and scored 42 if the input contains a "~", which only the name of the
+    // preferred node does
+    const PLUGIN: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) i32.const 0)
+  (func $contains_marker (param $ptr i32) (param $len i32) (result i32)
+    (local $i i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (if (i32.eq
+              (i32.load8_u (i32.add (local.get $ptr) (local.get $i)))
+              (i32.const 126))
+          (then (return (i32.const 1))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (i32.const 0))
+  (func (export "filter") (param i32 i32) (result i32)
+    (call $contains_marker (local.get 0) (local.get 1)))
+  (func (export "score") (param i32 i32) (result i64)
+    (if (result i64) (call $contains_marker (local.get 0) (local.get 1))
+      (then (i64.const 42))
+      (else (i64.const 0)))))
+"#;
+
+    const SPINNING_PLUGIN: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) i32.const 0)
+  (func (export "filter") (param i32 i32) (result i32)
+    (loop $forever (br $forever))
+    (i32.const 1)))
+"#;
+
+    fn plugin(name: &str, source: &str, fuel: u64, timeout_ms: u64) -> WasmPlugin {
+        let path = std::env::temp_dir().join(format!(
+            "kube-scheduler-rs-{name}-{}.wat",
+            std::process::id()
+        ));
+        std::fs::write(&path, source).unwrap();
+
+        WasmPlugin::load(&WasmPluginArgs {
+            path,
+            fuel,
+            timeout_ms,
+            ignorable: false,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_filter_and_score() {
+        let plugin = plugin("plugin", PLUGIN, 10_000_000, 1000);
+        let state = BTreeMap::new();
+        let ctx = ScoreContext {
+            nodes: &[],
+            state: &state,
+        };
+        let pod = pod("p", &[]);
+        let a = node("a", &[]);
+        let preferred = node("preferred~", &[]);
+
+        assert!(plugin.filter(&pod, &preferred));
+        assert!(!plugin.filter(&pod, &a));
+        assert_eq!(plugin.score(&ctx, &pod, &preferred), 42);
+        assert_eq!(plugin.score(&ctx, &pod, &a), 0);
+    }
+
+    #[test]
+    fn test_exhausted_fuel_rejects_node() {
+        let plugin = plugin("spinning-fuel", SPINNING_PLUGIN, 10_000, 60_000);
+
+        assert!(!plugin.filter(&pod("p", &[]), &node("a", &[])));
+    }
+
+    #[test]
+    fn test_deadline_rejects_node() {
+        let plugin = plugin("spinning-deadline", SPINNING_PLUGIN, u64::MAX, 20);
+
+        assert!(!plugin.filter(&pod("p", &[]), &node("a", &[])));
+    }
+
+    #[test]
+    fn test_unloadable_filter_rejects_nodes_unless_ignorable() {
+        let load = NodeLoad::default();
+        for ignorable in [false, true] {
+            let profile = Profile {
+                filter_plugins: vec![FilterPluginConfig::Wasm {
+                    args: WasmPluginArgs {
+                        path: PathBuf::from("/nonexistent/plugin.wasm"),
+                        fuel: 1,
+                        timeout_ms: 1,
+                        ignorable,
+                    },
+                }],
+                ..Profile::default()
+            };
+
+            let plugins = filters::plugins(&profile, &load);
+            let feasible = plugins
+                .iter()
+                .all(|plugin| plugin.filter(&pod("p", &[]), &node("a", &[])));
+            assert_eq!(feasible, ignorable);
+        }
+    }
+
+    #[test]
+    fn test_check_module_exports() {
+        let plugin = plugin("spinning-check", SPINNING_PLUGIN, 1, 1);
+
+        assert!(check_module(&plugin.args.path, FILTER_EXPORT).is_ok());
+        assert!(check_module(&plugin.args.path, SCORE_EXPORT).is_err());
+    }
+}